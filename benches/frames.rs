@@ -0,0 +1,94 @@
+// Times whole frames at increasing clock rates, for spotting changes in the
+// speed of the core. Run with
+//
+//     cargo bench --bench frames
+//
+// Each workload is a small loop that runs forever, so any number of cycles
+// per frame can be timed. Set RUST8_BENCH_ROM to the path of a game to time
+// that as well.
+
+extern crate rust8;
+
+use std::env;
+use std::fs;
+use std::time::Instant;
+
+use rust8::bus::Ram;
+use rust8::engine::Engine;
+use rust8::Chip8;
+
+// Draws the 0 glyph across the screen, moving it along every time
+const SPRITES: [u8; 14] = [
+    0x60, 0x00, // LD V0, 0
+    0x61, 0x00, // LD V1, 0
+    0xA0, 0x00, // LD I, 0x000
+    0xD0, 0x15, // DRW V0, V1, 5
+    0x70, 0x03, // ADD V0, 3
+    0x71, 0x01, // ADD V1, 1
+    0x12, 0x06, // JP 0x206
+];
+
+// Register arithmetic and skips, with no drawing
+const ALU: [u8; 14] = [
+    0x60, 0x01, // LD V0, 1
+    0x81, 0x04, // ADD V1, V0
+    0x82, 0x15, // SUB V2, V1
+    0x83, 0x26, // SHR V3, V2
+    0x34, 0x00, // SE V4, 0
+    0x74, 0x01, // ADD V4, 1
+    0x12, 0x02, // JP 0x202
+];
+
+// Converts a counter to decimal and stores and loads registers, the way
+// games draw their scores
+const BCD: [u8; 12] = [
+    0xA3, 0x00, // LD I, 0x300
+    0xF0, 0x33, // LD B, V0
+    0xF2, 0x65, // LD V2, [I]
+    0xF2, 0x55, // LD [I], V2
+    0x70, 0x01, // ADD V0, 1
+    0x12, 0x00, // JP 0x200
+];
+
+const FRAMES: u32 = 600;
+
+fn bench(name: &str, rom: &[u8], engine: Engine, cycles: u32) {
+    let mut chip8 = Chip8::with_seed(Ram::new(), 0);
+    chip8.load_rom(rom).unwrap();
+    chip8.set_engine(engine);
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        if let Err(fault) = chip8.run_frame(cycles) {
+            println!("{:<8} {:<11} {}", name, engine.to_string(), fault);
+            return;
+        }
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "{:<8} {:<11} {:>6} cycles/frame {:>10.0} frames/s {:>8.1} MIPS",
+        name,
+        engine.to_string(),
+        cycles,
+        FRAMES as f64 / seconds,
+        chip8.cycles() as f64 / seconds / 1e6
+    );
+}
+
+fn main() {
+    let mut workloads = vec![
+        ("sprites".to_string(), SPRITES.to_vec()),
+        ("alu".to_string(), ALU.to_vec()),
+        ("bcd".to_string(), BCD.to_vec()),
+    ];
+    if let Some(path) = env::var_os("RUST8_BENCH_ROM") {
+        let rom = fs::read(&path).unwrap();
+        workloads.push(("rom".to_string(), rom));
+    }
+    for (name, rom) in workloads.iter() {
+        for &engine in [Engine::Interpreter, Engine::Cached].iter() {
+            for &cycles in [10, 100, 1000, 10_000].iter() {
+                bench(name, rom, engine, cycles);
+            }
+        }
+    }
+}
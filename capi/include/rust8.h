@@ -0,0 +1,124 @@
+// rust8.h: C interface to the rust8 chip-8 emulator core
+//
+// Generated from capi/src/lib.rs by
+//     RUST8_BLESS=1 cargo test -p rust8-capi --test header
+// Do not edit by hand.
+//
+// Functions that can fail return RUST8_OK (0) or a negative RUST8_ERROR_
+// code; rust8_error_message describes a code. A machine is not thread safe,
+// but separate machines can be used from separate threads.
+
+#ifndef RUST8_H
+#define RUST8_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// Screen size in pixels
+#define RUST8_WIDTH 64
+#define RUST8_HEIGHT 32
+
+// Bytes of memory, including the interpreter area below 0x200
+#define RUST8_MEMORY_SIZE 4096
+
+#define RUST8_OK 0
+
+// A pointer that must not be null was
+#define RUST8_ERROR_NULL (-1)
+
+// A key number over 0xF, unknown quirks or similar
+#define RUST8_ERROR_INVALID_ARGUMENT (-2)
+#define RUST8_ERROR_ROM_TOO_BIG (-3)
+
+// An output buffer is smaller than the data it should hold
+#define RUST8_ERROR_BUFFER_TOO_SMALL (-4)
+
+// Not a save state, or one from a different ROM
+#define RUST8_ERROR_BAD_STATE (-5)
+
+// Faults raised by the game. The machine stops on the instruction at fault.
+#define RUST8_ERROR_STACK_OVERFLOW (-6)
+#define RUST8_ERROR_STACK_UNDERFLOW (-7)
+#define RUST8_ERROR_MEMORY_OUT_OF_RANGE (-8)
+#define RUST8_ERROR_PC_OUT_OF_RANGE (-9)
+#define RUST8_ERROR_UNKNOWN_OPCODE (-10)
+
+// A bug in rust8. The machine should not be used again.
+#define RUST8_ERROR_PANIC (-11)
+
+// A machine, created by rust8_new and freed by rust8_free
+typedef struct Rust8 Rust8;
+
+// The CPU state, filled in by rust8_registers
+typedef struct Rust8Registers {
+    // V0 to VF
+    uint8_t v[16];
+    uint16_t i;
+    uint16_t pc;
+    uint8_t sp;
+    uint8_t delay_timer;
+    uint8_t sound_timer;
+    uint16_t stack[16];
+} Rust8Registers;
+
+// The version of rust8, e.g. "0.1.0"
+const char *rust8_version(void);
+
+// A description of an error code, e.g. "Stack overflow"
+const char *rust8_error_message(int32_t code);
+
+// A new machine with nothing loaded. Returns null if it couldn't be made.
+Rust8 *rust8_new(void);
+
+// Frees a machine. Null is ignored.
+void rust8_free(Rust8 *machine);
+
+// Loads a ROM at 0x200
+int32_t rust8_load_rom(Rust8 *machine, const uint8_t *data, size_t size);
+
+// Sets the quirks from a preset and changes, e.g. "vip,-display-wait"
+int32_t rust8_set_quirks(Rust8 *machine, const char *quirks);
+
+// Seeds the random number generator, so runs can be repeated
+int32_t rust8_seed(Rust8 *machine, uint64_t seed);
+
+// Runs up to cycles instructions without touching the timers, stopping
+// early if the game faults
+int32_t rust8_step(Rust8 *machine, uint32_t cycles);
+
+// Runs one 60 Hz frame of cycles instructions, then counts the timers down
+int32_t rust8_run_frame(Rust8 *machine, uint32_t cycles);
+
+// Presses or releases hex key 0 to 0xF
+int32_t rust8_set_key(Rust8 *machine, uint8_t key, bool pressed);
+
+// Copies the screen into out, a byte per pixel row by row, 1 for lit and 0
+// for not. out must hold RUST8_WIDTH * RUST8_HEIGHT bytes.
+int32_t rust8_framebuffer(Rust8 *machine, uint8_t *out, size_t size);
+
+// Copies the CPU state into out
+int32_t rust8_registers(Rust8 *machine, Rust8Registers *out);
+
+// Copies size bytes of memory starting at address into out
+int32_t rust8_read_memory(Rust8 *machine, uint16_t address, uint8_t *out, size_t size);
+
+// Bytes needed to hold a save state
+size_t rust8_state_size(void);
+
+// Saves the machine into out, which must hold rust8_state_size() bytes
+int32_t rust8_save_state(Rust8 *machine, uint8_t *out, size_t size);
+
+// Restores a state saved by rust8_save_state with the same ROM loaded.
+// The machine is unchanged if the state is rejected.
+int32_t rust8_load_state(Rust8 *machine, const uint8_t *data, size_t size);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
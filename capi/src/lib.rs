@@ -0,0 +1,349 @@
+// A C interface to the emulator core, for embedding it in other programs.
+//
+// The header, include/rust8.h, is generated from this file by the header
+// test, so each item here is written the way the generator expects:
+// constants as plain literals, comments directly above the item they
+// describe, and only the types it knows how to turn into C. After changing
+// the interface, regenerate the header with
+//
+//     RUST8_BLESS=1 cargo test -p rust8-capi --test header
+//
+// Every function returns RUST8_OK or a negative error code rather than
+// panicking, and a panic inside the core is caught and reported as
+// RUST8_ERROR_PANIC.
+
+// Every entry point takes its pointers on trust from the caller
+#![allow(clippy::missing_safety_doc)]
+
+extern crate rust8;
+
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use rust8::bus::Bus;
+use rust8::fault::Fault;
+use rust8::state::STATE_SIZE;
+use rust8::Chip8;
+
+// Screen size in pixels
+pub const RUST8_WIDTH: usize = 64;
+pub const RUST8_HEIGHT: usize = 32;
+// Bytes of memory, including the interpreter area below 0x200
+pub const RUST8_MEMORY_SIZE: usize = 4096;
+
+pub const RUST8_OK: i32 = 0;
+// A pointer that must not be null was
+pub const RUST8_ERROR_NULL: i32 = -1;
+// A key number over 0xF, unknown quirks or similar
+pub const RUST8_ERROR_INVALID_ARGUMENT: i32 = -2;
+pub const RUST8_ERROR_ROM_TOO_BIG: i32 = -3;
+// An output buffer is smaller than the data it should hold
+pub const RUST8_ERROR_BUFFER_TOO_SMALL: i32 = -4;
+// Not a save state, or one from a different ROM
+pub const RUST8_ERROR_BAD_STATE: i32 = -5;
+// Faults raised by the game. The machine stops on the instruction at fault.
+pub const RUST8_ERROR_STACK_OVERFLOW: i32 = -6;
+pub const RUST8_ERROR_STACK_UNDERFLOW: i32 = -7;
+pub const RUST8_ERROR_MEMORY_OUT_OF_RANGE: i32 = -8;
+pub const RUST8_ERROR_PC_OUT_OF_RANGE: i32 = -9;
+pub const RUST8_ERROR_UNKNOWN_OPCODE: i32 = -10;
+// A bug in rust8. The machine should not be used again.
+pub const RUST8_ERROR_PANIC: i32 = -11;
+
+// A machine, created by rust8_new and freed by rust8_free
+pub struct Rust8 {
+    chip8: Chip8,
+}
+
+// The CPU state, filled in by rust8_registers
+#[repr(C)]
+pub struct Rust8Registers {
+    // V0 to VF
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack: [u16; 16],
+}
+
+fn fault_code(fault: Fault) -> i32 {
+    match fault {
+        Fault::StackOverflow { .. } => RUST8_ERROR_STACK_OVERFLOW,
+        Fault::StackUnderflow { .. } => RUST8_ERROR_STACK_UNDERFLOW,
+        Fault::MemoryOutOfRange { .. } => RUST8_ERROR_MEMORY_OUT_OF_RANGE,
+        Fault::PcOutOfRange { .. } => RUST8_ERROR_PC_OUT_OF_RANGE,
+        Fault::UnknownOpcode { .. } => RUST8_ERROR_UNKNOWN_OPCODE,
+    }
+}
+
+// Runs f on the machine, turning a null pointer or a panic into an error
+// code
+unsafe fn with_machine<F>(machine: *mut Rust8, f: F) -> i32
+where
+    F: FnOnce(&mut Chip8) -> i32,
+{
+    if machine.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    let chip8 = &mut (*machine).chip8;
+    panic::catch_unwind(AssertUnwindSafe(|| f(chip8))).unwrap_or(RUST8_ERROR_PANIC)
+}
+
+// The version of rust8, e.g. "0.1.0"
+#[no_mangle]
+pub extern "C" fn rust8_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+// A description of an error code, e.g. "Stack overflow"
+#[no_mangle]
+pub extern "C" fn rust8_error_message(code: i32) -> *const c_char {
+    let message: &[u8] = match code {
+        RUST8_OK => b"No error\0",
+        RUST8_ERROR_NULL => b"Null pointer\0",
+        RUST8_ERROR_INVALID_ARGUMENT => b"Invalid argument\0",
+        RUST8_ERROR_ROM_TOO_BIG => b"ROM is too big to fit in memory\0",
+        RUST8_ERROR_BUFFER_TOO_SMALL => b"Buffer is too small\0",
+        RUST8_ERROR_BAD_STATE => b"Not a save state for this ROM\0",
+        RUST8_ERROR_STACK_OVERFLOW => b"Stack overflow\0",
+        RUST8_ERROR_STACK_UNDERFLOW => b"Stack underflow\0",
+        RUST8_ERROR_MEMORY_OUT_OF_RANGE => b"Memory access out of range\0",
+        RUST8_ERROR_PC_OUT_OF_RANGE => b"PC out of range\0",
+        RUST8_ERROR_UNKNOWN_OPCODE => b"Unknown opcode\0",
+        RUST8_ERROR_PANIC => b"Internal error\0",
+        _ => b"Unknown error code\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+// A new machine with nothing loaded. Returns null if it couldn't be made.
+#[no_mangle]
+pub extern "C" fn rust8_new() -> *mut Rust8 {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(Rust8 {
+            chip8: Chip8::new(),
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+// Frees a machine. Null is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn rust8_free(machine: *mut Rust8) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+// Loads a ROM at 0x200
+#[no_mangle]
+pub unsafe extern "C" fn rust8_load_rom(machine: *mut Rust8, data: *const u8, size: usize) -> i32 {
+    if data.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    let rom = slice::from_raw_parts(data, size);
+    with_machine(machine, |chip8| match chip8.load_rom(rom) {
+        Ok(()) => RUST8_OK,
+        Err(_) => RUST8_ERROR_ROM_TOO_BIG,
+    })
+}
+
+// Sets the quirks from a preset and changes, e.g. "vip,-display-wait"
+#[no_mangle]
+pub unsafe extern "C" fn rust8_set_quirks(machine: *mut Rust8, quirks: *const c_char) -> i32 {
+    if quirks.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    let quirks = CStr::from_ptr(quirks).to_string_lossy().parse();
+    with_machine(machine, |chip8| match quirks {
+        Ok(quirks) => {
+            chip8.quirks = quirks;
+            RUST8_OK
+        }
+        Err(_) => RUST8_ERROR_INVALID_ARGUMENT,
+    })
+}
+
+// Seeds the random number generator, so runs can be repeated
+#[no_mangle]
+pub unsafe extern "C" fn rust8_seed(machine: *mut Rust8, seed: u64) -> i32 {
+    with_machine(machine, |chip8| {
+        chip8.seed(seed);
+        RUST8_OK
+    })
+}
+
+// Runs up to cycles instructions without touching the timers, stopping
+// early if the game faults
+#[no_mangle]
+pub unsafe extern "C" fn rust8_step(machine: *mut Rust8, cycles: u32) -> i32 {
+    with_machine(machine, |chip8| {
+        for _ in 0..cycles {
+            if let Err(fault) = chip8.cycle() {
+                return fault_code(fault);
+            }
+        }
+        RUST8_OK
+    })
+}
+
+// Runs one 60 Hz frame of cycles instructions, then counts the timers down
+#[no_mangle]
+pub unsafe extern "C" fn rust8_run_frame(machine: *mut Rust8, cycles: u32) -> i32 {
+    with_machine(machine, |chip8| match chip8.run_frame(cycles) {
+        Ok(()) => RUST8_OK,
+        Err(fault) => fault_code(fault),
+    })
+}
+
+// Presses or releases hex key 0 to 0xF
+#[no_mangle]
+pub unsafe extern "C" fn rust8_set_key(machine: *mut Rust8, key: u8, pressed: bool) -> i32 {
+    with_machine(machine, |chip8| match chip8.keys.get_mut(key as usize) {
+        Some(state) => {
+            *state = pressed;
+            RUST8_OK
+        }
+        None => RUST8_ERROR_INVALID_ARGUMENT,
+    })
+}
+
+// Copies the screen into out, a byte per pixel row by row, 1 for lit and 0
+// for not. out must hold RUST8_WIDTH * RUST8_HEIGHT bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rust8_framebuffer(machine: *mut Rust8, out: *mut u8, size: usize) -> i32 {
+    if out.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    if size < RUST8_WIDTH * RUST8_HEIGHT {
+        return RUST8_ERROR_BUFFER_TOO_SMALL;
+    }
+    let out = slice::from_raw_parts_mut(out, RUST8_WIDTH * RUST8_HEIGHT);
+    with_machine(machine, |chip8| {
+        for (byte, lit) in out.iter_mut().zip(chip8.screen.pixels()) {
+            *byte = lit as u8;
+        }
+        RUST8_OK
+    })
+}
+
+// Copies the CPU state into out
+#[no_mangle]
+pub unsafe extern "C" fn rust8_registers(machine: *mut Rust8, out: *mut Rust8Registers) -> i32 {
+    if out.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    with_machine(machine, |chip8| {
+        *out = Rust8Registers {
+            v: *chip8.registers(),
+            i: chip8.index(),
+            pc: chip8.pc(),
+            sp: chip8.sp(),
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer,
+            stack: *chip8.stack(),
+        };
+        RUST8_OK
+    })
+}
+
+// Copies size bytes of memory starting at address into out
+#[no_mangle]
+pub unsafe extern "C" fn rust8_read_memory(
+    machine: *mut Rust8,
+    address: u16,
+    out: *mut u8,
+    size: usize,
+) -> i32 {
+    if out.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    let start = address as usize;
+    if start + size > RUST8_MEMORY_SIZE {
+        return RUST8_ERROR_INVALID_ARGUMENT;
+    }
+    let out = slice::from_raw_parts_mut(out, size);
+    with_machine(machine, |chip8| {
+        out.copy_from_slice(&chip8.bus().memory()[start..start + size]);
+        RUST8_OK
+    })
+}
+
+// Bytes needed to hold a save state
+#[no_mangle]
+pub extern "C" fn rust8_state_size() -> usize {
+    STATE_SIZE
+}
+
+// Saves the machine into out, which must hold rust8_state_size() bytes
+#[no_mangle]
+pub unsafe extern "C" fn rust8_save_state(machine: *mut Rust8, out: *mut u8, size: usize) -> i32 {
+    if out.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    if size < STATE_SIZE {
+        return RUST8_ERROR_BUFFER_TOO_SMALL;
+    }
+    let out = slice::from_raw_parts_mut(out, STATE_SIZE);
+    with_machine(machine, |chip8| {
+        out.copy_from_slice(&chip8.save_state());
+        RUST8_OK
+    })
+}
+
+// Restores a state saved by rust8_save_state with the same ROM loaded.
+// The machine is unchanged if the state is rejected.
+#[no_mangle]
+pub unsafe extern "C" fn rust8_load_state(
+    machine: *mut Rust8,
+    data: *const u8,
+    size: usize,
+) -> i32 {
+    if data.is_null() {
+        return RUST8_ERROR_NULL;
+    }
+    let state = slice::from_raw_parts(data, size);
+    with_machine(machine, |chip8| match chip8.load_state(state) {
+        Ok(()) => RUST8_OK,
+        Err(_) => RUST8_ERROR_BAD_STATE,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust8::bus::MEMORY_SIZE;
+    use rust8::screen::{HEIGHT, WIDTH};
+
+    #[test]
+    fn test_constants_match_the_core() {
+        assert_eq!(RUST8_WIDTH, WIDTH);
+        assert_eq!(RUST8_HEIGHT, HEIGHT);
+        assert_eq!(RUST8_MEMORY_SIZE, MEMORY_SIZE);
+    }
+
+    #[test]
+    fn test_errors_instead_of_panics() {
+        unsafe {
+            let machine = rust8_new();
+            let big = vec![0; RUST8_MEMORY_SIZE];
+            assert_eq!(
+                rust8_load_rom(machine, big.as_ptr(), big.len()),
+                RUST8_ERROR_ROM_TOO_BIG
+            );
+            assert_eq!(
+                rust8_set_key(machine, 16, true),
+                RUST8_ERROR_INVALID_ARGUMENT
+            );
+            // 0x0000 is not an instruction
+            assert_eq!(rust8_step(machine, 1), RUST8_ERROR_UNKNOWN_OPCODE);
+            assert_eq!(rust8_run_frame(ptr::null_mut(), 1), RUST8_ERROR_NULL);
+            rust8_free(machine);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+// Generates include/rust8.h from src/lib.rs and checks the header in the
+// repository is up to date. The generator only understands the handful of
+// forms the C interface is written in: constants, opaque and #[repr(C)]
+// structs, and #[no_mangle] functions, each with its comments above it.
+// After changing the interface, regenerate the header with
+//
+//     RUST8_BLESS=1 cargo test -p rust8-capi --test header
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const PREAMBLE: &str = "\
+// rust8.h: C interface to the rust8 chip-8 emulator core
+//
+// Generated from capi/src/lib.rs by
+//     RUST8_BLESS=1 cargo test -p rust8-capi --test header
+// Do not edit by hand.
+//
+// Functions that can fail return RUST8_OK (0) or a negative RUST8_ERROR_
+// code; rust8_error_message describes a code. A machine is not thread safe,
+// but separate machines can be used from separate threads.
+
+#ifndef RUST8_H
+#define RUST8_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {
+#endif
+";
+
+const POSTAMBLE: &str = "
+#ifdef __cplusplus
+}
+#endif
+
+#endif
+";
+
+fn c_type(rust: &str) -> String {
+    let rust = rust.trim();
+    if let Some(pointee) = rust.strip_prefix("*const ") {
+        return format!("const {} *", c_type(pointee));
+    }
+    if let Some(pointee) = rust.strip_prefix("*mut ") {
+        return format!("{} *", c_type(pointee));
+    }
+    match rust {
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "u64" => "uint64_t",
+        "i32" => "int32_t",
+        "usize" => "size_t",
+        "bool" => "bool",
+        "c_char" => "char",
+        "()" => "void",
+        name => name,
+    }
+    .to_string()
+}
+
+// A C declaration of name with the given Rust type, e.g. "const char *name"
+// or "uint8_t v[16]"
+fn declare(name: &str, rust: &str) -> String {
+    let rust = rust.trim();
+    if rust.starts_with('[') {
+        let inner = rust.trim_start_matches('[').trim_end_matches(']');
+        let (element, length) = inner.split_at(inner.find(';').unwrap());
+        return format!("{} {}[{}]", c_type(element), name, length[1..].trim());
+    }
+    let c = c_type(rust);
+    if c.ends_with('*') {
+        format!("{}{}", c, name)
+    } else {
+        format!("{} {}", c, name)
+    }
+}
+
+// "name: type" split at the colon
+fn field(text: &str) -> (&str, &str) {
+    let colon = text.find(':').unwrap();
+    (text[..colon].trim(), &text[colon + 1..])
+}
+
+fn function(signature: &str) -> String {
+    let open = signature.find('(').unwrap();
+    let close = signature.rfind(')').unwrap();
+    let name = signature[..open].rsplit(' ').next().unwrap();
+    let params: Vec<String> = signature[open + 1..close]
+        .split(',')
+        .map(str::trim)
+        .filter(|param| !param.is_empty())
+        .map(|param| {
+            let (name, rust) = field(param);
+            declare(name, rust)
+        })
+        .collect();
+    let returns = signature[close + 1..]
+        .trim()
+        .trim_end_matches('{')
+        .trim()
+        .trim_start_matches("->");
+    let returns = if returns.trim().is_empty() {
+        "()"
+    } else {
+        returns
+    };
+    let params = if params.is_empty() {
+        "void".to_string()
+    } else {
+        params.join(", ")
+    };
+    format!("{};", declare(&format!("{}({})", name, params), returns))
+}
+
+fn generate(source: &str) -> String {
+    let mut header = PREAMBLE.to_string();
+    let mut comments: Vec<&str> = Vec::new();
+    let mut repr_c = false;
+    let mut last_define = false;
+    // Set by a blank line, which starts a new group of constants
+    let mut separated = false;
+    let mut lines = source.lines().take_while(|line| *line != "#[cfg(test)]");
+    while let Some(line) = lines.next() {
+        let mut item = Vec::new();
+        if let Some(comment) = line.strip_prefix("//") {
+            comments.push(comment);
+            continue;
+        } else if line == "#[repr(C)]" {
+            repr_c = true;
+            continue;
+        } else if line == "#[no_mangle]" {
+            let mut signature = String::new();
+            for line in lines.by_ref() {
+                signature.push_str(line.trim());
+                signature.push(' ');
+                if line.ends_with('{') {
+                    break;
+                }
+            }
+            item.push(function(&signature));
+        } else if let Some(constant) = line.strip_prefix("pub const ") {
+            let (name, rest) = field(constant);
+            let value = rest.split('=').nth(1).unwrap().trim().trim_end_matches(';');
+            if value.starts_with('-') {
+                item.push(format!("#define {} ({})", name, value));
+            } else {
+                item.push(format!("#define {} {}", name, value));
+            }
+        } else if let Some(name) = line.strip_prefix("pub struct ") {
+            let name = name.trim_end_matches('{').trim();
+            if repr_c {
+                item.push(format!("typedef struct {} {{", name));
+                for line in lines.by_ref() {
+                    let line = line.trim();
+                    if line == "}" {
+                        break;
+                    } else if let Some(comment) = line.strip_prefix("//") {
+                        item.push(format!("    //{}", comment));
+                    } else if let Some(text) = line.strip_prefix("pub ") {
+                        let (field_name, rust) = field(text.trim_end_matches(','));
+                        item.push(format!("    {};", declare(field_name, rust)));
+                    }
+                }
+                item.push(format!("}} {};", name));
+            } else {
+                // Skip the fields, which C never sees
+                for line in lines.by_ref() {
+                    if line == "}" {
+                        break;
+                    }
+                }
+                item.push(format!("typedef struct {} {};", name, name));
+            }
+        } else {
+            comments.clear();
+            repr_c = false;
+            separated |= line.is_empty();
+            continue;
+        }
+
+        // Constants without comments are kept together with the ones above
+        let define = item[0].starts_with("#define");
+        if !(define && last_define && comments.is_empty() && !separated) {
+            header.push('\n');
+        }
+        last_define = define;
+        separated = false;
+        for comment in comments.drain(..) {
+            header.push_str(&format!("//{}\n", comment));
+        }
+        for line in item {
+            header.push_str(&line);
+            header.push('\n');
+        }
+        repr_c = false;
+    }
+    header.push_str(POSTAMBLE);
+    header
+}
+
+#[test]
+fn test_header_is_up_to_date() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let source = fs::read_to_string(dir.join("src/lib.rs")).unwrap();
+    let header = generate(&source);
+    let path = dir.join("include/rust8.h");
+    if env::var_os("RUST8_BLESS").is_some() {
+        fs::write(&path, &header).unwrap();
+        return;
+    }
+    let current = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        current == header,
+        "include/rust8.h is out of date with src/lib.rs, regenerate it with\n\
+         RUST8_BLESS=1 cargo test -p rust8-capi --test header"
+    );
+}
+
+#[test]
+fn test_declarations() {
+    assert_eq!(declare("data", "*const u8"), "const uint8_t *data");
+    assert_eq!(declare("v", "[u8; 16]"), "uint8_t v[16]");
+    assert_eq!(
+        function("pub extern \"C\" fn rust8_version() -> *const c_char {"),
+        "const char *rust8_version(void);"
+    );
+    assert_eq!(
+        function("pub unsafe extern \"C\" fn rust8_free(machine: *mut Rust8) {"),
+        "void rust8_free(Rust8 *machine);"
+    );
+}
@@ -0,0 +1,91 @@
+/*
+ * Smoke test of the C interface, linked against the shared library.
+ * Prints "ok" and exits 0 if every check passes.
+ */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "rust8.h"
+
+static int failures;
+
+#define CHECK(cond)                                                     \
+    do {                                                                \
+        if (!(cond)) {                                                  \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,      \
+                    __LINE__, #cond);                                   \
+            failures++;                                                 \
+        }                                                               \
+    } while (0)
+
+/* LD V0, 5; LD F, V0; LD V1, 0; DRW V1, V1, 5; JP 0x208 */
+static const uint8_t ROM[] = {
+    0x60, 0x05, 0xF0, 0x29, 0x61, 0x00, 0xD1, 0x15, 0x12, 0x08,
+};
+
+int main(void)
+{
+    CHECK(strlen(rust8_version()) > 0);
+    CHECK(strcmp(rust8_error_message(RUST8_ERROR_STACK_OVERFLOW), "Stack overflow") == 0);
+
+    Rust8 *machine = rust8_new();
+    CHECK(machine != NULL);
+    CHECK(rust8_load_rom(machine, ROM, sizeof(ROM)) == RUST8_OK);
+    CHECK(rust8_set_quirks(machine, "vip") == RUST8_OK);
+    CHECK(rust8_set_quirks(machine, "nonsense") == RUST8_ERROR_INVALID_ARGUMENT);
+    CHECK(rust8_seed(machine, 1) == RUST8_OK);
+    CHECK(rust8_run_frame(machine, 8) == RUST8_OK);
+
+    /* The top row of the 5 is four lit pixels */
+    uint8_t screen[RUST8_WIDTH * RUST8_HEIGHT];
+    CHECK(rust8_framebuffer(machine, screen, sizeof(screen)) == RUST8_OK);
+    CHECK(screen[0] == 1 && screen[3] == 1 && screen[4] == 0);
+    CHECK(rust8_framebuffer(machine, screen, 10) == RUST8_ERROR_BUFFER_TOO_SMALL);
+
+    Rust8Registers registers;
+    CHECK(rust8_registers(machine, &registers) == RUST8_OK);
+    CHECK(registers.v[0] == 5);
+    CHECK(registers.pc == 0x208);
+    CHECK(registers.sp == 0);
+
+    uint8_t memory[sizeof(ROM)];
+    CHECK(rust8_read_memory(machine, 0x200, memory, sizeof(memory)) == RUST8_OK);
+    CHECK(memcmp(memory, ROM, sizeof(ROM)) == 0);
+    CHECK(rust8_read_memory(machine, 0xFFF, memory, 2) == RUST8_ERROR_INVALID_ARGUMENT);
+
+    CHECK(rust8_set_key(machine, 0xF, true) == RUST8_OK);
+    CHECK(rust8_set_key(machine, 0x10, true) == RUST8_ERROR_INVALID_ARGUMENT);
+
+    /* Save, run on, and load the state back */
+    size_t size = rust8_state_size();
+    uint8_t *state = malloc(size);
+    CHECK(rust8_save_state(machine, state, size) == RUST8_OK);
+    CHECK(rust8_step(machine, 3) == RUST8_OK);
+    CHECK(rust8_load_state(machine, state, size) == RUST8_OK);
+    CHECK(rust8_load_state(machine, state, size - 1) == RUST8_ERROR_BAD_STATE);
+    CHECK(rust8_registers(machine, &registers) == RUST8_OK);
+    CHECK(registers.pc == 0x208);
+    free(state);
+
+    /* Faults and bad arguments come back as error codes */
+    uint8_t bad[] = { 0xFF, 0xFF };
+    Rust8 *other = rust8_new();
+    CHECK(rust8_load_rom(other, bad, sizeof(bad)) == RUST8_OK);
+    CHECK(rust8_step(other, 1) == RUST8_ERROR_UNKNOWN_OPCODE);
+    uint8_t big[RUST8_MEMORY_SIZE] = { 0 };
+    CHECK(rust8_load_rom(other, big, sizeof(big)) == RUST8_ERROR_ROM_TOO_BIG);
+    CHECK(rust8_step(NULL, 1) == RUST8_ERROR_NULL);
+    CHECK(rust8_registers(machine, NULL) == RUST8_ERROR_NULL);
+
+    rust8_free(other);
+    rust8_free(machine);
+    rust8_free(NULL);
+
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,43 @@
+// Builds tests/smoke.c against the header and the shared library and runs
+// it. Needs a C compiler; without one the test is skipped.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_c_smoke() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // The library cargo built alongside this test
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let smoke = env::temp_dir().join(format!("rust8-capi-smoke-{}", std::process::id()));
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let built = Command::new(&cc)
+        .arg(manifest.join("tests/smoke.c"))
+        .arg("-I")
+        .arg(manifest.join("include"))
+        .arg("-L")
+        .arg(&deps)
+        .arg("-lrust8_capi")
+        .arg(format!("-Wl,-rpath,{}", deps.display()))
+        .arg("-o")
+        .arg(&smoke)
+        .status();
+    match built {
+        Ok(status) => assert!(status.success(), "Failed to build the smoke test"),
+        Err(e) => {
+            println!("Skipping, no C compiler ({}): {}", cc, e);
+            return;
+        }
+    }
+
+    let output = Command::new(&smoke).output().unwrap();
+    let _ = std::fs::remove_file(&smoke);
+    assert!(
+        output.status.success(),
+        "Smoke test failed:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
@@ -0,0 +1,10 @@
+// Arbitrary ROMs and starting registers, run on both engines in lockstep
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rust8;
+
+#[path = "../harness.rs"]
+mod harness;
+
+fuzz_target!(|data: &[u8]| harness::interpreter(data));
@@ -0,0 +1,10 @@
+// Arbitrary ROMs through the loader, analysis, disassembler and decompiler
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rust8;
+
+#[path = "../harness.rs"]
+mod harness;
+
+fuzz_target!(|data: &[u8]| harness::rom(data));
@@ -0,0 +1,10 @@
+// Arbitrary save states, whole and as the body behind a good header
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate rust8;
+
+#[path = "../harness.rs"]
+mod harness;
+
+fuzz_target!(|data: &[u8]| harness::state(data));
@@ -0,0 +1,135 @@
+// The bodies of the fuzz targets, shared with the regression tests in
+// tests/fuzz.rs so that every crash the fuzzer has found is checked by
+// cargo test. Each takes the raw input and panics on a bug.
+
+// Each fuzz target uses only one of them
+#![allow(dead_code)]
+
+use rust8::analysis;
+use rust8::bus::{Ram, MEMORY_SIZE};
+use rust8::decompile;
+use rust8::disasm;
+use rust8::engine::Engine;
+use rust8::fault::OutOfRange;
+use rust8::state::STATE_SIZE;
+use rust8::Chip8;
+
+// Instructions run for each interpreter input
+const CYCLES: u32 = 1000;
+// Timers tick every this many instructions
+const CYCLES_PER_FRAME: u32 = 10;
+
+// Bytes at the start of an interpreter input that set up the machine:
+// V0-VF, I, the delay and sound timers, the stack pointer, the keys held
+// down, a bit per quirk and whether out of range accesses wrap
+const SETUP: usize = 16 + 2 + 2 + 1 + 2 + 1 + 1;
+
+// Where save states keep the registers, after the magic, version, ROM hash
+// and memory. I, the timers, PC and SP follow.
+const REGISTERS: usize = 4 + 1 + 20 + MEMORY_SIZE;
+
+// A machine with the ROM loaded and the registers set up, through a save
+// state as that is the only way in from outside
+fn machine(setup: &[u8], rom: &[u8], engine: Engine) -> Option<Chip8> {
+    let mut chip8 = Chip8::with_seed(Ram::new(), 1);
+    chip8.load_rom(rom).ok()?;
+    let mut state = chip8.save_state();
+    state[REGISTERS..REGISTERS + 16].copy_from_slice(&setup[..16]);
+    state[REGISTERS + 16..REGISTERS + 20].copy_from_slice(&setup[16..20]);
+    state[REGISTERS + 22] = setup[20];
+    chip8.load_state(&state).ok()?;
+
+    for (key, down) in chip8.keys.iter_mut().enumerate() {
+        *down = u16::from_be_bytes([setup[21], setup[22]]) >> key & 1 == 1;
+    }
+    let quirks = setup[23];
+    chip8.quirks.shift_vy = quirks & 1 != 0;
+    chip8.quirks.increment_i = quirks & 2 != 0;
+    chip8.quirks.vf_reset = quirks & 4 != 0;
+    chip8.quirks.jump_vx = quirks & 8 != 0;
+    chip8.quirks.clip_sprites = quirks & 16 != 0;
+    chip8.quirks.display_wait = quirks & 32 != 0;
+    chip8.out_of_range = if setup[24] & 1 != 0 {
+        OutOfRange::Wrap
+    } else {
+        OutOfRange::Fault
+    };
+    chip8.set_engine(engine);
+    Some(chip8)
+}
+
+// Runs the ROM after the setup bytes on both engines, which have to agree
+// on every instruction, until it faults or the cycles run out
+pub fn interpreter(data: &[u8]) {
+    if data.len() < SETUP {
+        return;
+    }
+    let (setup, rom) = data.split_at(SETUP);
+    let (mut interpreter, mut cached) = match (
+        machine(setup, rom, Engine::Interpreter),
+        machine(setup, rom, Engine::Cached),
+    ) {
+        (Some(interpreter), Some(cached)) => (interpreter, cached),
+        _ => return,
+    };
+    for cycle in 1..=CYCLES {
+        let result = interpreter.cycle();
+        assert_eq!(result, cached.cycle(), "result at cycle {}", cycle);
+        assert_eq!(interpreter.pc(), cached.pc(), "PC at cycle {}", cycle);
+        assert_eq!(interpreter.registers(), cached.registers());
+        assert_eq!(interpreter.index(), cached.index());
+        if result.is_err() {
+            break;
+        }
+        if cycle % CYCLES_PER_FRAME == 0 {
+            interpreter.update_timers();
+            cached.update_timers();
+        }
+    }
+    assert_eq!(interpreter.state_hash(), cached.state_hash());
+}
+
+// Loads the input as a ROM and runs the analysis, disassembler and
+// decompiler over it
+pub fn rom(data: &[u8]) {
+    let mut chip8 = Chip8::new();
+    if chip8.load_rom(data).is_err() {
+        assert!(data.len() > rust8::MAX_ROM_SIZE);
+        return;
+    }
+    let analysis = analysis::analyze(data);
+    disasm::listing(&analysis);
+    decompile::decompile(&analysis);
+}
+
+// Loads the input as a save state, and also as the body of a state with a
+// good header so the fuzzer doesn't have to find one. States that load have
+// to run and save back the same.
+pub fn state(data: &[u8]) {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(&[0x12, 0x00]).unwrap();
+    let _ = chip8.load_state(data);
+
+    let mut state = chip8.save_state();
+    let header = 4 + 1 + 20;
+    let body = data.len().min(STATE_SIZE - header);
+    state[header..header + body].copy_from_slice(&data[..body]);
+    if chip8.load_state(&state).is_err() {
+        return;
+    }
+    let saved = chip8.save_state();
+    // The screen is saved a byte per pixel, so anything but 0 reads back as 1
+    let screen = REGISTERS + 16 + 2 + 2 + 2 + 1 + 16 * 2;
+    for (i, (&before, &after)) in state.iter().zip(saved.iter()).enumerate() {
+        if i >= screen && i < screen + 64 * 32 + 1 {
+            assert_eq!(before != 0, after != 0, "state byte {}", i);
+        } else {
+            assert_eq!(before, after, "state byte {}", i);
+        }
+    }
+    for _ in 0..CYCLES {
+        if chip8.cycle().is_err() {
+            break;
+        }
+    }
+}
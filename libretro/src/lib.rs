@@ -0,0 +1,611 @@
+// rust8 as a libretro core, for RetroArch and other libretro frontends.
+//
+// Each retro_run is one 60 Hz frame. The screen goes out as 64x32 XRGB8888,
+// the beeper as a 440 Hz square wave at 44.1 kHz, and the RetroPad's 16
+// buttons map one to one onto the hex keys. Save states are the machine's
+// own, and the quirks, speed and colours are core options. Frontends call a
+// core from one thread, so its state lives in thread locals.
+
+// Every entry point takes its pointers on trust from the frontend, as the
+// libretro API requires
+#![allow(clippy::missing_safety_doc)]
+// The types keep their names from libretro.h
+#![allow(non_camel_case_types)]
+
+extern crate rust8;
+
+use std::cell::{Cell, RefCell};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::slice;
+
+use rust8::bus::{Bus, MEMORY_SIZE};
+use rust8::display::{Filter, Framebuffer};
+use rust8::fault::{Fault, OutOfRange};
+use rust8::palette::Palette;
+use rust8::quirks::Quirks;
+use rust8::romdb::RomDb;
+use rust8::screen::{HEIGHT, WIDTH};
+use rust8::state::STATE_SIZE;
+use rust8::timing::Timing;
+use rust8::Chip8;
+
+pub const RETRO_API_VERSION: c_uint = 1;
+
+pub const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+pub const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+pub const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+
+pub const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+pub const RETRO_DEVICE_JOYPAD: c_uint = 1;
+pub const RETRO_REGION_NTSC: c_uint = 0;
+pub const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+
+pub const FRAMERATE: f64 = 60.0;
+pub const SAMPLE_RATE: f64 = 44100.0;
+// Stereo frames of audio sent each retro_run
+pub const AUDIO_FRAMES: usize = 735;
+const TONE: f32 = 440.0;
+const VOLUME: i16 = 0x2000;
+
+// RetroPad button ids and the hex key each one presses. The d-pad is on
+// 2, 4, 6 and 8 and A on 5, which is how most games lay out their controls.
+pub const JOYPAD: [(c_uint, u8); 16] = [
+    (4, 0x2),  // Up
+    (5, 0x8),  // Down
+    (6, 0x4),  // Left
+    (7, 0x6),  // Right
+    (8, 0x5),  // A
+    (0, 0x0),  // B
+    (9, 0x1),  // X
+    (1, 0x3),  // Y
+    (10, 0x7), // L
+    (11, 0x9), // R
+    (12, 0xA), // L2
+    (13, 0xB), // R2
+    (2, 0xC),  // Select
+    (3, 0xD),  // Start
+    (14, 0xE), // L3
+    (15, 0xF), // R3
+];
+
+#[repr(C)]
+pub struct retro_system_info {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct retro_game_geometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct retro_system_timing {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct retro_system_av_info {
+    pub geometry: retro_game_geometry,
+    pub timing: retro_system_timing,
+}
+
+#[repr(C)]
+pub struct retro_game_info {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+#[repr(C)]
+pub struct retro_variable {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+pub type retro_environment_t = unsafe extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type retro_video_refresh_t =
+    unsafe extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type retro_audio_sample_t = unsafe extern "C" fn(left: i16, right: i16);
+pub type retro_audio_sample_batch_t =
+    unsafe extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type retro_input_poll_t = unsafe extern "C" fn();
+pub type retro_input_state_t =
+    unsafe extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+// The core options as "key", "Description; first|second|...". The first
+// value is the default.
+const VARIABLES: [(&[u8], &[u8]); 4] = [
+    (
+        b"rust8_quirks\0",
+        b"Quirks; auto|default|vip|schip|xo-chip\0",
+    ),
+    (
+        b"rust8_cycles\0",
+        b"Instructions per frame; auto|8|10|15|20|30|50|100|200|500|1000\0",
+    ),
+    (
+        b"rust8_theme\0",
+        b"Colours; classic|amber|green|lcd|high-contrast|colorblind\0",
+    ),
+    (
+        b"rust8_filter\0",
+        b"Anti-flicker filter; auto|none|phosphor|blend|max2\0",
+    ),
+];
+
+// Instructions per frame when neither the options nor the ROM database say
+const DEFAULT_CYCLES: u32 = 8;
+
+#[derive(Clone, Copy, Default)]
+struct Callbacks {
+    environment: Option<retro_environment_t>,
+    video: Option<retro_video_refresh_t>,
+    audio_batch: Option<retro_audio_sample_batch_t>,
+    input_poll: Option<retro_input_poll_t>,
+    input_state: Option<retro_input_state_t>,
+}
+
+// Settings from the core options, with "auto" filled in from the ROM
+// database
+#[derive(Clone, Copy)]
+struct Settings {
+    quirks: Quirks,
+    out_of_range: OutOfRange,
+    timing: Timing,
+    cycles: u32,
+    palette: Palette,
+    filter: Filter,
+}
+
+struct Core {
+    chip8: Chip8,
+    rom: Vec<u8>,
+    settings: Settings,
+    // Set when the game faults. It stays stopped until reset.
+    fault: Option<Fault>,
+    framebuffer: Framebuffer,
+    video: Vec<u32>,
+    audio: Vec<i16>,
+    phase: f32,
+}
+
+thread_local! {
+    static CALLBACKS: Cell<Callbacks> = Cell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn callbacks() -> Callbacks {
+    CALLBACKS.with(|c| c.get())
+}
+
+fn set_callbacks<F: FnOnce(&mut Callbacks)>(f: F) {
+    CALLBACKS.with(|c| {
+        let mut callbacks = c.get();
+        f(&mut callbacks);
+        c.set(callbacks);
+    });
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => unsafe { environment(cmd, data) },
+        None => false,
+    }
+}
+
+// The value of a core option, if the frontend has one
+fn variable(key: &[u8]) -> Option<String> {
+    let mut variable = retro_variable {
+        key: key.as_ptr() as *const c_char,
+        value: ptr::null(),
+    };
+    let found = environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE,
+        &mut variable as *mut retro_variable as *mut c_void,
+    );
+    if !found || variable.value.is_null() {
+        return None;
+    }
+    let value = unsafe { CStr::from_ptr(variable.value) };
+    Some(value.to_string_lossy().into_owned())
+}
+
+// Reads the core options, taking anything left on auto from the game's
+// entry in the ROM database
+fn settings(rom: &[u8]) -> Settings {
+    let mut settings = Settings {
+        quirks: Quirks::default(),
+        out_of_range: OutOfRange::default(),
+        timing: Timing::default(),
+        cycles: DEFAULT_CYCLES,
+        palette: Palette::default(),
+        filter: Filter::default(),
+    };
+    if let Some(game) = RomDb::bundled().lookup(&rust8::sha1::sha1(rom)) {
+        for setting in &game.settings {
+            match setting.key.as_str() {
+                "quirks" => settings.quirks = setting.value.parse().unwrap_or_default(),
+                "out-of-range" => settings.out_of_range = setting.value.parse().unwrap_or_default(),
+                "timing" => settings.timing = setting.value.parse().unwrap_or_default(),
+                "cycles" => settings.cycles = setting.value.parse().unwrap_or(DEFAULT_CYCLES),
+                "filter" => settings.filter = setting.value.parse().unwrap_or_default(),
+                _ => {}
+            }
+        }
+    }
+    if let Some(quirks) = variable(VARIABLES[0].0).and_then(|v| v.parse().ok()) {
+        settings.quirks = quirks;
+    }
+    if let Some(cycles) = variable(VARIABLES[1].0).and_then(|v| v.parse().ok()) {
+        settings.cycles = cycles;
+    }
+    if let Some(palette) = variable(VARIABLES[2].0).and_then(|v| Palette::theme(&v)) {
+        settings.palette = palette;
+    }
+    if let Some(filter) = variable(VARIABLES[3].0).and_then(|v| v.parse().ok()) {
+        settings.filter = filter;
+    }
+    settings
+}
+
+// A fresh machine with the game loaded
+fn machine(rom: &[u8], settings: &Settings) -> Option<Chip8> {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(rom).ok()?;
+    chip8.quirks = settings.quirks;
+    chip8.out_of_range = settings.out_of_range;
+    chip8.timing = settings.timing;
+    Some(chip8)
+}
+
+// The filtered screen as 0RGB pixels in the palette's colours
+pub fn to_xrgb(framebuffer: &Framebuffer, palette: &Palette, out: &mut [u32]) {
+    let mut rgba = [0; WIDTH * HEIGHT * 4];
+    framebuffer.to_rgba(palette.foreground(), palette.background(), &mut rgba);
+    for (pixel, rgba) in out.iter_mut().zip(rgba.chunks(4)) {
+        *pixel = (rgba[0] as u32) << 16 | (rgba[1] as u32) << 8 | rgba[2] as u32;
+    }
+}
+
+impl Core {
+    fn run(&mut self) {
+        let callbacks = callbacks();
+        if let Some(input_poll) = callbacks.input_poll {
+            unsafe { input_poll() };
+        }
+        if let Some(input_state) = callbacks.input_state {
+            for &(id, key) in JOYPAD.iter() {
+                let pressed = unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, id) };
+                self.chip8.keys[key as usize] = pressed != 0;
+            }
+        }
+
+        if self.fault.is_none() {
+            if let Err(fault) = self.chip8.run_frame(self.settings.cycles) {
+                eprintln!("rust8: {}", fault);
+                self.fault = Some(fault);
+            }
+        }
+
+        self.framebuffer
+            .update(&self.chip8.screen, 1000.0 / FRAMERATE as f32);
+        to_xrgb(&self.framebuffer, &self.settings.palette, &mut self.video);
+        if let Some(video) = callbacks.video {
+            unsafe {
+                video(
+                    self.video.as_ptr() as *const c_void,
+                    WIDTH as c_uint,
+                    HEIGHT as c_uint,
+                    WIDTH * 4,
+                )
+            };
+        }
+
+        // The square wave carries on from where the last frame left it so
+        // there are no clicks between frames
+        let tone = self.chip8.sound_timer > 0 && self.fault.is_none();
+        for frame in self.audio.chunks_mut(2) {
+            let sample = match (tone, self.phase < 0.5) {
+                (false, _) => 0,
+                (true, true) => VOLUME,
+                (true, false) => -VOLUME,
+            };
+            frame[0] = sample;
+            frame[1] = sample;
+            self.phase = (self.phase + TONE / SAMPLE_RATE as f32) % 1.0;
+        }
+        if let Some(audio_batch) = callbacks.audio_batch {
+            let mut sent = 0;
+            while sent < AUDIO_FRAMES {
+                let taken =
+                    unsafe { audio_batch(self.audio[sent * 2..].as_ptr(), AUDIO_FRAMES - sent) };
+                if taken == 0 {
+                    break;
+                }
+                sent += taken;
+            }
+        }
+    }
+}
+
+fn with_core<T, F: FnOnce(&mut Core) -> T>(f: F) -> Option<T> {
+    CORE.with(|core| core.borrow_mut().as_mut().map(f))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut retro_system_info) {
+    *info = retro_system_info {
+        library_name: b"rust8\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut retro_system_av_info) {
+    *info = retro_system_av_info {
+        geometry: retro_game_geometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: retro_system_timing {
+            fps: FRAMERATE,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: retro_environment_t) {
+    set_callbacks(|c| c.environment = Some(callback));
+    let mut variables: Vec<retro_variable> = VARIABLES
+        .iter()
+        .map(|&(key, value)| retro_variable {
+            key: key.as_ptr() as *const c_char,
+            value: value.as_ptr() as *const c_char,
+        })
+        .collect();
+    variables.push(retro_variable {
+        key: ptr::null(),
+        value: ptr::null(),
+    });
+    environment(
+        RETRO_ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr() as *mut c_void,
+    );
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: retro_video_refresh_t) {
+    set_callbacks(|c| c.video = Some(callback));
+}
+
+// Audio goes out a frame at a time through the batch callback
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: retro_audio_sample_t) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: retro_audio_sample_batch_t) {
+    set_callbacks(|c| c.audio_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: retro_input_poll_t) {
+    set_callbacks(|c| c.input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: retro_input_state_t) {
+    set_callbacks(|c| c.input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const retro_game_info) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut c_uint as *mut c_void,
+    ) {
+        eprintln!("rust8: the frontend does not support XRGB8888");
+        return false;
+    }
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    let settings = settings(&rom);
+    let chip8 = match machine(&rom, &settings) {
+        Some(chip8) => chip8,
+        None => {
+            eprintln!("rust8: ROM is too big, {} bytes", rom.len());
+            return false;
+        }
+    };
+    CORE.with(|core| {
+        *core.borrow_mut() = Some(Core {
+            chip8,
+            rom,
+            settings,
+            fault: None,
+            framebuffer: Framebuffer::new(settings.filter),
+            video: vec![0; WIDTH * HEIGHT],
+            audio: vec![0; AUDIO_FRAMES * 2],
+            phase: 0.0,
+        })
+    });
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const retro_game_info,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| *core.borrow_mut() = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| {
+        if let Some(chip8) = machine(&core.rom, &core.settings) {
+            core.chip8 = chip8;
+            core.fault = None;
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let mut updated = false;
+    environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+        &mut updated as *mut bool as *mut c_void,
+    );
+    with_core(|core| {
+        if updated {
+            core.settings = settings(&core.rom);
+            core.chip8.quirks = core.settings.quirks;
+            core.chip8.out_of_range = core.settings.out_of_range;
+            core.chip8.timing = core.settings.timing;
+            if core.settings.filter != core.framebuffer.filter() {
+                core.framebuffer.set_filter(core.settings.filter);
+            }
+        }
+        core.run();
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() || size < STATE_SIZE {
+        return false;
+    }
+    let out = slice::from_raw_parts_mut(data as *mut u8, STATE_SIZE);
+    with_core(|core| out.copy_from_slice(&core.chip8.save_state())).is_some()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = slice::from_raw_parts(data as *const u8, size);
+    with_core(|core| match core.chip8.load_state(state) {
+        Ok(()) => {
+            core.fault = None;
+            true
+        }
+        Err(e) => {
+            eprintln!("rust8: {}", e);
+            false
+        }
+    })
+    .unwrap_or(false)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+    with_core(|core| core.chip8.bus_mut().memory_mut().as_mut_ptr() as *mut c_void)
+        .unwrap_or(ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match id {
+        RETRO_MEMORY_SYSTEM_RAM => MEMORY_SIZE,
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust8::screen::Screen;
+
+    #[test]
+    fn test_joypad_covers_every_key() {
+        let mut keys: Vec<u8> = JOYPAD.iter().map(|&(_, key)| key).collect();
+        let mut ids: Vec<c_uint> = JOYPAD.iter().map(|&(id, _)| id).collect();
+        keys.sort();
+        ids.sort();
+        assert_eq!(keys, (0..16).collect::<Vec<u8>>());
+        assert_eq!(ids, (0..16).collect::<Vec<c_uint>>());
+    }
+
+    #[test]
+    fn test_to_xrgb() {
+        let mut screen = Screen::new();
+        screen.set(1, 0, true);
+        let mut framebuffer = Framebuffer::new(Filter::Blend { frames: 2 });
+        framebuffer.update(&screen, 16.0);
+        let mut out = vec![0; WIDTH * HEIGHT];
+        let amber = Palette::theme("amber").unwrap();
+        to_xrgb(&framebuffer, &amber, &mut out);
+        assert_eq!(out[0], 0x1A1000);
+        assert_eq!(out[1], 0xFFB000);
+
+        // Blending with a frame where the pixel is off gives half its colour
+        framebuffer.update(&Screen::new(), 16.0);
+        to_xrgb(&framebuffer, &amber, &mut out);
+        assert_eq!(out[1], 0x8D6000);
+    }
+}
@@ -0,0 +1,284 @@
+/*
+ * A minimal libretro frontend for testing the core without RetroArch.
+ *
+ *     harness CORE ROM
+ *
+ * Loads the core with dlopen, runs the ROM for a second holding the A
+ * button, and checks the video, audio, options and save states. Prints
+ * "ok" and exits 0 if everything checks out.
+ */
+#include <dlfcn.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* The parts of libretro.h the harness uses */
+#define RETRO_API_VERSION 1
+#define RETRO_ENVIRONMENT_SET_PIXEL_FORMAT 10
+#define RETRO_ENVIRONMENT_GET_VARIABLE 15
+#define RETRO_ENVIRONMENT_SET_VARIABLES 16
+#define RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE 17
+#define RETRO_PIXEL_FORMAT_XRGB8888 1
+#define RETRO_DEVICE_JOYPAD 1
+#define RETRO_DEVICE_ID_JOYPAD_A 8
+#define RETRO_MEMORY_SYSTEM_RAM 2
+
+struct retro_system_info {
+    const char *library_name;
+    const char *library_version;
+    const char *valid_extensions;
+    bool need_fullpath;
+    bool block_extract;
+};
+
+struct retro_game_geometry {
+    unsigned base_width, base_height, max_width, max_height;
+    float aspect_ratio;
+};
+
+struct retro_system_timing {
+    double fps, sample_rate;
+};
+
+struct retro_system_av_info {
+    struct retro_game_geometry geometry;
+    struct retro_system_timing timing;
+};
+
+struct retro_game_info {
+    const char *path;
+    const void *data;
+    size_t size;
+    const char *meta;
+};
+
+struct retro_variable {
+    const char *key;
+    const char *value;
+};
+
+typedef bool (*retro_environment_t)(unsigned, void *);
+typedef void (*retro_video_refresh_t)(const void *, unsigned, unsigned, size_t);
+typedef void (*retro_audio_sample_t)(int16_t, int16_t);
+typedef size_t (*retro_audio_sample_batch_t)(const int16_t *, size_t);
+typedef void (*retro_input_poll_t)(void);
+typedef int16_t (*retro_input_state_t)(unsigned, unsigned, unsigned, unsigned);
+
+static int failures;
+
+#define CHECK(cond)                                                     \
+    do {                                                                \
+        if (!(cond)) {                                                  \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,      \
+                    __LINE__, #cond);                                   \
+            failures++;                                                 \
+        }                                                               \
+    } while (0)
+
+static void *core;
+
+static void *symbol(const char *name)
+{
+    void *sym = dlsym(core, name);
+    if (!sym) {
+        fprintf(stderr, "missing symbol %s\n", name);
+        exit(1);
+    }
+    return sym;
+}
+
+#define LOAD(ret, name, args) ret(*name) args = (ret(*) args)symbol(#name)
+
+/* What the frontend saw */
+static unsigned pixel_format;
+static int options_set;
+static int frames;
+static uint32_t screen[64 * 32];
+static long audio_frames;
+static int polls;
+
+static bool environment(unsigned cmd, void *data)
+{
+    switch (cmd) {
+    case RETRO_ENVIRONMENT_SET_PIXEL_FORMAT:
+        pixel_format = *(unsigned *)data;
+        return true;
+    case RETRO_ENVIRONMENT_SET_VARIABLES: {
+        const struct retro_variable *vars = data;
+        for (; vars->key; vars++)
+            options_set++;
+        return true;
+    }
+    case RETRO_ENVIRONMENT_GET_VARIABLE: {
+        struct retro_variable *var = data;
+        if (strcmp(var->key, "rust8_cycles") == 0) {
+            var->value = "20";
+            return true;
+        }
+        return false;
+    }
+    case RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE:
+        *(bool *)data = false;
+        return true;
+    }
+    return false;
+}
+
+static void video(const void *data, unsigned width, unsigned height, size_t pitch)
+{
+    CHECK(width == 64 && height == 32 && pitch == 64 * 4);
+    memcpy(screen, data, sizeof(screen));
+    frames++;
+}
+
+static void audio_sample(int16_t left, int16_t right)
+{
+    (void)left;
+    (void)right;
+}
+
+static size_t audio_batch(const int16_t *data, size_t count)
+{
+    (void)data;
+    audio_frames += count;
+    return count;
+}
+
+static void input_poll(void)
+{
+    polls++;
+}
+
+static int16_t input_state(unsigned port, unsigned device, unsigned index, unsigned id)
+{
+    (void)index;
+    return port == 0 && device == RETRO_DEVICE_JOYPAD && id == RETRO_DEVICE_ID_JOYPAD_A;
+}
+
+static void *read_file(const char *path, size_t *size)
+{
+    FILE *f = fopen(path, "rb");
+    if (!f) {
+        perror(path);
+        exit(1);
+    }
+    fseek(f, 0, SEEK_END);
+    *size = ftell(f);
+    fseek(f, 0, SEEK_SET);
+    void *data = malloc(*size);
+    if (fread(data, 1, *size, f) != *size) {
+        perror(path);
+        exit(1);
+    }
+    fclose(f);
+    return data;
+}
+
+static int lit_pixels(void)
+{
+    int lit = 0;
+    for (int i = 0; i < 64 * 32; i++)
+        if (screen[i] != screen[0])
+            lit++;
+    return lit;
+}
+
+int main(int argc, char **argv)
+{
+    if (argc != 3) {
+        fprintf(stderr, "usage: %s CORE ROM\n", argv[0]);
+        return 2;
+    }
+    core = dlopen(argv[1], RTLD_NOW);
+    if (!core) {
+        fprintf(stderr, "%s\n", dlerror());
+        return 1;
+    }
+
+    LOAD(unsigned, retro_api_version, (void));
+    LOAD(void, retro_get_system_info, (struct retro_system_info *));
+    LOAD(void, retro_get_system_av_info, (struct retro_system_av_info *));
+    LOAD(void, retro_set_environment, (retro_environment_t));
+    LOAD(void, retro_set_video_refresh, (retro_video_refresh_t));
+    LOAD(void, retro_set_audio_sample, (retro_audio_sample_t));
+    LOAD(void, retro_set_audio_sample_batch, (retro_audio_sample_batch_t));
+    LOAD(void, retro_set_input_poll, (retro_input_poll_t));
+    LOAD(void, retro_set_input_state, (retro_input_state_t));
+    LOAD(void, retro_init, (void));
+    LOAD(void, retro_deinit, (void));
+    LOAD(bool, retro_load_game, (const struct retro_game_info *));
+    LOAD(void, retro_unload_game, (void));
+    LOAD(void, retro_run, (void));
+    LOAD(void, retro_reset, (void));
+    LOAD(size_t, retro_serialize_size, (void));
+    LOAD(bool, retro_serialize, (void *, size_t));
+    LOAD(bool, retro_unserialize, (const void *, size_t));
+    LOAD(void *, retro_get_memory_data, (unsigned));
+    LOAD(size_t, retro_get_memory_size, (unsigned));
+
+    CHECK(retro_api_version() == RETRO_API_VERSION);
+    struct retro_system_info info;
+    retro_get_system_info(&info);
+    CHECK(strcmp(info.library_name, "rust8") == 0);
+    CHECK(!info.need_fullpath);
+
+    retro_set_environment(environment);
+    retro_set_video_refresh(video);
+    retro_set_audio_sample(audio_sample);
+    retro_set_audio_sample_batch(audio_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+    CHECK(options_set == 4);
+
+    struct retro_game_info game = { argv[2], NULL, 0, NULL };
+    game.data = read_file(argv[2], &game.size);
+    CHECK(retro_load_game(&game));
+    CHECK(pixel_format == RETRO_PIXEL_FORMAT_XRGB8888);
+
+    struct retro_system_av_info av;
+    retro_get_system_av_info(&av);
+    CHECK(av.geometry.base_width == 64 && av.geometry.base_height == 32);
+    CHECK(av.timing.fps == 60.0);
+
+    uint8_t *ram = retro_get_memory_data(RETRO_MEMORY_SYSTEM_RAM);
+    CHECK(ram != NULL && retro_get_memory_size(RETRO_MEMORY_SYSTEM_RAM) == 4096);
+    CHECK(ram && memcmp(ram + 0x200, game.data, game.size) == 0);
+
+    for (int i = 0; i < 60; i++)
+        retro_run();
+    CHECK(frames == 60 && polls == 60);
+    CHECK(audio_frames == 60 * 44100 / 60);
+    CHECK(lit_pixels() > 0);
+
+    /* Running on from a save state gives the same screen again */
+    size_t size = retro_serialize_size();
+    void *state = malloc(size);
+    CHECK(retro_serialize(state, size));
+    for (int i = 0; i < 30; i++)
+        retro_run();
+    uint32_t expected[64 * 32];
+    memcpy(expected, screen, sizeof(screen));
+    CHECK(retro_unserialize(state, size));
+    for (int i = 0; i < 30; i++)
+        retro_run();
+    CHECK(memcmp(expected, screen, sizeof(screen)) == 0);
+    CHECK(!retro_unserialize(state, size - 1));
+
+    retro_reset();
+    retro_run();
+    retro_unload_game();
+    retro_deinit();
+    free(state);
+    free((void *)game.data);
+
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,57 @@
+// Builds tests/harness.c, a minimal libretro frontend, and runs the core
+// through it. Needs a C compiler; without one the test is skipped.
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// The core cargo built alongside this test
+fn core() -> PathBuf {
+    let deps = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let name = format!(
+        "{}rust8_libretro{}",
+        env::consts::DLL_PREFIX,
+        env::consts::DLL_SUFFIX
+    );
+    let path = deps.join(&name);
+    if path.exists() {
+        path
+    } else {
+        deps.parent().unwrap().join(&name)
+    }
+}
+
+#[test]
+fn test_c_frontend() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let harness = env::temp_dir().join(format!("rust8-libretro-harness-{}", std::process::id()));
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let built = Command::new(&cc)
+        .arg(manifest.join("tests/harness.c"))
+        .arg("-o")
+        .arg(&harness)
+        .arg("-ldl")
+        .status();
+    match built {
+        Ok(status) => assert!(status.success(), "Failed to build the harness"),
+        Err(e) => {
+            println!("Skipping, no C compiler ({}): {}", cc, e);
+            return;
+        }
+    }
+
+    let rom = manifest.join("../tests/conformance/font.ch8");
+    let output = Command::new(&harness)
+        .arg(core())
+        .arg(&rom)
+        .output()
+        .unwrap();
+    let _ = std::fs::remove_file(&harness);
+    assert!(
+        output.status.success(),
+        "Harness failed:\n{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "ok\n");
+}
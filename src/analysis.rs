@@ -0,0 +1,476 @@
+// Static analysis of a ROM. Chip-8 programs mix code and data freely, so a
+// straight disassembly of the file turns sprites into nonsense
+// instructions. Instead this follows the code from 0x200 through jumps,
+// calls, skips and returns to find what can run, splits it into basic
+// blocks, and tracks I through them to find the bytes DXYN draws, which
+// are marked as sprite data. Jumps through BNNN can go anywhere, so their
+// blocks are flagged as indirect and followed no further.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use disasm::{self, Instruction};
+
+// Where ROMs are loaded
+pub const ORIGIN: u16 = 0x200;
+
+// What each byte of a ROM is used for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteKind {
+    // Not reached, which is usually data other than sprites
+    Unknown,
+    Code,
+    Sprite,
+}
+
+// How control gets from one block to another
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Edge {
+    // On to the next instruction, including after a call returns or a skip
+    // isn't taken
+    Next,
+    Jump,
+    Call,
+    // The instruction skipped over by a taken skip
+    Skip,
+}
+
+// A straight run of instructions, only entered at the top and only left
+// at the bottom
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: u16,
+    // The address just past the last instruction
+    pub end: u16,
+    pub edges: Vec<(Edge, u16)>,
+    // Ends in a BNNN computed jump, whose targets aren't known
+    pub indirect: bool,
+}
+
+// I as far as the analysis knows it on the way into a block
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Index {
+    Known(u16),
+    Varies,
+}
+
+impl Index {
+    fn merge(self, other: Index) -> Index {
+        if self == other {
+            self
+        } else {
+            Index::Varies
+        }
+    }
+}
+
+pub struct Analysis {
+    rom: Vec<u8>,
+    map: Vec<ByteKind>,
+    // The address of every instruction that can run
+    instructions: BTreeSet<u16>,
+    blocks: BTreeMap<u16, Block>,
+    // Where sprites drawn from the ROM start
+    sprites: BTreeSet<u16>,
+}
+
+// Where control can go after the instruction at address
+fn successors(address: u16, instruction: Instruction) -> Vec<(Edge, u16)> {
+    let next = address.wrapping_add(2);
+    match instruction {
+        Instruction::Jp(nnn) => vec![(Edge::Jump, nnn)],
+        Instruction::Call(nnn) => vec![(Edge::Call, nnn), (Edge::Next, next)],
+        Instruction::SeByte(..)
+        | Instruction::SneByte(..)
+        | Instruction::SeReg(..)
+        | Instruction::SneReg(..)
+        | Instruction::Skp(_)
+        | Instruction::Sknp(_) => vec![(Edge::Next, next), (Edge::Skip, next.wrapping_add(2))],
+        // Unknown opcodes fault, and SYS called machine code that an
+        // emulator can't run
+        Instruction::Ret | Instruction::JpV0(_) | Instruction::Sys(_) | Instruction::Unknown(_) => {
+            Vec::new()
+        }
+        _ => vec![(Edge::Next, next)],
+    }
+}
+
+pub fn analyze(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis {
+        rom: rom.to_vec(),
+        map: vec![ByteKind::Unknown; rom.len()],
+        instructions: BTreeSet::new(),
+        blocks: BTreeMap::new(),
+        sprites: BTreeSet::new(),
+    };
+
+    // Find every instruction that can run, and the ones blocks start at
+    let mut leaders = BTreeSet::new();
+    leaders.insert(ORIGIN);
+    let mut work = vec![ORIGIN];
+    while let Some(address) = work.pop() {
+        let instruction = match analysis.instruction(address) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        if !analysis.instructions.insert(address) {
+            continue;
+        }
+        let edges = successors(address, instruction);
+        let straight = edges == [(Edge::Next, address.wrapping_add(2))];
+        for &(_, target) in &edges {
+            if !straight {
+                leaders.insert(target);
+            }
+            work.push(target);
+        }
+    }
+    for &address in &analysis.instructions {
+        let offset = (address - ORIGIN) as usize;
+        analysis.map[offset] = ByteKind::Code;
+        analysis.map[offset + 1] = ByteKind::Code;
+    }
+
+    // Split the code into blocks at the leaders and after anything that
+    // isn't straight line code
+    for &start in &leaders {
+        if !analysis.instructions.contains(&start) {
+            continue;
+        }
+        let mut address = start;
+        let block = loop {
+            let instruction = analysis.instruction(address).unwrap();
+            let next = address.wrapping_add(2);
+            let edges = successors(address, instruction);
+            let last = edges != [(Edge::Next, next)]
+                || leaders.contains(&next)
+                || !analysis.instructions.contains(&next);
+            if last {
+                break Block {
+                    start,
+                    end: next,
+                    indirect: matches!(instruction, Instruction::JpV0(_)),
+                    edges,
+                };
+            }
+            address = next;
+        };
+        analysis.blocks.insert(start, block);
+    }
+
+    analysis.find_sprites();
+    analysis
+}
+
+impl Analysis {
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // The decoded instruction at address, if both its bytes are in the ROM
+    pub fn instruction(&self, address: u16) -> Option<Instruction> {
+        let offset = address.checked_sub(ORIGIN)? as usize;
+        let bytes = self.rom.get(offset..offset + 2)?;
+        Some(disasm::decode((bytes[0] as u16) << 8 | bytes[1] as u16))
+    }
+
+    pub fn kind(&self, address: u16) -> ByteKind {
+        address
+            .checked_sub(ORIGIN)
+            .and_then(|offset| self.map.get(offset as usize))
+            .cloned()
+            .unwrap_or(ByteKind::Unknown)
+    }
+
+    // Whether an instruction that can run starts at address
+    pub fn is_instruction(&self, address: u16) -> bool {
+        self.instructions.contains(&address)
+    }
+
+    pub fn is_sprite(&self, address: u16) -> bool {
+        self.sprites.contains(&address)
+    }
+
+    pub fn blocks(&self) -> impl Iterator<Item = &Block> {
+        self.blocks.values()
+    }
+
+    pub fn block(&self, start: u16) -> Option<&Block> {
+        self.blocks.get(&start)
+    }
+
+    // Whether any block reaches address through the given kind of edge
+    pub fn is_target(&self, address: u16, edge: Edge) -> bool {
+        self.blocks()
+            .any(|block| block.edges.contains(&(edge, address)))
+    }
+
+    // The instructions of a block with their addresses
+    fn block_instructions<'a>(
+        &'a self,
+        block: &Block,
+    ) -> impl Iterator<Item = (u16, Instruction)> + 'a {
+        (block.start..block.end)
+            .step_by(2)
+            .map(move |address| (address, self.instruction(address).unwrap()))
+    }
+
+    // Works out I on the way into each block, then marks what every DRW
+    // with a known I draws as sprite data
+    fn find_sprites(&mut self) {
+        let mut entry: BTreeMap<u16, Index> = BTreeMap::new();
+        // I starts at 0 on a fresh machine
+        entry.insert(ORIGIN, Index::Known(0));
+        let mut work = vec![ORIGIN];
+        while let Some(start) = work.pop() {
+            let block = match self.blocks.get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            let mut index = entry[&start];
+            for (_, instruction) in self.block_instructions(block) {
+                index = step(index, instruction, |_, _| {});
+            }
+            // The subroutine may have changed I by the time it returns
+            let clobbered = block
+                .edges
+                .iter()
+                .any(|&(edge, target)| edge == Edge::Call && self.changes_index(target));
+            for &(edge, target) in &block.edges {
+                let out = if clobbered && edge == Edge::Next {
+                    Index::Varies
+                } else {
+                    index
+                };
+                let merged = entry.get(&target).map_or(out, |&old| old.merge(out));
+                if entry.get(&target) != Some(&merged) {
+                    entry.insert(target, merged);
+                    work.push(target);
+                }
+            }
+        }
+
+        let mut drawn = Vec::new();
+        for block in self.blocks.values() {
+            let mut index = entry.get(&block.start).cloned().unwrap_or(Index::Varies);
+            for (_, instruction) in self.block_instructions(block) {
+                index = step(index, instruction, |address, rows| {
+                    drawn.push((address, rows))
+                });
+            }
+        }
+        for (address, rows) in drawn {
+            let mut marked = false;
+            for offset in 0..rows {
+                let offset = address as usize + offset;
+                if let Some(offset) = offset.checked_sub(ORIGIN as usize) {
+                    // Code drawn as a sprite is still code
+                    if self.map.get(offset) == Some(&ByteKind::Unknown) {
+                        self.map[offset] = ByteKind::Sprite;
+                        marked = true;
+                    }
+                }
+            }
+            if marked {
+                self.sprites.insert(address);
+            }
+        }
+    }
+
+    // Whether anything the code from start reaches, until it returns, can
+    // change I
+    fn changes_index(&self, start: u16) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut work = vec![start];
+        while let Some(start) = work.pop() {
+            if !seen.insert(start) {
+                continue;
+            }
+            let block = match self.blocks.get(&start) {
+                Some(block) => block,
+                None => continue,
+            };
+            let changes = self
+                .block_instructions(block)
+                .any(|(_, instruction)| sets_index(instruction));
+            if changes || block.indirect {
+                return true;
+            }
+            work.extend(block.edges.iter().map(|&(_, target)| target));
+        }
+        false
+    }
+
+    // The control-flow graph in Graphviz's DOT language, one box per block
+    // listing its instructions, e.g. for "dot -Tsvg"
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph rom {\n");
+        dot.push_str("    node [shape=box, fontname=monospace];\n");
+        for block in self.blocks() {
+            let mut label = String::new();
+            for (address, instruction) in self.block_instructions(block) {
+                write!(label, "{:03X}: {}\\l", address, instruction).unwrap();
+            }
+            let style = if block.indirect { ", color=red" } else { "" };
+            writeln!(
+                dot,
+                "    \"{:03X}\" [label=\"{}\"{}];",
+                block.start, label, style
+            )
+            .unwrap();
+        }
+        for block in self.blocks() {
+            for &(edge, target) in &block.edges {
+                let attributes = match edge {
+                    Edge::Next => "",
+                    Edge::Jump => " [label=jump]",
+                    Edge::Call => " [label=call, style=dashed]",
+                    Edge::Skip => " [label=skip]",
+                };
+                writeln!(
+                    dot,
+                    "    \"{:03X}\" -> \"{:03X}\"{};",
+                    block.start, target, attributes
+                )
+                .unwrap();
+            }
+            if block.indirect {
+                writeln!(
+                    dot,
+                    "    \"{:03X}\" -> \"?\" [label=\"V0 +\", color=red];",
+                    block.start
+                )
+                .unwrap();
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn sets_index(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::LdI(_)
+            | Instruction::AddI(_)
+            | Instruction::LdF(_)
+            | Instruction::LdIVx(_)
+            | Instruction::LdVxI(_)
+    )
+}
+
+// I after an instruction, calling draw with the address and rows of any
+// sprite drawn from a known I
+fn step<F: FnMut(u16, usize)>(index: Index, instruction: Instruction, mut draw: F) -> Index {
+    match instruction {
+        Instruction::LdI(nnn) => Index::Known(nnn),
+        // Which font character or how far I moves depends on a register, and
+        // the increment-i quirk moves I on stores and loads
+        _ if sets_index(instruction) => Index::Varies,
+        Instruction::Drw(_, _, n) => {
+            if let Index::Known(address) = index {
+                draw(address, n as usize);
+            }
+            index
+        }
+        _ => index,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws a sprite from a subroutine, skips over a jump, and ends with a
+    // computed jump. Sprite data and an unreachable byte follow the code.
+    const ROM: [u8; 26] = [
+        0xA2, 0x14, // 200: LD I, 0x214
+        0x22, 0x0C, // 202: CALL 0x20C
+        0x30, 0x00, // 204: SE V0, 0
+        0x12, 0x02, // 206: JP 0x202
+        0xB2, 0x00, // 208: JP V0, 0x200
+        0x00, 0x00, // 20A: unreachable
+        0xD0, 0x13, // 20C: DRW V0, V1, 3
+        0x00, 0xEE, // 20E: RET
+        0x00, 0x00, // 210: unreachable
+        0x00, 0x00, // 212: unreachable
+        0x3C, 0x42, // 214: sprite
+        0x3C, 0x12, // 216: sprite, then unreachable
+        0x34, 0x56, // 218: unreachable
+    ];
+
+    #[test]
+    fn test_code_and_data() {
+        let analysis = analyze(&ROM);
+        assert_eq!(analysis.kind(0x200), ByteKind::Code);
+        assert_eq!(analysis.kind(0x20F), ByteKind::Code);
+        assert_eq!(analysis.kind(0x20A), ByteKind::Unknown);
+        assert_eq!(analysis.kind(0x214), ByteKind::Sprite);
+        assert_eq!(analysis.kind(0x216), ByteKind::Sprite);
+        assert_eq!(analysis.kind(0x217), ByteKind::Unknown);
+        assert_eq!(analysis.kind(0x100), ByteKind::Unknown);
+        assert!(analysis.is_sprite(0x214));
+        assert!(analysis.is_instruction(0x208));
+        assert!(!analysis.is_instruction(0x20A));
+    }
+
+    #[test]
+    fn test_blocks() {
+        let analysis = analyze(&ROM);
+        let starts: Vec<u16> = analysis.blocks().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0x200, 0x202, 0x204, 0x206, 0x208, 0x20C]);
+        // 0x202 is jumped to, so it starts a block of its own
+        assert_eq!(
+            analysis.block(0x200).unwrap().edges,
+            vec![(Edge::Next, 0x202)]
+        );
+        assert_eq!(
+            analysis.block(0x202).unwrap().edges,
+            vec![(Edge::Call, 0x20C), (Edge::Next, 0x204)]
+        );
+        assert_eq!(
+            analysis.block(0x204).unwrap().edges,
+            vec![(Edge::Next, 0x206), (Edge::Skip, 0x208)]
+        );
+        assert!(analysis.block(0x208).unwrap().indirect);
+        assert!(analysis.block(0x20C).unwrap().edges.is_empty());
+        assert!(analysis.is_target(0x20C, Edge::Call));
+    }
+
+    #[test]
+    fn test_varying_index() {
+        // I is set differently on the two paths to the DRW, so neither is
+        // marked
+        let rom = [
+            0x30, 0x00, // 200: SE V0, 0
+            0xA2, 0x0A, // 202: LD I, 0x20A
+            0xD0, 0x11, // 204: DRW V0, V1, 1
+            0x12, 0x04, // 206: JP 0x204
+            0x00, 0x00, // 208
+            0xFF, 0xFF, // 20A
+        ];
+        let analysis = analyze(&rom);
+        assert_eq!(analysis.kind(0x20A), ByteKind::Unknown);
+
+        // Past the end of the ROM isn't followed
+        let analysis = analyze(&[0x60, 0x01]);
+        assert_eq!(analysis.blocks().count(), 1);
+        assert_eq!(
+            analysis.blocks().next().unwrap().edges,
+            vec![(Edge::Next, 0x202)]
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let dot = analyze(&ROM).to_dot();
+        assert!(dot.starts_with("digraph rom {\n"));
+        assert!(dot.contains("\"20C\" [label=\"20C: DRW V0, V1, 3\\l20E: RET\\l\"];"));
+        assert!(dot.contains("\"202\" -> \"20C\" [label=call, style=dashed];"));
+        assert!(dot.contains("\"200\" -> \"202\";"));
+        assert!(dot.contains("\"204\" -> \"208\" [label=skip];"));
+        assert!(dot.contains("\"208\" -> \"?\""));
+        assert!(dot.ends_with("}\n"));
+    }
+}
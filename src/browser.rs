@@ -0,0 +1,240 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::Image;
+use palette::Palette;
+use text;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+// A keyboard driven file list for picking a ROM from inside the window
+pub struct RomBrowser {
+    dir: PathBuf,
+    entries: Vec<Entry>,
+    selected: usize,
+}
+
+// What the caller should do after a key press in the browser
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    None,
+    Load(PathBuf),
+}
+
+impl RomBrowser {
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<RomBrowser> {
+        let mut browser = RomBrowser {
+            dir: PathBuf::new(),
+            entries: Vec::new(),
+            selected: 0,
+        };
+        browser.change_dir(dir.as_ref())?;
+        Ok(browser)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    // Lists a directory, subdirectories first, then files, both sorted by
+    // name. Hidden files are skipped.
+    fn change_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let dir = dir.canonicalize()?;
+        let mut dirs = Vec::new();
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let is_dir = entry.path().is_dir();
+            let entry = Entry {
+                name,
+                path: entry.path(),
+                is_dir,
+            };
+            if is_dir {
+                dirs.push(entry);
+            } else {
+                files.push(entry);
+            }
+        }
+        dirs.sort_by(|a, b| a.name.cmp(&b.name));
+        files.sort_by(|a, b| a.name.cmp(&b.name));
+
+        self.entries.clear();
+        if let Some(parent) = dir.parent() {
+            self.entries.push(Entry {
+                name: "..".to_string(),
+                path: parent.to_path_buf(),
+                is_dir: true,
+            });
+        }
+        self.entries.extend(dirs);
+        self.entries.extend(files);
+        self.dir = dir;
+        self.selected = 0;
+        Ok(())
+    }
+
+    // Moves the selection by the given number of entries, stopping at the
+    // ends of the list
+    pub fn move_selection(&mut self, amount: isize) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let last = self.entries.len() as isize - 1;
+        self.selected = (self.selected as isize + amount).clamp(0, last) as usize;
+    }
+
+    // Opens the selected entry. Directories are entered, files are handed
+    // back to be loaded.
+    pub fn activate(&mut self) -> io::Result<Action> {
+        let entry = match self.entries.get(self.selected) {
+            Some(entry) => entry.clone(),
+            None => return Ok(Action::None),
+        };
+        if entry.is_dir {
+            self.change_dir(&entry.path)?;
+            Ok(Action::None)
+        } else {
+            Ok(Action::Load(entry.path))
+        }
+    }
+
+    pub fn parent(&mut self) -> io::Result<()> {
+        match self.dir.parent().map(|p| p.to_path_buf()) {
+            Some(parent) => self.change_dir(&parent),
+            None => Ok(()),
+        }
+    }
+
+    // Draws the list, scrolled so the selection is visible, with the
+    // selected entry in inverted colours
+    pub fn render(&self, width: usize, height: usize, size: usize, palette: &Palette) -> Image {
+        let mut image = Image::new(width, height);
+        let background = palette.background();
+        let foreground = palette.foreground();
+        image.fill_rect(
+            0,
+            0,
+            width,
+            height,
+            [background[0], background[1], background[2], 0xFF],
+        );
+
+        let margin = size * 2;
+        let line_height = text::height(size) + size * 2;
+        let max_chars = (width - margin * 2) / (text::ADVANCE * size);
+        let title = format!("OPEN: {}", self.dir.display());
+        text::draw(
+            &mut image,
+            margin,
+            margin,
+            &clip(&title, max_chars),
+            size,
+            foreground,
+        );
+
+        let top = margin + line_height * 2;
+        let visible = ((height - top) / line_height).max(1);
+        let first = (self.selected + 1).saturating_sub(visible);
+        for (row, (i, entry)) in self
+            .entries
+            .iter()
+            .enumerate()
+            .skip(first)
+            .take(visible)
+            .enumerate()
+        {
+            let y = top + row * line_height;
+            let label = if entry.is_dir {
+                format!("{}/", entry.name)
+            } else {
+                entry.name.clone()
+            };
+            let label = clip(&label, max_chars);
+            let colour = if i == self.selected {
+                image.fill_rect(
+                    margin - size,
+                    y - size,
+                    text::width(&label, size) + size * 2,
+                    text::height(size) + size * 2,
+                    [foreground[0], foreground[1], foreground[2], 0xFF],
+                );
+                background
+            } else {
+                foreground
+            };
+            text::draw(&mut image, margin, y, &label, size, colour);
+        }
+        image
+    }
+}
+
+// Shortens text to fit in max_chars, marking the cut with ".."
+fn clip(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+    let keep = max_chars.saturating_sub(2);
+    let mut clipped: String = text.chars().take(keep).collect();
+    clipped.push_str("..");
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::File;
+
+    #[test]
+    fn test_browse() {
+        let dir = env::temp_dir().join(format!("rust8-browser-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("games")).unwrap();
+        File::create(dir.join("b.ch8")).unwrap();
+        File::create(dir.join("a.ch8")).unwrap();
+        File::create(dir.join(".hidden")).unwrap();
+
+        let mut browser = RomBrowser::open(&dir).unwrap();
+        let names: Vec<&str> = browser.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["..", "games", "a.ch8", "b.ch8"]);
+
+        browser.move_selection(2);
+        assert_eq!(
+            browser.activate().unwrap(),
+            Action::Load(dir.canonicalize().unwrap().join("a.ch8"))
+        );
+        browser.move_selection(10);
+        assert_eq!(browser.selected(), 3);
+        browser.move_selection(-2);
+        assert_eq!(browser.activate().unwrap(), Action::None);
+        assert_eq!(browser.dir(), dir.join("games").canonicalize().unwrap());
+        browser.parent().unwrap();
+        assert_eq!(browser.dir(), dir.canonicalize().unwrap());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_clip() {
+        assert_eq!(clip("short", 10), "short");
+        assert_eq!(clip("a very long name", 8), "a very..");
+    }
+}
@@ -0,0 +1,171 @@
+// The chip-8's 4K of memory, seen through a bus so accesses made by the
+// interpreter can be watched or changed. Loading ROMs and the font, and
+// hashing the machine state, go straight to the memory behind the bus.
+
+pub const MEMORY_SIZE: usize = 4096;
+
+pub trait Bus {
+    // Data reads, by DXYN, FX65 and the like
+    fn read(&mut self, address: u16) -> u8;
+    fn write(&mut self, address: u16, value: u8);
+    // Reads of instruction bytes at the PC, kept apart from data reads so
+    // hooks can tell code from data
+    fn fetch(&mut self, address: u16) -> u8 {
+        self.read(address)
+    }
+    fn memory(&self) -> &[u8; MEMORY_SIZE];
+    fn memory_mut(&mut self) -> &mut [u8; MEMORY_SIZE];
+}
+
+// Plain memory, the default. Every access compiles down to an array index.
+#[derive(Clone)]
+pub struct Ram {
+    memory: [u8; MEMORY_SIZE],
+}
+
+impl Default for Ram {
+    fn default() -> Ram {
+        Ram::new()
+    }
+}
+
+impl Ram {
+    pub fn new() -> Ram {
+        Ram {
+            memory: [0; MEMORY_SIZE],
+        }
+    }
+}
+
+impl Bus for Ram {
+    #[inline]
+    fn read(&mut self, address: u16) -> u8 {
+        self.memory[address as usize]
+    }
+
+    #[inline]
+    fn write(&mut self, address: u16, value: u8) {
+        self.memory[address as usize] = value;
+    }
+
+    #[inline]
+    fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    #[inline]
+    fn memory_mut(&mut self) -> &mut [u8; MEMORY_SIZE] {
+        &mut self.memory
+    }
+}
+
+// Callbacks for a Hooked bus. Each one is told about an access as it
+// happens and returns the value to use, so as well as watching memory a hook
+// can change what is read, or refuse or replace a write. The defaults let
+// everything through unchanged.
+pub trait Hooks {
+    fn read(&mut self, _address: u16, value: u8) -> u8 {
+        value
+    }
+
+    // Called with the value in memory and the value being written
+    fn write(&mut self, _address: u16, _old: u8, new: u8) -> u8 {
+        new
+    }
+
+    fn fetch(&mut self, _address: u16, value: u8) -> u8 {
+        value
+    }
+}
+
+// RAM with hooks on every access the interpreter makes
+pub struct Hooked<H: Hooks> {
+    ram: Ram,
+    pub hooks: H,
+}
+
+impl<H: Hooks> Hooked<H> {
+    pub fn new(hooks: H) -> Hooked<H> {
+        Hooked {
+            ram: Ram::new(),
+            hooks,
+        }
+    }
+}
+
+impl<H: Hooks> Bus for Hooked<H> {
+    fn read(&mut self, address: u16) -> u8 {
+        let value = self.ram.read(address);
+        self.hooks.read(address, value)
+    }
+
+    fn write(&mut self, address: u16, value: u8) {
+        let old = self.ram.read(address);
+        let value = self.hooks.write(address, old, value);
+        self.ram.write(address, value);
+    }
+
+    fn fetch(&mut self, address: u16) -> u8 {
+        let value = self.ram.fetch(address);
+        self.hooks.fetch(address, value)
+    }
+
+    fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        self.ram.memory()
+    }
+
+    fn memory_mut(&mut self) -> &mut [u8; MEMORY_SIZE] {
+        self.ram.memory_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Chip8;
+
+    // Counts accesses and write protects the ROM
+    #[derive(Default)]
+    struct Watch {
+        reads: usize,
+        fetches: usize,
+        blocked: Vec<u16>,
+    }
+
+    impl Hooks for Watch {
+        fn read(&mut self, _address: u16, value: u8) -> u8 {
+            self.reads += 1;
+            value
+        }
+
+        fn write(&mut self, address: u16, old: u8, new: u8) -> u8 {
+            if address >= 0x200 {
+                self.blocked.push(address);
+                return old;
+            }
+            new
+        }
+
+        fn fetch(&mut self, _address: u16, value: u8) -> u8 {
+            self.fetches += 1;
+            value
+        }
+    }
+
+    #[test]
+    fn test_hooks() {
+        // LD I, 0x1FF; LD V1, 0xAB; LD [I], V1 (V0 to 0x1FF, V1 to 0x200);
+        // LD V1, [I]
+        let rom = [0xA1, 0xFF, 0x61, 0xAB, 0xF1, 0x55, 0xF1, 0x65];
+        let mut chip8 = Chip8::with_bus(Hooked::new(Watch::default()));
+        chip8.load_rom(&rom).unwrap();
+        for _ in 0..4 {
+            chip8.cycle().unwrap();
+        }
+        let watch = &chip8.bus().hooks;
+        assert_eq!(watch.fetches, 8);
+        assert_eq!(watch.reads, 2);
+        assert_eq!(watch.blocked, vec![0x200]);
+        assert_eq!(chip8.bus().memory()[0x200], 0xA1);
+    }
+}
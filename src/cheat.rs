@@ -0,0 +1,350 @@
+// Cheats: searching memory and registers for the values a game keeps its
+// lives, score or timers in, and freezing them at a value of our choosing.
+//
+// Cheat files are in the config format with one section per game, named by
+// the SHA-1 of its ROM like the ROM database. Each setting is a cheat, named
+// by its key, with the location to freeze and the value to keep there in
+// hex. A cheat ending in "off" is read but not applied until turned on.
+//
+//     [0123456789abcdef0123456789abcdef01234567]
+//     Infinite lives = 3A0 03
+//     Slow enemies = VE 20 off
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use bus::{Bus, MEMORY_SIZE};
+use config;
+use sha1::Digest;
+use Chip8;
+
+// A byte a cheat can watch or change
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Target {
+    Memory(u16),
+    Register(u8),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Memory(address) => write!(f, "{:03X}", address),
+            Target::Register(x) => write!(f, "V{:X}", x),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Target, String> {
+        if let Some(x) = s.strip_prefix('V').or_else(|| s.strip_prefix('v')) {
+            return match u8::from_str_radix(x, 16) {
+                Ok(x) if x <= 0xF => Ok(Target::Register(x)),
+                _ => Err(format!("Invalid register: {}", s)),
+            };
+        }
+        match parse_hex(s).and_then(|address| u16::try_from(address).ok()) {
+            Some(address) if (address as usize) < MEMORY_SIZE => Ok(Target::Memory(address)),
+            _ => Err(format!("Invalid address: {}", s)),
+        }
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    let digits = s.strip_prefix("0x").unwrap_or(s);
+    u32::from_str_radix(digits, 16).ok()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub name: String,
+    pub target: Target,
+    pub value: u8,
+    pub enabled: bool,
+}
+
+impl Cheat {
+    // Reads a cheat from its name and a "TARGET VALUE [off]" setting
+    pub fn parse(name: &str, setting: &str) -> Result<Cheat, String> {
+        let parts: Vec<&str> = setting.split_whitespace().collect();
+        let enabled = match parts.len() {
+            2 => true,
+            3 if parts[2] == "off" => false,
+            _ => return Err(format!("Expected TARGET VALUE [off]: {}", setting)),
+        };
+        let value = parse_hex(parts[1])
+            .and_then(|value| u8::try_from(value).ok())
+            .ok_or_else(|| format!("Invalid value: {}", parts[1]))?;
+        Ok(Cheat {
+            name: name.to_string(),
+            target: parts[0].parse()?,
+            value,
+            enabled,
+        })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {} {:02X}", self.name, self.target, self.value)?;
+        if !self.enabled {
+            write!(f, " off")?;
+        }
+        Ok(())
+    }
+}
+
+// The cheats for one game, which can be switched on and off as a whole
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CheatList {
+    pub cheats: Vec<Cheat>,
+    pub active: bool,
+}
+
+impl CheatList {
+    pub fn new(cheats: Vec<Cheat>) -> CheatList {
+        CheatList {
+            cheats,
+            active: true,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    // Writes every enabled cheat's value into the machine. Run before each
+    // frame so the game never sees anything else.
+    pub fn apply<B: Bus>(&self, chip8: &mut Chip8<B>) {
+        if !self.active {
+            return;
+        }
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            chip8.poke(cheat.target, cheat.value);
+        }
+    }
+}
+
+// Cheats for every game the user has written some for
+#[derive(Clone, Debug, Default)]
+pub struct CheatFile {
+    games: HashMap<Digest, Vec<Cheat>>,
+}
+
+impl CheatFile {
+    pub fn parse(text: &str) -> Result<CheatFile, String> {
+        let mut file = CheatFile::default();
+        for setting in config::parse(text)? {
+            let cheat = Cheat::parse(&setting.key, &setting.value)
+                .map_err(|e| format!("line {}: {}", setting.line, e))?;
+            file.games.entry(setting.game()?).or_default().push(cheat);
+        }
+        Ok(file)
+    }
+
+    // Adds the cheats of another file after those already read
+    pub fn merge(&mut self, other: CheatFile) {
+        for (hash, cheats) in other.games {
+            self.games.entry(hash).or_default().extend(cheats);
+        }
+    }
+
+    // Adds a cheat for a game after those it already has
+    pub fn add(&mut self, hash: Digest, cheat: Cheat) {
+        self.games.entry(hash).or_default().push(cheat);
+    }
+
+    // The game's cheats, empty if it has none
+    pub fn lookup(&self, hash: &Digest) -> CheatList {
+        CheatList::new(self.games.get(hash).cloned().unwrap_or_default())
+    }
+}
+
+// How a search narrows down its matches, comparing each byte with its value
+// when the search last looked
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    Equal(u8),
+    Changed,
+    Increased,
+    Decreased,
+}
+
+impl Filter {
+    fn keeps(self, before: u8, now: u8) -> bool {
+        match self {
+            Filter::Equal(value) => now == value,
+            Filter::Changed => now != before,
+            Filter::Increased => now > before,
+            Filter::Decreased => now < before,
+        }
+    }
+}
+
+// A RAM search: starts with every byte of memory and every register, and
+// each filter keeps only those whose value changed the way it asks
+#[derive(Clone, Debug)]
+pub struct Search {
+    // The bytes still matching and their values at the last filter
+    matches: Vec<(Target, u8)>,
+}
+
+impl Search {
+    pub fn new<B: Bus>(chip8: &Chip8<B>) -> Search {
+        let registers = (0..16).map(Target::Register);
+        let memory = (0..MEMORY_SIZE as u16).map(Target::Memory);
+        Search {
+            matches: registers
+                .chain(memory)
+                .map(|target| (target, chip8.peek(target)))
+                .collect(),
+        }
+    }
+
+    pub fn filter<B: Bus>(&mut self, chip8: &Chip8<B>, filter: Filter) {
+        self.matches
+            .retain(|&(target, before)| filter.keeps(before, chip8.peek(target)));
+        for found in self.matches.iter_mut() {
+            found.1 = chip8.peek(found.0);
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.matches.is_empty()
+    }
+
+    // The bytes still matching with their current values
+    pub fn matches(&self) -> &[(Target, u8)] {
+        &self.matches
+    }
+}
+
+impl<B: Bus> Chip8<B> {
+    pub fn peek(&self, target: Target) -> u8 {
+        match target {
+            Target::Memory(address) => self.bus.memory()[address as usize],
+            Target::Register(x) => self.registers[x as usize],
+        }
+    }
+
+    // Changes a byte behind the game's back, without going through the bus
+    pub fn poke(&mut self, target: Target, value: u8) {
+        match target {
+            Target::Memory(address) => {
+                if self.bus.memory()[address as usize] != value {
+                    self.bus_mut().memory_mut()[address as usize] = value;
+                }
+            }
+            Target::Register(x) => self.registers[x as usize] = value,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1;
+
+    // Counts up in V0 and keeps a copy at 0x300
+    const COUNTER: [u8; 8] = [0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00];
+
+    #[test]
+    fn test_parse_cheats() {
+        let file = CheatFile::parse(
+            "[0123456789ABCDEF0123456789abcdef01234567]
+            Infinite lives = 3A0 03
+            Slow enemies = ve 0x20 off
+            ",
+        )
+        .unwrap();
+        let hash = sha1::from_hex("0123456789abcdef0123456789abcdef01234567").unwrap();
+        let list = file.lookup(&hash);
+        assert!(list.active);
+        assert_eq!(
+            list.cheats[0],
+            Cheat {
+                name: "Infinite lives".to_string(),
+                target: Target::Memory(0x3A0),
+                value: 3,
+                enabled: true,
+            }
+        );
+        assert_eq!(list.cheats[1].to_string(), "Slow enemies = VE 20 off");
+        assert!(file.lookup(&[0; 20]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(CheatFile::parse("Lives = 3A0 03").is_err());
+        assert!(Cheat::parse("Lives", "3A0").is_err());
+        assert!(Cheat::parse("Lives", "1000 03").is_err());
+        assert!(Cheat::parse("Lives", "V10 03").is_err());
+        assert!(Cheat::parse("Lives", "3A0 100").is_err());
+        assert!(Cheat::parse("Lives", "3A0 03 of").is_err());
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&COUNTER).unwrap();
+        let mut list = CheatList::new(vec![
+            Cheat::parse("Counter", "V0 10").unwrap(),
+            Cheat::parse("Copy", "300 AA").unwrap(),
+        ]);
+        for _ in 0..3 {
+            list.apply(&mut chip8);
+            chip8.run_frame(4).unwrap();
+        }
+        // The counter is put back to 0x10 every frame and counts up once
+        assert_eq!(chip8.registers()[0], 0x11);
+        assert_eq!(chip8.bus().memory()[0x300], 0x11);
+
+        list.active = false;
+        list.apply(&mut chip8);
+        chip8.run_frame(4).unwrap();
+        assert_eq!(chip8.registers()[0], 0x12);
+    }
+
+    #[test]
+    fn test_add_cheat() {
+        // Added cheats are saved as they display, which has to read back
+        let cheat = Cheat::parse("Found V3", "V3 05").unwrap();
+        let hash = sha1::from_hex("0123456789abcdef0123456789abcdef01234567").unwrap();
+        let saved = format!("[{}]\n{}\n", sha1::to_hex(&hash), cheat);
+        let mut file = CheatFile::parse("").unwrap();
+        file.merge(CheatFile::parse(&saved).unwrap());
+        file.add(hash, cheat.clone());
+        assert_eq!(file.lookup(&hash).cheats, [cheat.clone(), cheat]);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&COUNTER).unwrap();
+        let mut search = Search::new(&chip8);
+        assert_eq!(search.len(), 16 + MEMORY_SIZE);
+
+        chip8.run_frame(4).unwrap();
+        search.filter(&chip8, Filter::Changed);
+        let targets: Vec<Target> = search.matches().iter().map(|m| m.0).collect();
+        assert_eq!(targets, [Target::Register(0), Target::Memory(0x300)]);
+
+        chip8.run_frame(4).unwrap();
+        search.filter(&chip8, Filter::Increased);
+        assert_eq!(search.len(), 2);
+        search.filter(&chip8, Filter::Equal(2));
+        assert_eq!(
+            search.matches(),
+            [(Target::Register(0), 2), (Target::Memory(0x300), 2)]
+        );
+        search.filter(&chip8, Filter::Decreased);
+        assert!(search.is_empty());
+    }
+}
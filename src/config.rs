@@ -0,0 +1,120 @@
+// Config files are plain text with one "key = value" setting per line.
+// Blank lines and lines starting with '#' are ignored. Keys are the same as
+// the long command line options without the leading "--", e.g.
+//
+//     # Amber screen with a custom background
+//     theme = amber
+//     background = #101010
+//
+// Settings can be grouped under "[name]" section headers, which the ROM
+// database uses to key settings by game.
+
+use sha1;
+use sha1::Digest;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Setting {
+    // Line number in the file, starting at 1, for error messages
+    pub line: usize,
+    // The section the setting is in, None before the first header
+    pub section: Option<String>,
+    pub key: String,
+    pub value: String,
+}
+
+impl Setting {
+    // The game the setting is for, in files like the ROM database whose
+    // sections are named by the SHA-1 of the ROM
+    pub fn game(&self) -> Result<Digest, String> {
+        let section = self
+            .section
+            .as_ref()
+            .ok_or_else(|| format!("line {}: setting outside of a game section", self.line))?;
+        sha1::from_hex(section).ok_or_else(|| {
+            format!(
+                "line {}: section is not a SHA-1 hash: {}",
+                self.line, section
+            )
+        })
+    }
+}
+
+pub fn parse(text: &str) -> Result<Vec<Setting>, String> {
+    let mut settings = Vec::new();
+    let mut section = None;
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            if !line.ends_with(']') || line.len() < 3 {
+                return Err(format!("line {}: invalid section header", i + 1));
+            }
+            section = Some(line[1..line.len() - 1].trim().to_string());
+            continue;
+        }
+        let mut parts = line.splitn(2, '=');
+        let key = parts.next().unwrap().trim();
+        let value = match parts.next() {
+            Some(value) => value.trim(),
+            None => return Err(format!("line {}: expected key = value", i + 1)),
+        };
+        if key.is_empty() {
+            return Err(format!("line {}: missing key", i + 1));
+        }
+        settings.push(Setting {
+            line: i + 1,
+            section: section.clone(),
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+    Ok(settings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = "# comment\n\ntheme = amber\n  background=#101010  \n";
+        let settings = parse(text).unwrap();
+        assert_eq!(settings.len(), 2);
+        assert_eq!(settings[0].line, 3);
+        assert_eq!(settings[0].key, "theme");
+        assert_eq!(settings[0].value, "amber");
+        assert_eq!(settings[1].key, "background");
+        assert_eq!(settings[1].value, "#101010");
+        assert_eq!(settings[1].section, None);
+    }
+
+    #[test]
+    fn test_parse_sections() {
+        let settings = parse("a = 1\n[game]\nb = 2\n").unwrap();
+        assert_eq!(settings[0].section, None);
+        assert_eq!(settings[1].section, Some("game".to_string()));
+        assert!(parse("[game\n").is_err());
+        assert!(parse("[]\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("theme amber").is_err());
+        assert!(parse(" = amber").is_err());
+    }
+
+    #[test]
+    fn test_game() {
+        let settings =
+            parse("a = 1\n[0123456789ABCDEF0123456789abcdef01234567]\nb = 2\n[pong]\nc = 3\n")
+                .unwrap();
+        assert!(settings[0].game().is_err());
+        assert_eq!(
+            settings[1].game().map(|hash| sha1::to_hex(&hash)),
+            Ok("0123456789abcdef0123456789abcdef01234567".to_string())
+        );
+        assert!(settings[2].game().is_err());
+    }
+}
@@ -0,0 +1,573 @@
+// Turns a ROM into structured pseudo-code in the style of Octo, the
+// chip-8 assembler most games are written in today. Code found by the
+// analysis is split into subroutines at each call target. Within each run
+// of code, a skip followed by a forward jump becomes an if block (with an
+// else when the block ends by jumping over what follows), an unconditional
+// jump backwards becomes a loop, and any other skip becomes a one line if.
+// Jumps that fit none of these are kept as labels and jumps, so the output
+// always means the same as the ROM. Registers are named after what they
+// are mostly used for, and sprites are drawn out in comments.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use analysis::{Analysis, ByteKind, Edge, ORIGIN};
+use disasm::Instruction;
+
+enum Stmt {
+    // A single instruction
+    Plain(u16),
+    // The instruction after a skip, run only when the condition holds
+    If(u16, String),
+    // The condition, the block run when it holds and optionally the one
+    // run when it doesn't
+    Block(u16, String, Vec<Stmt>, Option<Vec<Stmt>>),
+    // Runs forever, or until something jumps out of it
+    Loop(u16, Vec<Stmt>),
+}
+
+impl Stmt {
+    fn address(&self) -> u16 {
+        match *self {
+            Stmt::Plain(address) | Stmt::If(address, _) => address,
+            Stmt::Block(address, ..) | Stmt::Loop(address, _) => address,
+        }
+    }
+}
+
+// What registers are used for, most telling first, with the name given to
+// a register mostly used that way
+const ROLES: [&str; 9] = [
+    "x", "y", "key", "timer", "buzzer", "score", "digit", "offset", "random",
+];
+
+fn role(instruction: Instruction) -> Vec<(u8, usize)> {
+    match instruction {
+        Instruction::Drw(x, y, _) => vec![(x, 0), (y, 1)],
+        Instruction::Skp(x) | Instruction::Sknp(x) | Instruction::LdVxK(x) => vec![(x, 2)],
+        Instruction::LdVxDt(x) | Instruction::LdDtVx(x) => vec![(x, 3)],
+        Instruction::LdStVx(x) => vec![(x, 4)],
+        Instruction::LdB(x) => vec![(x, 5)],
+        Instruction::LdF(x) => vec![(x, 6)],
+        Instruction::AddI(x) => vec![(x, 7)],
+        Instruction::Rnd(x, _) => vec![(x, 8)],
+        _ => Vec::new(),
+    }
+}
+
+// The condition under which a skip instruction skips, if it is one
+fn skip_condition(instruction: Instruction) -> Option<(u8, &'static str, String)> {
+    match instruction {
+        Instruction::SeByte(x, kk) => Some((x, "==", format!("0x{:02X}", kk))),
+        Instruction::SneByte(x, kk) => Some((x, "!=", format!("0x{:02X}", kk))),
+        Instruction::SeReg(x, y) => Some((x, "==", format!("v{:X}", y))),
+        Instruction::SneReg(x, y) => Some((x, "!=", format!("v{:X}", y))),
+        Instruction::Skp(x) => Some((x, "key", String::new())),
+        Instruction::Sknp(x) => Some((x, "-key", String::new())),
+        _ => None,
+    }
+}
+
+fn negate(operator: &str) -> &'static str {
+    match operator {
+        "==" => "!=",
+        "!=" => "==",
+        "key" => "-key",
+        _ => "key",
+    }
+}
+
+struct Decompiler<'a> {
+    analysis: &'a Analysis,
+    names: [String; 16],
+    // Where subroutines start
+    subroutines: BTreeSet<u16>,
+    // Jumps rendered as part of an if or a loop, which need no label
+    absorbed: BTreeSet<u16>,
+}
+
+impl<'a> Decompiler<'a> {
+    fn new(analysis: &'a Analysis) -> Decompiler<'a> {
+        let mut subroutines: BTreeSet<u16> = analysis
+            .blocks()
+            .flat_map(|block| block.edges.iter())
+            .filter(|&&(edge, _)| edge == Edge::Call)
+            .map(|&(_, target)| target)
+            .collect();
+        subroutines.insert(ORIGIN);
+        Decompiler {
+            analysis,
+            names: register_names(analysis),
+            subroutines,
+            absorbed: BTreeSet::new(),
+        }
+    }
+
+    fn instruction(&self, address: u16) -> Instruction {
+        self.analysis.instruction(address).unwrap()
+    }
+
+    // Whether a jump or call from outside lands on address
+    fn entered(&self, address: u16) -> bool {
+        self.analysis.is_target(address, Edge::Jump) || self.analysis.is_target(address, Edge::Call)
+    }
+
+    fn condition(&self, x: u8, operator: &str, operand: &str) -> String {
+        let operand = match operand.strip_prefix('v') {
+            Some(y) => self.names[usize::from_str_radix(y, 16).unwrap()].clone(),
+            None => operand.to_string(),
+        };
+        if operand.is_empty() {
+            format!("{} {}", self.names[x as usize], operator)
+        } else {
+            format!("{} {} {}", self.names[x as usize], operator, operand)
+        }
+    }
+
+    // The last unconditional jump back to start before end, which closes a
+    // loop starting there
+    fn loop_end(&self, start: u16, end: u16) -> Option<u16> {
+        (start..end).step_by(2).rev().find(|&address| {
+            self.instruction(address) == Instruction::Jp(start)
+                && (address == start || skip_condition(self.instruction(address - 2)).is_none())
+        })
+    }
+
+    // Structures the straight run of code from start up to end
+    fn structure(&mut self, start: u16, end: u16) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        let mut address = start;
+        while address < end {
+            if let Some(jump) = self.loop_end(address, end) {
+                self.absorbed.insert(jump);
+                let body = self.structure(address, jump);
+                stmts.push(Stmt::Loop(address, body));
+                address = jump + 2;
+                continue;
+            }
+            let instruction = self.instruction(address);
+            let (x, operator, operand) = match skip_condition(instruction) {
+                Some(condition) if address + 2 < end => condition,
+                _ => {
+                    stmts.push(Stmt::Plain(address));
+                    address += 2;
+                    continue;
+                }
+            };
+            let next = address + 2;
+            let body = address + 4;
+            match self.instruction(next) {
+                // Skipping the jump runs what follows it. A jump into the
+                // middle of an instruction can't close a block.
+                Instruction::Jp(target)
+                    if target > body
+                        && target <= end
+                        && (target - body).is_multiple_of(2)
+                        && !self.entered(next) =>
+                {
+                    self.absorbed.insert(next);
+                    let condition = self.condition(x, operator, &operand);
+                    let last = target - 2;
+                    let otherwise = match self.instruction(last) {
+                        Instruction::Jp(after)
+                            if last >= body
+                                && after > target
+                                && after <= end
+                                && (after - target).is_multiple_of(2)
+                                && !self.entered(last)
+                                && (last == body
+                                    || skip_condition(self.instruction(last - 2)).is_none()) =>
+                        {
+                            Some(after)
+                        }
+                        _ => None,
+                    };
+                    match otherwise {
+                        Some(after) => {
+                            self.absorbed.insert(last);
+                            let then = self.structure(body, last);
+                            let otherwise = self.structure(target, after);
+                            stmts.push(Stmt::Block(address, condition, then, Some(otherwise)));
+                            address = after;
+                        }
+                        None => {
+                            let then = self.structure(body, target);
+                            stmts.push(Stmt::Block(address, condition, then, None));
+                            address = target;
+                        }
+                    }
+                }
+                // A skip over another skip, or over code jumped to from
+                // elsewhere, is left as it is
+                other if skip_condition(other).is_some() || self.entered(next) => {
+                    stmts.push(Stmt::Plain(address));
+                    address = next;
+                }
+                _ => {
+                    let condition = self.condition(x, negate(operator), &operand);
+                    stmts.push(Stmt::If(address, condition));
+                    address = body;
+                }
+            }
+        }
+        stmts
+    }
+
+    fn label(&self, address: u16) -> String {
+        if address == ORIGIN {
+            "main".to_string()
+        } else if self.subroutines.contains(&address) {
+            format!("sub_{:03X}", address)
+        } else if self.analysis.is_sprite(address) {
+            format!("sprite_{:03X}", address)
+        } else if self.analysis.is_instruction(address) {
+            format!("loc_{:03X}", address)
+        } else {
+            format!("data_{:03X}", address)
+        }
+    }
+
+    // Whether the output has a label at address
+    fn labelled(&self, address: u16) -> bool {
+        if self.subroutines.contains(&address) || self.analysis.is_sprite(address) {
+            return true;
+        }
+        if self.analysis.is_instruction(address) {
+            return self.analysis.blocks().any(|block| {
+                let jump = block.end - 2;
+                !self.absorbed.contains(&jump) && block.edges.contains(&(Edge::Jump, address))
+            });
+        }
+        self.is_loaded(address) && self.analysis.kind(address) == ByteKind::Unknown
+    }
+
+    // Whether some LD I points at address
+    fn is_loaded(&self, address: u16) -> bool {
+        self.analysis
+            .blocks()
+            .flat_map(|block| (block.start..block.end).step_by(2))
+            .any(|at| self.instruction(at) == Instruction::LdI(address))
+    }
+
+    fn target(&self, address: u16) -> String {
+        if self.labelled(address) {
+            self.label(address)
+        } else {
+            format!("0x{:03X}", address)
+        }
+    }
+
+    fn statement(&self, address: u16) -> String {
+        let n = |x: u8| self.names[x as usize].clone();
+        match self.instruction(address) {
+            Instruction::Cls => "clear".to_string(),
+            Instruction::Ret => "return".to_string(),
+            Instruction::Jp(nnn) => format!("jump {}", self.target(nnn)),
+            Instruction::Call(nnn) if self.labelled(nnn) => self.label(nnn),
+            Instruction::Call(nnn) => format!(":call 0x{:03X}", nnn),
+            Instruction::LdByte(x, kk) => format!("{} := 0x{:02X}", n(x), kk),
+            Instruction::AddByte(x, kk) => format!("{} += 0x{:02X}", n(x), kk),
+            Instruction::LdReg(x, y) => format!("{} := {}", n(x), n(y)),
+            Instruction::Or(x, y) => format!("{} |= {}", n(x), n(y)),
+            Instruction::And(x, y) => format!("{} &= {}", n(x), n(y)),
+            Instruction::Xor(x, y) => format!("{} ^= {}", n(x), n(y)),
+            Instruction::AddReg(x, y) => format!("{} += {}", n(x), n(y)),
+            Instruction::Sub(x, y) => format!("{} -= {}", n(x), n(y)),
+            Instruction::Shr(x, y) => format!("{} >>= {}", n(x), n(y)),
+            Instruction::Subn(x, y) => format!("{} =- {}", n(x), n(y)),
+            Instruction::Shl(x, y) => format!("{} <<= {}", n(x), n(y)),
+            Instruction::LdI(nnn) => format!("i := {}", self.target(nnn)),
+            Instruction::JpV0(nnn) => format!("jump0 0x{:03X}  # computed jump", nnn),
+            Instruction::Rnd(x, kk) => format!("{} := random 0x{:02X}", n(x), kk),
+            Instruction::Drw(x, y, rows) => format!("sprite {} {} {}", n(x), n(y), rows),
+            Instruction::LdVxDt(x) => format!("{} := delay", n(x)),
+            Instruction::LdVxK(x) => format!("{} := key", n(x)),
+            Instruction::LdDtVx(x) => format!("delay := {}", n(x)),
+            Instruction::LdStVx(x) => format!("buzzer := {}", n(x)),
+            Instruction::AddI(x) => format!("i += {}", n(x)),
+            Instruction::LdF(x) => format!("i := hex {}", n(x)),
+            Instruction::LdB(x) => format!("bcd {}", n(x)),
+            Instruction::LdIVx(x) => format!("save {}", n(x)),
+            Instruction::LdVxI(x) => format!("load {}", n(x)),
+            // Skips that couldn't be turned into ifs, SYS and unknown
+            // opcodes are written out as bytes
+            other => {
+                let offset = (address - ORIGIN) as usize;
+                let bytes = &self.analysis.rom()[offset..offset + 2];
+                format!("0x{:02X} 0x{:02X}  # {}", bytes[0], bytes[1], other)
+            }
+        }
+    }
+
+    fn print(&self, stmts: &[Stmt], depth: usize, text: &mut String) {
+        let indent = "\t".repeat(depth);
+        for stmt in stmts {
+            let address = stmt.address();
+            if self.labelled(address) && !self.subroutines.contains(&address) {
+                writeln!(text, ": {}", self.label(address)).unwrap();
+            }
+            match *stmt {
+                Stmt::Plain(address) => {
+                    writeln!(text, "{}{}", indent, self.statement(address)).unwrap()
+                }
+                Stmt::If(address, ref condition) => writeln!(
+                    text,
+                    "{}if {} then {}",
+                    indent,
+                    condition,
+                    self.statement(address + 2)
+                )
+                .unwrap(),
+                Stmt::Block(_, ref condition, ref then, ref otherwise) => {
+                    writeln!(text, "{}if {} begin", indent, condition).unwrap();
+                    self.print(then, depth + 1, text);
+                    if let Some(ref otherwise) = *otherwise {
+                        writeln!(text, "{}else", indent).unwrap();
+                        self.print(otherwise, depth + 1, text);
+                    }
+                    writeln!(text, "{}end", indent).unwrap();
+                }
+                Stmt::Loop(_, ref body) => {
+                    writeln!(text, "{}loop", indent).unwrap();
+                    self.print(body, depth + 1, text);
+                    writeln!(text, "{}again", indent).unwrap();
+                }
+            }
+        }
+    }
+}
+
+// Names each register after the use it is most often put to, numbering
+// them when more than one has the same use. VF is the flag register and
+// unused registers keep their number.
+fn register_names(analysis: &Analysis) -> [String; 16] {
+    let mut counts = [[0usize; ROLES.len()]; 16];
+    for block in analysis.blocks() {
+        for address in (block.start..block.end).step_by(2) {
+            for (register, role) in role(analysis.instruction(address).unwrap()) {
+                counts[register as usize][role] += 1;
+            }
+        }
+    }
+    let mut names: [String; 16] = Default::default();
+    let mut taken = [0; ROLES.len()];
+    for (register, name) in names.iter_mut().enumerate() {
+        let best = (0..ROLES.len())
+            .filter(|&role| counts[register][role] > 0)
+            .max_by_key(|&role| (counts[register][role], ROLES.len() - role));
+        *name = match best {
+            Some(role) if register != 0xF => {
+                taken[role] += 1;
+                match taken[role] {
+                    1 => ROLES[role].to_string(),
+                    n => format!("{}{}", ROLES[role], n),
+                }
+            }
+            _ => format!("v{:X}", register),
+        };
+    }
+    names
+}
+
+// Writes out a sprite a row to a line, each with its pixels drawn in a
+// comment
+fn sprite_rows(rom: &[u8], start: usize, end: usize, text: &mut String) {
+    for &byte in &rom[start..end] {
+        let pixels: String = (0..8)
+            .map(|bit| if byte << bit & 0x80 != 0 { '#' } else { '.' })
+            .collect();
+        writeln!(text, "\t0x{:02X}  # {}", byte, pixels).unwrap();
+    }
+}
+
+pub fn decompile(analysis: &Analysis) -> String {
+    let mut decompiler = Decompiler::new(analysis);
+    let rom = analysis.rom();
+    let end = ORIGIN as usize + rom.len();
+
+    // Structure every run of code first, so labels that are only jumped to
+    // by ifs and loops can be left out
+    let mut runs = Vec::new();
+    let mut address = ORIGIN as usize;
+    while address < end {
+        let start = address as u16;
+        if !analysis.is_instruction(start) {
+            address += 1;
+            continue;
+        }
+        // A run stops at the next subroutine, as each is printed with its
+        // own heading
+        let mut stop = start + 2;
+        while analysis.is_instruction(stop) && !decompiler.subroutines.contains(&stop) {
+            stop += 2;
+        }
+        let stmts = decompiler.structure(start, stop);
+        runs.push((start, stop, stmts));
+        address = stop as usize;
+    }
+
+    let mut text = String::new();
+    writeln!(text, "# Decompiled by rust8 from a {} byte ROM", rom.len()).unwrap();
+    for (register, name) in decompiler.names.iter().enumerate() {
+        if *name != format!("v{:X}", register) {
+            writeln!(text, ":alias {} v{:X}", name, register).unwrap();
+        }
+    }
+
+    let mut runs = runs.into_iter().peekable();
+    let mut address = ORIGIN as usize;
+    while address < end {
+        let here = address as u16;
+        if runs.peek().map(|run| run.0) == Some(here) {
+            let (start, stop, stmts) = runs.next().unwrap();
+            if decompiler.subroutines.contains(&start) {
+                writeln!(text, "\n: {}", decompiler.label(start)).unwrap();
+            } else {
+                text.push('\n');
+            }
+            decompiler.print(&stmts, 1, &mut text);
+            address = stop as usize;
+            continue;
+        }
+        // Data runs until the next code, sprite or labelled byte
+        let sprite = analysis.kind(here) == ByteKind::Sprite;
+        let mut stop = address + 1;
+        while stop < end {
+            let at = stop as u16;
+            if analysis.is_instruction(at)
+                || (analysis.kind(at) == ByteKind::Sprite) != sprite
+                || decompiler.labelled(at)
+            {
+                break;
+            }
+            stop += 1;
+        }
+        if decompiler.labelled(here) {
+            writeln!(text, "\n: {}", decompiler.label(here)).unwrap();
+        } else {
+            text.push('\n');
+        }
+        let offset = address - ORIGIN as usize;
+        if sprite {
+            sprite_rows(rom, offset, stop - ORIGIN as usize, &mut text);
+        } else {
+            let bytes: Vec<String> = rom[offset..stop - ORIGIN as usize]
+                .iter()
+                .map(|byte| format!("0x{:02X}", byte))
+                .collect();
+            for line in bytes.chunks(8) {
+                writeln!(text, "\t{}", line.join(" ")).unwrap();
+            }
+        }
+        address = stop;
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analysis;
+
+    fn decompiled(rom: &[u8]) -> String {
+        decompile(&analysis::analyze(rom))
+    }
+
+    #[test]
+    fn test_if_else_and_loop() {
+        let rom = [
+            0x60, 0x00, // 200: LD V0, 0
+            0x30, 0x05, // 202: SE V0, 5
+            0x12, 0x0A, // 204: JP 0x20A
+            0x61, 0x01, // 206: LD V1, 1
+            0x12, 0x0C, // 208: JP 0x20C
+            0x61, 0x02, // 20A: LD V1, 2
+            0x70, 0x01, // 20C: ADD V0, 1
+            0x12, 0x02, // 20E: JP 0x202
+        ];
+        assert_eq!(
+            decompiled(&rom),
+            "# Decompiled by rust8 from a 16 byte ROM\n\
+             \n\
+             : main\n\
+             \tv0 := 0x00\n\
+             \tloop\n\
+             \t\tif v0 == 0x05 begin\n\
+             \t\t\tv1 := 0x01\n\
+             \t\telse\n\
+             \t\t\tv1 := 0x02\n\
+             \t\tend\n\
+             \t\tv0 += 0x01\n\
+             \tagain\n"
+        );
+    }
+
+    #[test]
+    fn test_subroutines_and_sprites() {
+        let rom = [
+            0xA2, 0x0C, // 200: LD I, 0x20C
+            0xE3, 0x9E, // 202: SKP V3
+            0x22, 0x08, // 204: CALL 0x208
+            0x12, 0x06, // 206: JP 0x206
+            0xD1, 0x21, // 208: DRW V1, V2, 1
+            0x00, 0xEE, // 20A: RET
+            0x3C, // 20C: sprite
+            0x01, 0x02, // 20D: data
+        ];
+        assert_eq!(
+            decompiled(&rom),
+            "# Decompiled by rust8 from a 15 byte ROM\n\
+             :alias x v1\n\
+             :alias y v2\n\
+             :alias key v3\n\
+             \n\
+             : main\n\
+             \ti := sprite_20C\n\
+             \tif key -key then sub_208\n\
+             \tloop\n\
+             \tagain\n\
+             \n\
+             : sub_208\n\
+             \tsprite x y 1\n\
+             \treturn\n\
+             \n\
+             : sprite_20C\n\
+             \t0x3C  # ..####..\n\
+             \n\
+             \t0x01 0x02\n"
+        );
+    }
+
+    #[test]
+    fn test_unstructured_jumps() {
+        // The skip over a backward jump can't be a loop, so it stays a jump
+        // to a label
+        let rom = [
+            0x60, 0x00, // 200: LD V0, 0
+            0x70, 0x01, // 202: ADD V0, 1
+            0x30, 0x10, // 204: SE V0, 0x10
+            0x12, 0x02, // 206: JP 0x202
+            0x00, 0xE0, // 208: CLS
+            0x12, 0x08, // 20A: JP 0x208
+        ];
+        let text = decompiled(&rom);
+        assert!(text.contains(": loc_202\n\tv0 += 0x01\n\tif v0 != 0x10 then jump loc_202\n"));
+        assert!(text.contains("\tloop\n\t\tclear\n\tagain\n"));
+    }
+
+    #[test]
+    fn test_register_names() {
+        let analysis = analysis::analyze(&[
+            0xD0, 0x11, // DRW V0, V1, 1
+            0xD2, 0x31, // DRW V2, V3, 1
+            0xF0, 0x33, // LD B, V0
+            0xCF, 0x01, // RND VF, 1
+        ]);
+        let names = register_names(&analysis);
+        assert_eq!(names[0], "x");
+        assert_eq!(names[2], "x2");
+        assert_eq!(names[3], "y2");
+        assert_eq!(names[4], "v4");
+        assert_eq!(names[0xF], "vF");
+    }
+}
@@ -0,0 +1,150 @@
+use std::fmt;
+use std::fmt::Write;
+
+use analysis::{Analysis, ByteKind, Edge, ORIGIN};
+
+pub use instruction::{decode, Instruction};
+
+// Assembly in the style of Cowgod's reference, e.g. "LD V1, 0x2A"
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Sys(nnn) => write!(f, "SYS 0x{:03X}", nnn),
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jp(nnn) => write!(f, "JP 0x{:03X}", nnn),
+            Instruction::Call(nnn) => write!(f, "CALL 0x{:03X}", nnn),
+            Instruction::SeByte(x, kk) => write!(f, "SE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SneByte(x, kk) => write!(f, "SNE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SeReg(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LdByte(x, kk) => write!(f, "LD V{:X}, 0x{:02X}", x, kk),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{:X}, 0x{:02X}", x, kk),
+            Instruction::LdReg(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => write!(f, "LD I, 0x{:03X}", nnn),
+            Instruction::JpV0(nnn) => write!(f, "JP V0, 0x{:03X}", nnn),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{:X}, 0x{:02X}", x, kk),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => write!(f, "SKP V{:X}", x),
+            Instruction::Sknp(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LdVxDt(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LdF(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::LdB(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::LdIVx(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LdVxI(x) => write!(f, "LD V{:X}, [I]", x),
+            Instruction::Unknown(opcode) => write!(f, "DW 0x{:04X}", opcode),
+        }
+    }
+}
+
+// A listing of a ROM laid out by its analysis: instructions for the code
+// that can run, sprites a row of pixels at a time, and anything else as
+// bytes, with labels on the places that are called, jumped to or drawn.
+pub fn listing(analysis: &Analysis) -> String {
+    let rom = analysis.rom();
+    let end = ORIGIN as usize + rom.len();
+    let mut text = String::new();
+    let mut address = ORIGIN as usize;
+    while address < end {
+        let here = address as u16;
+        let byte = rom[address - ORIGIN as usize];
+        if analysis.is_target(here, Edge::Call) {
+            writeln!(text, "sub_{:03X}:", here).unwrap();
+        } else if analysis.is_target(here, Edge::Jump) || analysis.is_target(here, Edge::Skip) {
+            writeln!(text, "loc_{:03X}:", here).unwrap();
+        }
+        if analysis.is_sprite(here) {
+            writeln!(text, "sprite_{:03X}:", here).unwrap();
+        }
+
+        if analysis.is_instruction(here) {
+            let instruction = analysis.instruction(here).unwrap();
+            let next = rom[address + 1 - ORIGIN as usize];
+            write!(
+                text,
+                "{:03X}: {:02X}{:02X}  {}",
+                here, byte, next, instruction
+            )
+            .unwrap();
+            if let Instruction::JpV0(_) = instruction {
+                text.push_str("  ; computed jump");
+            }
+            text.push('\n');
+            address += 2;
+        } else if analysis.kind(here) == ByteKind::Sprite {
+            let pixels: String = (0..8)
+                .map(|bit| if byte << bit & 0x80 != 0 { '#' } else { '.' })
+                .collect();
+            writeln!(text, "{:03X}: {:02X}    {}", here, byte, pixels).unwrap();
+            address += 1;
+        } else {
+            // Up to 8 bytes of anything else to a line
+            write!(text, "{:03X}:", here).unwrap();
+            let start = address;
+            while address < end && address - start < 8 {
+                let at = address as u16;
+                if address > start
+                    && (analysis.is_instruction(at) || analysis.kind(at) == ByteKind::Sprite)
+                {
+                    break;
+                }
+                write!(text, " {:02X}", rom[address - ORIGIN as usize]).unwrap();
+                address += 1;
+            }
+            text.push('\n');
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use analysis;
+
+    #[test]
+    fn test_mnemonics() {
+        assert_eq!(decode(0x6A2B).to_string(), "LD VA, 0x2B");
+        assert_eq!(decode(0xA2F0).to_string(), "LD I, 0x2F0");
+        assert_eq!(decode(0xD01F).to_string(), "DRW V0, V1, 15");
+        assert_eq!(decode(0xFB65).to_string(), "LD VB, [I]");
+        assert_eq!(decode(0xFFFF).to_string(), "DW 0xFFFF");
+    }
+
+    #[test]
+    fn test_listing() {
+        let rom = [
+            0xA2, 0x08, // 200: LD I, 0x208
+            0xD0, 0x12, // 202: DRW V0, V1, 2
+            0x12, 0x04, // 204: JP 0x204
+            0xB3, 0x00, // 206: unreachable
+            0x81, 0x7E, // 208: sprite
+            0x01, 0x02, 0x03, // 20A: data
+        ];
+        let text = listing(&analysis::analyze(&rom));
+        assert_eq!(
+            text,
+            "200: A208  LD I, 0x208\n\
+             202: D012  DRW V0, V1, 2\n\
+             loc_204:\n\
+             204: 1204  JP 0x204\n\
+             206: B3 00\n\
+             sprite_208:\n\
+             208: 81    #......#\n\
+             209: 7E    .######.\n\
+             20A: 01 02 03\n"
+        );
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+
+use image::Image;
+use screen::Screen;
+
+pub use screen::{HEIGHT, WIDTH};
+
+// Filters that smooth over the flicker caused by games erasing and redrawing
+// sprites with XOR every frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Filter {
+    // Show exactly what is in the chip-8 framebuffer
+    #[default]
+    None,
+    // Lit pixels switch on instantly and fade out over fade_ms once cleared,
+    // like the phosphor of an old CRT
+    Phosphor {
+        fade_ms: u32,
+    },
+    // Average the last `frames` frames together
+    Blend {
+        frames: usize,
+    },
+    // A pixel is lit if it was lit in either of the last two frames
+    MaxOfTwo,
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Filter::None => write!(f, "none"),
+            Filter::Phosphor { fade_ms } => write!(f, "phosphor:{}", fade_ms),
+            Filter::Blend { frames } => write!(f, "blend:{}", frames),
+            Filter::MaxOfTwo => write!(f, "max2"),
+        }
+    }
+}
+
+// Parses filters of the form "none", "phosphor[:fade_ms]", "blend[:frames]"
+// and "max2"
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Filter, String> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let arg = parts.next();
+        let parse_arg = |default: u32| -> Result<u32, String> {
+            match arg {
+                Some(a) => a
+                    .parse::<u32>()
+                    .map_err(|_| format!("Invalid filter argument: {}", a)),
+                None => Ok(default),
+            }
+        };
+        match name {
+            "none" => Ok(Filter::None),
+            "phosphor" => Ok(Filter::Phosphor {
+                fade_ms: parse_arg(150)?,
+            }),
+            "blend" => {
+                let frames = parse_arg(3)?;
+                if frames == 0 {
+                    return Err("Blend filter needs at least one frame".to_string());
+                }
+                Ok(Filter::Blend {
+                    frames: frames as usize,
+                })
+            }
+            "max2" => Ok(Filter::MaxOfTwo),
+            _ => Err(format!("Unknown display filter: {}", s)),
+        }
+    }
+}
+
+// Converts the chip-8 screen into per-pixel intensities, applying the
+// selected filter, and from there into RGBA for a frontend to display.
+pub struct Framebuffer {
+    filter: Filter,
+    // 0.0 is background, 1.0 is a fully lit pixel
+    intensity: [[f32; WIDTH]; HEIGHT],
+    // Most recent frame at the front
+    history: VecDeque<Screen>,
+}
+
+impl Framebuffer {
+    pub fn new(filter: Filter) -> Framebuffer {
+        Framebuffer {
+            filter,
+            intensity: [[0.0; WIDTH]; HEIGHT],
+            history: VecDeque::new(),
+        }
+    }
+
+    pub fn filter(&self) -> Filter {
+        self.filter
+    }
+
+    // Switching filters drops any state the old filter built up
+    pub fn set_filter(&mut self, filter: Filter) {
+        self.filter = filter;
+        self.history.clear();
+    }
+
+    // Feed in the screen as it looks at the end of a frame. elapsed_ms is the
+    // time since the previous update and drives the phosphor decay.
+    pub fn update(&mut self, screen: &Screen, elapsed_ms: f32) {
+        let depth = match self.filter {
+            Filter::None | Filter::Phosphor { .. } => 1,
+            Filter::Blend { frames } => frames,
+            Filter::MaxOfTwo => 2,
+        };
+        self.history.push_front(*screen);
+        self.history.truncate(depth);
+
+        match self.filter {
+            Filter::None => {
+                for (y, row) in self.intensity.iter_mut().enumerate() {
+                    for (x, level) in row.iter_mut().enumerate() {
+                        *level = if screen.pixel(x, y) { 1.0 } else { 0.0 };
+                    }
+                }
+            }
+            Filter::Phosphor { fade_ms } => {
+                let decay = if fade_ms == 0 {
+                    1.0
+                } else {
+                    elapsed_ms / fade_ms as f32
+                };
+                for (y, row) in self.intensity.iter_mut().enumerate() {
+                    for (x, level) in row.iter_mut().enumerate() {
+                        *level = if screen.pixel(x, y) {
+                            1.0
+                        } else {
+                            (*level - decay).max(0.0)
+                        };
+                    }
+                }
+            }
+            Filter::Blend { .. } => {
+                let count = self.history.len() as f32;
+                for y in 0..HEIGHT {
+                    for x in 0..WIDTH {
+                        let lit = self
+                            .history
+                            .iter()
+                            .filter(|frame| frame.pixel(x, y))
+                            .count();
+                        self.intensity[y][x] = lit as f32 / count;
+                    }
+                }
+            }
+            Filter::MaxOfTwo => {
+                for y in 0..HEIGHT {
+                    for x in 0..WIDTH {
+                        let lit = self.history.iter().any(|frame| frame.pixel(x, y));
+                        self.intensity[y][x] = if lit { 1.0 } else { 0.0 };
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn intensity(&self, x: usize, y: usize) -> f32 {
+        self.intensity[y][x]
+    }
+
+    // Writes the filtered screen into buffer as RGBA bytes, one pixel per
+    // chip-8 pixel, mixing between the background and foreground colours
+    // by intensity. buffer must hold WIDTH * HEIGHT * 4 bytes.
+    pub fn to_rgba(&self, foreground: [u8; 3], background: [u8; 3], buffer: &mut [u8]) {
+        assert!(buffer.len() >= WIDTH * HEIGHT * 4);
+        for (y, row) in self.intensity.iter().enumerate() {
+            for (x, level) in row.iter().enumerate() {
+                let offset = (y * WIDTH + x) * 4;
+                for channel in 0..3 {
+                    buffer[offset + channel] =
+                        mix(background[channel], foreground[channel], *level);
+                }
+                buffer[offset + 3] = 0xFF;
+            }
+        }
+    }
+
+    // Same as to_rgba but into a new image
+    pub fn to_image(&self, foreground: [u8; 3], background: [u8; 3]) -> Image {
+        let mut image = Image::new(WIDTH, HEIGHT);
+        self.to_rgba(foreground, background, &mut image.pixels);
+        image
+    }
+}
+
+// Linear interpolation between two colour channels
+fn mix(from: u8, to: u8, amount: f32) -> u8 {
+    let value = from as f32 + (to as f32 - from as f32) * amount;
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen_with(x: usize, y: usize) -> Screen {
+        let mut screen = Screen::new();
+        screen.set(x, y, true);
+        screen
+    }
+
+    #[test]
+    fn test_parse_filter() {
+        assert_eq!("none".parse::<Filter>(), Ok(Filter::None));
+        assert_eq!(
+            "phosphor:200".parse::<Filter>(),
+            Ok(Filter::Phosphor { fade_ms: 200 })
+        );
+        assert_eq!("blend".parse::<Filter>(), Ok(Filter::Blend { frames: 3 }));
+        assert_eq!("max2".parse::<Filter>(), Ok(Filter::MaxOfTwo));
+        assert!("blend:0".parse::<Filter>().is_err());
+        assert!("sepia".parse::<Filter>().is_err());
+    }
+
+    #[test]
+    fn test_phosphor_decay() {
+        let mut fb = Framebuffer::new(Filter::Phosphor { fade_ms: 100 });
+        fb.update(&screen_with(3, 4), 25.0);
+        assert_eq!(fb.intensity(3, 4), 1.0);
+        fb.update(&Screen::new(), 25.0);
+        assert_eq!(fb.intensity(3, 4), 0.75);
+        for _ in 0..4 {
+            fb.update(&Screen::new(), 25.0);
+        }
+        assert_eq!(fb.intensity(3, 4), 0.0);
+    }
+
+    #[test]
+    fn test_blend_frames() {
+        let mut fb = Framebuffer::new(Filter::Blend { frames: 4 });
+        fb.update(&screen_with(0, 0), 16.0);
+        fb.update(&Screen::new(), 16.0);
+        fb.update(&Screen::new(), 16.0);
+        fb.update(&screen_with(0, 0), 16.0);
+        assert_eq!(fb.intensity(0, 0), 0.5);
+        fb.update(&Screen::new(), 16.0);
+        assert_eq!(fb.intensity(0, 0), 0.25);
+    }
+
+    #[test]
+    fn test_max_of_two() {
+        let mut fb = Framebuffer::new(Filter::MaxOfTwo);
+        fb.update(&screen_with(5, 5), 16.0);
+        fb.update(&Screen::new(), 16.0);
+        assert_eq!(fb.intensity(5, 5), 1.0);
+        fb.update(&Screen::new(), 16.0);
+        assert_eq!(fb.intensity(5, 5), 0.0);
+    }
+
+    #[test]
+    fn test_to_rgba() {
+        let mut fb = Framebuffer::new(Filter::None);
+        fb.update(&screen_with(1, 0), 16.0);
+        let mut buffer = vec![0; WIDTH * HEIGHT * 4];
+        fb.to_rgba([255, 255, 255], [0, 0, 0], &mut buffer);
+        assert_eq!(&buffer[0..4], &[0, 0, 0, 255]);
+        assert_eq!(&buffer[4..8], &[255, 255, 255, 255]);
+    }
+}
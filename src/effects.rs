@@ -0,0 +1,280 @@
+use std::fmt;
+use std::str::FromStr;
+
+use image::Image;
+
+// Retro CRT style post-processing, done on the CPU so no GPU is needed.
+// Every effect works on an image that has already been scaled up, and uses
+// the scale factor to find the edges of each chip-8 pixel.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Effects {
+    // How much to darken every other line, 0.0 to 1.0
+    pub scanlines: Option<f32>,
+    // How much to darken the outline of each chip-8 pixel, 0.0 to 1.0
+    pub grid: Option<f32>,
+    // How much light bleeds out around lit pixels
+    pub bloom: Option<f32>,
+    // Strength of the barrel distortion, 0.0 is flat
+    pub curvature: Option<f32>,
+}
+
+// A single effect with its setting, as given on the command line in the form
+// "name[:amount]"
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Effect {
+    Scanlines(f32),
+    Grid(f32),
+    Bloom(f32),
+    Curvature(f32),
+}
+
+impl fmt::Display for Effect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Effect::Scanlines(amount) => write!(f, "scanlines:{}", amount),
+            Effect::Grid(amount) => write!(f, "grid:{}", amount),
+            Effect::Bloom(amount) => write!(f, "bloom:{}", amount),
+            Effect::Curvature(amount) => write!(f, "curvature:{}", amount),
+        }
+    }
+}
+
+impl FromStr for Effect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Effect, String> {
+        let mut parts = s.splitn(2, ':');
+        let name = parts.next().unwrap_or("");
+        let amount = match parts.next() {
+            Some(a) => Some(
+                a.parse::<f32>()
+                    .map_err(|_| format!("Invalid effect amount: {}", a))?,
+            ),
+            None => None,
+        };
+        if let Some(a) = amount {
+            if !(0.0..=1.0).contains(&a) {
+                return Err(format!("Effect amount must be between 0 and 1: {}", s));
+            }
+        }
+        match name {
+            "scanlines" => Ok(Effect::Scanlines(amount.unwrap_or(0.4))),
+            "grid" => Ok(Effect::Grid(amount.unwrap_or(0.3))),
+            "bloom" => Ok(Effect::Bloom(amount.unwrap_or(0.5))),
+            "curvature" => Ok(Effect::Curvature(amount.unwrap_or(0.1))),
+            _ => Err(format!("Unknown effect: {}", s)),
+        }
+    }
+}
+
+impl Effects {
+    // Turns on the given effect, replacing any earlier setting for it
+    pub fn enable(&mut self, effect: Effect) {
+        match effect {
+            Effect::Scanlines(amount) => self.scanlines = Some(amount),
+            Effect::Grid(amount) => self.grid = Some(amount),
+            Effect::Bloom(amount) => self.bloom = Some(amount),
+            Effect::Curvature(amount) => self.curvature = Some(amount),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Effects::default()
+    }
+
+    // Applies all enabled effects to an image that was scaled up by scale
+    pub fn apply(&self, image: &mut Image, scale: usize) {
+        if let Some(amount) = self.grid {
+            grid(image, scale, amount);
+        }
+        if let Some(amount) = self.scanlines {
+            scanlines(image, scale, amount);
+        }
+        if let Some(amount) = self.bloom {
+            bloom(image, scale, amount);
+        }
+        if let Some(amount) = self.curvature {
+            curvature(image, amount);
+        }
+    }
+}
+
+fn darken(pixel: [u8; 4], amount: f32) -> [u8; 4] {
+    let keep = 1.0 - amount;
+    [
+        (pixel[0] as f32 * keep) as u8,
+        (pixel[1] as f32 * keep) as u8,
+        (pixel[2] as f32 * keep) as u8,
+        pixel[3],
+    ]
+}
+
+// Darkens every other line. With a small scale there is no room for a gap
+// inside each chip-8 pixel, so this only kicks in from a scale of 2.
+fn scanlines(image: &mut Image, scale: usize, amount: f32) {
+    if scale < 2 {
+        return;
+    }
+    for y in (1..image.height).step_by(2) {
+        for x in 0..image.width {
+            let pixel = image.get(x, y);
+            image.set(x, y, darken(pixel, amount));
+        }
+    }
+}
+
+// Darkens the right and bottom edge of every chip-8 pixel
+fn grid(image: &mut Image, scale: usize, amount: f32) {
+    if scale < 3 {
+        return;
+    }
+    for y in 0..image.height {
+        for x in 0..image.width {
+            if x % scale == scale - 1 || y % scale == scale - 1 {
+                let pixel = image.get(x, y);
+                image.set(x, y, darken(pixel, amount));
+            }
+        }
+    }
+}
+
+// Adds a blurred copy of the image on top of itself so lit pixels glow. The
+// blur is a separable box blur about half a chip-8 pixel wide.
+fn bloom(image: &mut Image, scale: usize, amount: f32) {
+    let radius = (scale / 2).max(1);
+    let horizontal = box_blur(image, radius, true);
+    let blurred = box_blur(&horizontal, radius, false);
+    for (out, glow) in image.pixels.chunks_mut(4).zip(blurred.pixels.chunks(4)) {
+        for channel in 0..3 {
+            let value = out[channel] as f32 + glow[channel] as f32 * amount;
+            out[channel] = value.min(255.0) as u8;
+        }
+    }
+}
+
+fn box_blur(image: &Image, radius: usize, horizontal: bool) -> Image {
+    let mut blurred = Image::new(image.width, image.height);
+    let (outer, inner) = if horizontal {
+        (image.height, image.width)
+    } else {
+        (image.width, image.height)
+    };
+    for o in 0..outer {
+        for i in 0..inner {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(inner);
+            let mut sum = [0u32; 3];
+            for j in start..end {
+                let pixel = if horizontal {
+                    image.get(j, o)
+                } else {
+                    image.get(o, j)
+                };
+                for channel in 0..3 {
+                    sum[channel] += pixel[channel] as u32;
+                }
+            }
+            let count = (end - start) as u32;
+            let pixel = [
+                (sum[0] / count) as u8,
+                (sum[1] / count) as u8,
+                (sum[2] / count) as u8,
+                0xFF,
+            ];
+            if horizontal {
+                blurred.set(i, o, pixel);
+            } else {
+                blurred.set(o, i, pixel);
+            }
+        }
+    }
+    blurred
+}
+
+// Barrel distortion, bending the picture as if it was on a curved tube.
+// Anything that falls outside the source image is black.
+fn curvature(image: &mut Image, amount: f32) {
+    let source = image.clone();
+    let half_width = image.width as f32 / 2.0;
+    let half_height = image.height as f32 / 2.0;
+    for y in 0..image.height {
+        for x in 0..image.width {
+            // Map to -1.0..1.0 with the origin in the middle of the image
+            let nx = (x as f32 + 0.5 - half_width) / half_width;
+            let ny = (y as f32 + 0.5 - half_height) / half_height;
+            let distortion = 1.0 + amount * (nx * nx + ny * ny);
+            let sx = nx * distortion * half_width + half_width;
+            let sy = ny * distortion * half_height + half_height;
+            let pixel = if sx < 0.0
+                || sy < 0.0
+                || sx >= source.width as f32
+                || sy >= source.height as f32
+            {
+                [0, 0, 0, 0xFF]
+            } else {
+                source.get(sx as usize, sy as usize)
+            };
+            image.set(x, y, pixel);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn white(width: usize, height: usize) -> Image {
+        Image::from_rgba(width, height, vec![0xFF; width * height * 4])
+    }
+
+    #[test]
+    fn test_parse_effect() {
+        assert_eq!("scanlines".parse::<Effect>(), Ok(Effect::Scanlines(0.4)));
+        assert_eq!("bloom:0.25".parse::<Effect>(), Ok(Effect::Bloom(0.25)));
+        assert!("bloom:2".parse::<Effect>().is_err());
+        assert!("vhs".parse::<Effect>().is_err());
+    }
+
+    #[test]
+    fn test_scanlines() {
+        let mut image = white(4, 4);
+        let mut effects = Effects::default();
+        effects.enable(Effect::Scanlines(0.5));
+        effects.apply(&mut image, 2);
+        assert_eq!(image.get(0, 0), [255, 255, 255, 255]);
+        assert_eq!(image.get(0, 1), [127, 127, 127, 255]);
+    }
+
+    #[test]
+    fn test_grid() {
+        let mut image = white(6, 6);
+        let mut effects = Effects::default();
+        effects.enable(Effect::Grid(1.0));
+        effects.apply(&mut image, 3);
+        assert_eq!(image.get(0, 0), [255, 255, 255, 255]);
+        assert_eq!(image.get(2, 0), [0, 0, 0, 255]);
+        assert_eq!(image.get(0, 5), [0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn test_bloom_spreads_light() {
+        let mut image = Image::new(9, 1);
+        image.set(4, 0, [200, 200, 200, 255]);
+        let mut effects = Effects::default();
+        effects.enable(Effect::Bloom(1.0));
+        effects.apply(&mut image, 2);
+        assert!(image.get(3, 0)[0] > 0);
+        assert_eq!(image.get(0, 0)[0], 0);
+    }
+
+    #[test]
+    fn test_curvature_keeps_centre() {
+        let mut image = Image::new(10, 10);
+        image.set(5, 5, [255, 0, 0, 255]);
+        let mut effects = Effects::default();
+        effects.enable(Effect::Curvature(0.2));
+        effects.apply(&mut image, 1);
+        assert_eq!(image.get(5, 5), [255, 0, 0, 255]);
+        assert_eq!(image.get(0, 0), [0, 0, 0, 255]);
+    }
+}
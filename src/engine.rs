@@ -0,0 +1,140 @@
+// How instructions are run. The interpreter fetches and decodes every
+// instruction each time it runs; the cached engine keeps each instruction it
+// decodes, by address, and only decodes it again after the game writes over
+// it with FX33 or FX55. Both run what they decode through Chip8::execute,
+// so they give exactly the same results, but the cached engine skips the
+// bus for instruction fetches, so fetch hooks only see the first time an
+// instruction runs.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bus::{Bus, MEMORY_SIZE};
+use fault::Fault;
+use instruction::{self, Instruction};
+use Chip8;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Engine {
+    #[default]
+    Interpreter,
+    Cached,
+}
+
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Engine::Interpreter => write!(f, "interpreter"),
+            Engine::Cached => write!(f, "cached"),
+        }
+    }
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Engine, String> {
+        match s {
+            "interpreter" => Ok(Engine::Interpreter),
+            "cached" => Ok(Engine::Cached),
+            _ => Err(format!("Expected interpreter or cached: {}", s)),
+        }
+    }
+}
+
+// The opcode at each address and its decoded instruction, if it has run
+// since memory there last changed
+pub struct DecodeCache {
+    entries: Vec<Option<(u16, Instruction)>>,
+}
+
+impl Default for DecodeCache {
+    fn default() -> DecodeCache {
+        DecodeCache::new()
+    }
+}
+
+impl DecodeCache {
+    pub fn new() -> DecodeCache {
+        DecodeCache {
+            entries: vec![None; MEMORY_SIZE],
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, address: u16) -> Option<(u16, Instruction)> {
+        self.entries[address as usize]
+    }
+
+    pub fn insert(&mut self, address: u16, opcode: u16, instruction: Instruction) {
+        self.entries[address as usize] = Some((opcode, instruction));
+    }
+
+    // Forgets the instructions that include the byte at address, which
+    // start there or the byte before. The instruction at 0xFFF wraps round
+    // to take its second byte from 0.
+    pub fn invalidate(&mut self, address: u16) {
+        let address = address as usize;
+        self.entries[address] = None;
+        self.entries[(address + MEMORY_SIZE - 1) % MEMORY_SIZE] = None;
+    }
+
+    pub fn clear(&mut self) {
+        for entry in self.entries.iter_mut() {
+            *entry = None;
+        }
+    }
+}
+
+impl<B: Bus> Chip8<B> {
+    // Runs the instruction at pc from the cache, decoding and keeping it
+    // first if it isn't there
+    pub(crate) fn step_cached(&mut self, pc: u16) -> Result<(), Fault> {
+        let cached = match self.cache {
+            Some(ref cache) => cache.get(pc),
+            None => None,
+        };
+        let (opcode, instruction) = match cached {
+            Some(entry) => entry,
+            None => {
+                let mut opcode: u16 = (self.bus.fetch(pc) as u16) << 8;
+                opcode |= self.bus.fetch((pc + 1) % MEMORY_SIZE as u16) as u16;
+                let instruction = instruction::decode(opcode);
+                if let Some(ref mut cache) = self.cache {
+                    cache.insert(pc, opcode, instruction);
+                }
+                (opcode, instruction)
+            }
+        };
+        self.opcode = opcode;
+        self.execute(instruction, pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_engine() {
+        assert_eq!("cached".parse::<Engine>(), Ok(Engine::Cached));
+        assert_eq!(Engine::Interpreter.to_string(), "interpreter");
+        assert!("jit".parse::<Engine>().is_err());
+    }
+
+    #[test]
+    fn test_invalidate() {
+        let mut cache = DecodeCache::new();
+        for address in 0x200..0x204 {
+            cache.insert(address, 0x00E0, Instruction::Cls);
+        }
+        cache.invalidate(0x202);
+        assert_eq!(cache.get(0x200), Some((0x00E0, Instruction::Cls)));
+        assert_eq!(cache.get(0x201), None);
+        assert_eq!(cache.get(0x202), None);
+        assert_eq!(cache.get(0x203), Some((0x00E0, Instruction::Cls)));
+        cache.invalidate(0);
+        cache.clear();
+        assert_eq!(cache.get(0x203), None);
+    }
+}
@@ -0,0 +1,73 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::str::FromStr;
+
+// What happens when a game reaches outside the machine: memory past 0xFFF
+// through I, the stack over- or underflowing, or the PC running off the
+// end of memory
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OutOfRange {
+    // Stop and report a fault, leaving the PC on the instruction at fault
+    #[default]
+    Fault,
+    // Wrap around, as addresses do on 12-bit hardware. Memory and the PC
+    // wrap at 4K and the stack pointer at 16 entries.
+    Wrap,
+}
+
+impl fmt::Display for OutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            OutOfRange::Fault => write!(f, "fault"),
+            OutOfRange::Wrap => write!(f, "wrap"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl FromStr for OutOfRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<OutOfRange, String> {
+        match s {
+            "fault" => Ok(OutOfRange::Fault),
+            "wrap" => Ok(OutOfRange::Wrap),
+            _ => Err(format!("Expected fault or wrap: {}", s)),
+        }
+    }
+}
+
+// Why the machine stopped. The PC is the address of the instruction that
+// caused it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    // 2NNN with all 16 stack entries in use
+    StackOverflow { pc: u16 },
+    // 00EE with nothing on the stack
+    StackUnderflow { pc: u16 },
+    // I plus an offset went past 0xFFF
+    MemoryOutOfRange { pc: u16, address: usize },
+    // The PC left memory, so there is no whole instruction to fetch
+    PcOutOfRange { pc: u16 },
+    // An opcode that isn't a chip-8 instruction, usually from running into
+    // data
+    UnknownOpcode { pc: u16, opcode: u16 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fault::StackOverflow { pc } => write!(f, "Stack overflow at 0x{:03X}", pc),
+            Fault::StackUnderflow { pc } => write!(f, "Stack underflow at 0x{:03X}", pc),
+            Fault::MemoryOutOfRange { pc, address } => write!(
+                f,
+                "Memory access at 0x{:X} out of range at 0x{:03X}",
+                address, pc
+            ),
+            Fault::PcOutOfRange { pc } => write!(f, "PC out of range at 0x{:X}", pc),
+            Fault::UnknownOpcode { pc, opcode } => {
+                write!(f, "Unknown opcode 0x{:04X} at 0x{:03X}", opcode, pc)
+            }
+        }
+    }
+}
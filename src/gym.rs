@@ -0,0 +1,604 @@
+// A reinforcement learning environment in the style of OpenAI Gym. An agent
+// picks one of a game's actions each step, which holds down a set of hex
+// keys for a few frames, and gets back the screen, a reward and whether the
+// episode is over.
+//
+// What a game's actions are and how it is scored are kept in env files,
+// in the config format with one section per game named by the SHA-1 of its
+// ROM like the ROM database:
+//
+//     [0123456789abcdef0123456789abcdef01234567]
+//     # Nothing, left, right, and fire while moving either way
+//     actions = none 4 6 4+5 6+5
+//     frame-skip = 4
+//     cycles = 15
+//     quirks = vip
+//     # The score is kept as BCD digits, and the game ends with no lives
+//     score = [3A0] * 100 + [3A1] * 10 + [3A2]
+//     done = vE == 0
+//
+// The reward for a step is how much the score went up. Expressions can use
+// V0 to VF, i, the delay and sound timers as dt and st, memory bytes as
+// [ADDR] with the address in hex, numbers in decimal or 0x hex, the usual
+// arithmetic and comparisons, && and || and brackets. A value other than 0
+// counts as true.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bus::{Bus, MEMORY_SIZE};
+use config;
+use quirks::Quirks;
+use screen::{HEIGHT, WIDTH};
+use sha1::Digest;
+use Chip8;
+
+// Observations are the screen, a byte per pixel that is 1 when lit,
+// starting from the top left a row at a time
+pub const OBSERVATION_SIZE: usize = WIDTH * HEIGHT;
+
+const DEFAULT_CYCLES: u32 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+impl Op {
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            Op::Add => a.wrapping_add(b),
+            Op::Sub => a.wrapping_sub(b),
+            Op::Mul => a.wrapping_mul(b),
+            Op::Eq => (a == b) as i64,
+            Op::Ne => (a != b) as i64,
+            Op::Lt => (a < b) as i64,
+            Op::Le => (a <= b) as i64,
+            Op::Gt => (a > b) as i64,
+            Op::Ge => (a >= b) as i64,
+            Op::And => (a != 0 && b != 0) as i64,
+            Op::Or => (a != 0 || b != 0) as i64,
+        }
+    }
+
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::And => "&&",
+            Op::Or => "||",
+        }
+    }
+}
+
+// Binary operators from loosest to tightest binding
+const PRECEDENCE: [&[Op]; 5] = [
+    &[Op::Or],
+    &[Op::And],
+    &[Op::Eq, Op::Ne, Op::Le, Op::Ge, Op::Lt, Op::Gt],
+    &[Op::Add, Op::Sub],
+    &[Op::Mul],
+];
+
+// An expression over the machine's registers and memory
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Register(u8),
+    Index,
+    DelayTimer,
+    SoundTimer,
+    Memory(u16),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, chip8: &Chip8) -> i64 {
+        match *self {
+            Expr::Number(n) => n,
+            Expr::Register(x) => chip8.registers()[x as usize] as i64,
+            Expr::Index => chip8.index() as i64,
+            Expr::DelayTimer => chip8.delay_timer() as i64,
+            Expr::SoundTimer => chip8.sound_timer as i64,
+            Expr::Memory(address) => chip8.bus().memory()[address as usize] as i64,
+            Expr::Not(ref a) => (a.eval(chip8) == 0) as i64,
+            Expr::Negate(ref a) => a.eval(chip8).wrapping_neg(),
+            Expr::Binary(op, ref a, ref b) => op.apply(a.eval(chip8), b.eval(chip8)),
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Register(x) => write!(f, "v{:X}", x),
+            Expr::Index => write!(f, "i"),
+            Expr::DelayTimer => write!(f, "dt"),
+            Expr::SoundTimer => write!(f, "st"),
+            Expr::Memory(address) => write!(f, "[{:03X}]", address),
+            Expr::Not(ref a) => write!(f, "!{}", a),
+            Expr::Negate(ref a) => write!(f, "-{}", a),
+            // Always bracketed, so the expression reads back the same
+            Expr::Binary(op, ref a, ref b) => write!(f, "({} {} {})", a, op.symbol(), b),
+        }
+    }
+}
+
+// How deep brackets, ! and - can nest, as each level is another call on
+// the stack and an env file shouldn't be able to overflow it
+const MAX_DEPTH: usize = 64;
+
+// Reads an expression by recursive descent over its characters
+struct Parser<'a> {
+    text: &'a str,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_space(&mut self) {
+        self.text = self.text.trim_start();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_space();
+        match self.text.strip_prefix(token) {
+            Some(rest) => {
+                self.text = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The longest run of letters and digits next in the text
+    fn word(&mut self) -> &'a str {
+        self.skip_space();
+        let end = self
+            .text
+            .find(|c: char| !c.is_ascii_alphanumeric())
+            .unwrap_or(self.text.len());
+        let (word, rest) = self.text.split_at(end);
+        self.text = rest;
+        word
+    }
+
+    // Parses what a bracket, ! or - nests, one level deeper
+    fn nested(
+        &mut self,
+        parse: fn(&mut Parser<'a>) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!("Nested more than {} deep", MAX_DEPTH));
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == PRECEDENCE.len() {
+            return self.unary();
+        }
+        let mut expr = self.binary(level + 1)?;
+        'operators: loop {
+            for &op in PRECEDENCE[level] {
+                if self.eat(op.symbol()) {
+                    let right = self.binary(level + 1)?;
+                    expr = Expr::Binary(op, Box::new(expr), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(expr);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.nested(Parser::unary)?)));
+        }
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.nested(Parser::unary)?)));
+        }
+        if self.eat("(") {
+            let expr = self.nested(|parser| parser.binary(0))?;
+            if !self.eat(")") {
+                return Err("Expected )".to_string());
+            }
+            return Ok(expr);
+        }
+        if self.eat("[") {
+            let address = self.word();
+            if !self.eat("]") {
+                return Err("Expected ]".to_string());
+            }
+            return match u16::from_str_radix(address, 16) {
+                Ok(address) if (address as usize) < MEMORY_SIZE => Ok(Expr::Memory(address)),
+                _ => Err(format!("Invalid address: {}", address)),
+            };
+        }
+        let word = self.word();
+        let lower = word.to_ascii_lowercase();
+        match lower.as_str() {
+            "" => Err(format!("Expected a value at: {}", self.text)),
+            "i" => Ok(Expr::Index),
+            "dt" => Ok(Expr::DelayTimer),
+            "st" => Ok(Expr::SoundTimer),
+            _ if lower.len() == 2 && lower.starts_with('v') => u8::from_str_radix(&lower[1..], 16)
+                .map(Expr::Register)
+                .map_err(|_| format!("Invalid register: {}", word)),
+            _ => {
+                let number = match lower.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => lower.parse(),
+                };
+                number
+                    .map(Expr::Number)
+                    .map_err(|_| format!("Invalid value: {}", word))
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Expr, String> {
+        let mut parser = Parser { text: s, depth: 0 };
+        let expr = parser.binary(0)?;
+        parser.skip_space();
+        if !parser.text.is_empty() {
+            return Err(format!("Unexpected {}", parser.text));
+        }
+        Ok(expr)
+    }
+}
+
+// The hex keys held down for an action, as a bit per key
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Action(pub u16);
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0 == 0 {
+            return write!(f, "none");
+        }
+        let keys: Vec<String> = (0..16)
+            .filter(|key| self.0 >> key & 1 == 1)
+            .map(|key| format!("{:X}", key))
+            .collect();
+        write!(f, "{}", keys.join("+"))
+    }
+}
+
+// Actions are written as hex keys joined by +, or none for no keys
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Action, String> {
+        if s == "none" {
+            return Ok(Action(0));
+        }
+        let mut keys = 0;
+        for key in s.split('+') {
+            match u8::from_str_radix(key, 16) {
+                Ok(key) if key <= 0xF => keys |= 1 << key,
+                _ => return Err(format!("Invalid key: {}", key)),
+            }
+        }
+        Ok(Action(keys))
+    }
+}
+
+// How a game is played and scored
+#[derive(Clone, Debug, PartialEq)]
+pub struct Game {
+    pub actions: Vec<Action>,
+    // Frames each action is held for, only the last of which is observed
+    pub frame_skip: u32,
+    pub cycles: u32,
+    pub quirks: Quirks,
+    pub score: Expr,
+    pub done: Expr,
+}
+
+impl Default for Game {
+    fn default() -> Game {
+        Game {
+            actions: vec![Action(0)],
+            frame_skip: 1,
+            cycles: DEFAULT_CYCLES,
+            quirks: Quirks::default(),
+            score: Expr::Number(0),
+            done: Expr::Number(0),
+        }
+    }
+}
+
+// Env files, holding games keyed by ROM hash
+#[derive(Clone, Debug, Default)]
+pub struct EnvFile {
+    games: HashMap<Digest, Game>,
+}
+
+impl EnvFile {
+    pub fn parse(text: &str) -> Result<EnvFile, String> {
+        let mut file = EnvFile::default();
+        for setting in config::parse(text)? {
+            let game = file.games.entry(setting.game()?).or_default();
+            set_game(game, &setting.key, &setting.value)
+                .map_err(|e| format!("line {}: {}", setting.line, e))?;
+        }
+        Ok(file)
+    }
+
+    pub fn lookup(&self, hash: &Digest) -> Option<&Game> {
+        self.games.get(hash)
+    }
+}
+
+fn set_game(game: &mut Game, key: &str, value: &str) -> Result<(), String> {
+    let at_least_one = |n: u32| {
+        if n == 0 {
+            Err(format!("{} must be at least 1", key))
+        } else {
+            Ok(n)
+        }
+    };
+    let count = |value: &str| {
+        value
+            .parse()
+            .map_err(|_| format!("Invalid count: {}", value))
+    };
+    match key {
+        "actions" => {
+            game.actions = value
+                .split_whitespace()
+                .map(|action| action.parse())
+                .collect::<Result<_, _>>()?;
+            if game.actions.is_empty() {
+                return Err("A game needs at least one action".to_string());
+            }
+        }
+        "frame-skip" => game.frame_skip = at_least_one(count(value)?)?,
+        "cycles" => game.cycles = at_least_one(count(value)?)?,
+        "quirks" => game.quirks = value.parse()?,
+        "score" => game.score = value.parse()?,
+        "done" => game.done = value.parse()?,
+        _ => return Err(format!("Unknown setting: {}", key)),
+    }
+    Ok(())
+}
+
+// What came of a step
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub observation: Vec<u8>,
+    pub reward: i64,
+    pub done: bool,
+}
+
+pub struct Env {
+    rom: Vec<u8>,
+    game: Game,
+    chip8: Chip8,
+    // The score at the end of the last step
+    score: i64,
+    done: bool,
+}
+
+impl Env {
+    pub fn new(rom: &[u8], game: Game) -> Result<Env, String> {
+        let mut env = Env {
+            rom: rom.to_vec(),
+            game,
+            chip8: Chip8::new(),
+            score: 0,
+            done: true,
+        };
+        env.reset(0)?;
+        Ok(env)
+    }
+
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+
+    // Starts a new episode, with the random numbers the game sees decided
+    // by the seed
+    pub fn reset(&mut self, seed: u64) -> Result<Vec<u8>, String> {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&self.rom)?;
+        chip8.quirks = self.game.quirks;
+        chip8.seed(seed);
+        self.chip8 = chip8;
+        self.score = self.game.score.eval(&self.chip8);
+        self.done = false;
+        Ok(self.observation())
+    }
+
+    // Holds the action's keys down for frame_skip frames. An episode ends
+    // when done holds or the game faults, after which it has to be reset.
+    pub fn step(&mut self, action: usize) -> Result<Step, String> {
+        if self.done {
+            return Err("The episode is over, reset to start another".to_string());
+        }
+        let keys = match self.game.actions.get(action) {
+            Some(keys) => keys.0,
+            None => {
+                return Err(format!(
+                    "Action {} out of range, the game has {}",
+                    action,
+                    self.game.actions.len()
+                ))
+            }
+        };
+        for (key, down) in self.chip8.keys.iter_mut().enumerate() {
+            *down = keys >> key & 1 == 1;
+        }
+        for _ in 0..self.game.frame_skip {
+            if self.chip8.run_frame(self.game.cycles).is_err()
+                || self.game.done.eval(&self.chip8) != 0
+            {
+                self.done = true;
+                break;
+            }
+        }
+        let score = self.game.score.eval(&self.chip8);
+        let reward = score.wrapping_sub(self.score);
+        self.score = score;
+        Ok(Step {
+            observation: self.observation(),
+            reward,
+            done: self.done,
+        })
+    }
+
+    pub fn observation(&self) -> Vec<u8> {
+        self.chip8.screen.pixels().map(|lit| lit as u8).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1;
+
+    // Adds key 5 or 6 to V0 while it is held down, counting steps in V1,
+    // and draws a dot at the top left once
+    const ROM: [u8; 18] = [
+        0xA2, 0x10, // 200: LD I, 0x210
+        0xD0, 0x01, // 202: DRW V0, V0, 1, at 0,0 while V0 is 0
+        0x62, 0x05, // 204: LD V2, 5
+        0xE2, 0xA1, // 206: SKNP V2
+        0x70, 0x05, // 208: ADD V0, 5
+        0x71, 0x01, // 20A: ADD V1, 1
+        0x12, 0x06, // 20C: JP 0x206
+        0x00, 0x00, // 20E
+        0x80, 0x00, // 210: sprite
+    ];
+
+    fn game() -> Game {
+        let mut game = Game::default();
+        let file = "[x]\nactions = none 5 5+6\nframe-skip = 2\ncycles = 4\n\
+                    score = v0\ndone = v0 >= 20";
+        for setting in config::parse(file).unwrap() {
+            set_game(&mut game, &setting.key, &setting.value).unwrap();
+        }
+        game
+    }
+
+    #[test]
+    fn test_parse_expr() {
+        let expr: Expr = "[3A0] * 100 + -v1 >= 0x10 && !(dt == st) || i"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "((((([3A0] * 100) + -v1) >= 16) && !(dt == st)) || i)"
+        );
+        assert_eq!(
+            "1 - 2 - 3".parse::<Expr>().unwrap().to_string(),
+            "((1 - 2) - 3)"
+        );
+        for bad in ["", "1 +", "(1", "[1000]", "vG", "x", "1 2"].iter() {
+            assert!(bad.parse::<Expr>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_parse_depth() {
+        let nested = |depth| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(nested(MAX_DEPTH).parse::<Expr>().is_ok());
+        assert!(nested(MAX_DEPTH + 1).parse::<Expr>().is_err());
+        assert!(format!("{}1", "-!".repeat(MAX_DEPTH / 2))
+            .parse::<Expr>()
+            .is_ok());
+        // Deep enough to overflow the stack without the limit
+        assert!(format!("{}1", "!".repeat(100_000)).parse::<Expr>().is_err());
+        assert!(nested(100_000).parse::<Expr>().is_err());
+    }
+
+    #[test]
+    fn test_parse_actions() {
+        assert_eq!("none".parse(), Ok(Action(0)));
+        assert_eq!("4+a".parse(), Ok(Action(0x0410)));
+        assert_eq!(Action(0x0410).to_string(), "4+A");
+        assert!("4+".parse::<Action>().is_err());
+        assert!("10".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn test_env_file() {
+        let file = EnvFile::parse(
+            "[0123456789ABCDEF0123456789abcdef01234567]\nactions = none 4\nscore = v3",
+        )
+        .unwrap();
+        let hash = sha1::from_hex("0123456789abcdef0123456789abcdef01234567").unwrap();
+        let game = file.lookup(&hash).unwrap();
+        assert_eq!(game.actions, [Action(0), Action(0x10)]);
+        assert_eq!(game.score, Expr::Register(3));
+        assert!(file.lookup(&[0; 20]).is_none());
+        assert!(EnvFile::parse("actions = 4").is_err());
+        assert!(EnvFile::parse("[0123456789abcdef0123456789abcdef01234567]\nlives = 3").is_err());
+        assert!(EnvFile::parse("[0123456789abcdef0123456789abcdef01234567]\nactions =").is_err());
+    }
+
+    #[test]
+    fn test_episode() {
+        let mut env = Env::new(&ROM, game()).unwrap();
+        let observation = env.reset(1).unwrap();
+        assert_eq!(observation.len(), OBSERVATION_SIZE);
+
+        // Nothing held, so nothing scored, but the dot has been drawn
+        let step = env.step(0).unwrap();
+        assert_eq!((step.reward, step.done), (0, false));
+        assert_eq!(step.observation[0], 1);
+        assert_eq!(step.observation[1..].iter().sum::<u8>(), 0);
+
+        // Each frame of 4 cycles adds 5 about once with the key down
+        let step = env.step(1).unwrap();
+        assert!(step.reward > 0 && !step.done);
+        let mut total = step.reward;
+        while !env
+            .step(2)
+            .map(|step| {
+                total += step.reward;
+                step.done
+            })
+            .unwrap()
+        {}
+        assert_eq!(total, env.chip8().registers()[0] as i64);
+        assert!(env.step(0).is_err());
+
+        env.reset(1).unwrap();
+        assert_eq!(env.chip8().registers()[0], 0);
+        assert!(env.step(3).is_err());
+        assert!(env.step(0).is_ok());
+    }
+}
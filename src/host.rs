@@ -0,0 +1,193 @@
+// The interface between the machine and whatever it runs on. A frontend
+// provides a display sink, an input source, an audio sink and a clock, and
+// Host drives the machine a frame at a time through them, so SDL, a
+// terminal, headless runs and tests all plug in the same way.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bus::Bus;
+use fault::Fault;
+use screen::Screen;
+use Chip8;
+
+#[cfg(feature = "sdl")]
+pub mod sdl;
+
+// Shows the screen at the end of each frame
+pub trait DisplaySink {
+    fn present(&mut self, screen: &Screen);
+}
+
+// Sets the hex keys held down at the start of each frame
+pub trait InputSource {
+    fn poll(&mut self, keys: &mut [bool; 16]);
+}
+
+// Plays the tone while the sound timer is running
+pub trait AudioSink {
+    fn set_tone(&mut self, on: bool);
+}
+
+// Paces frames, returning once it is time for the next one
+pub trait Clock {
+    fn wait(&mut self);
+}
+
+// A frontend made of the four parts
+pub struct Host<D, I, A, C> {
+    pub display: D,
+    pub input: I,
+    pub audio: A,
+    pub clock: C,
+}
+
+impl<D: DisplaySink, I: InputSource, A: AudioSink, C: Clock> Host<D, I, A, C> {
+    pub fn new(display: D, input: I, audio: A, clock: C) -> Host<D, I, A, C> {
+        Host {
+            display,
+            input,
+            audio,
+            clock,
+        }
+    }
+
+    // Reads the keys, runs a frame, then shows the screen, updates the tone
+    // and waits for the next frame. The screen is shown even if the frame
+    // faults, so the frontend has the picture the fault left behind.
+    pub fn run_frame<B: Bus>(&mut self, chip8: &mut Chip8<B>, cycles: u32) -> Result<(), Fault> {
+        self.input.poll(&mut chip8.keys);
+        let result = chip8.run_frame(cycles);
+        self.display.present(&chip8.screen);
+        self.audio.set_tone(chip8.sound_timer > 0);
+        self.clock.wait();
+        result
+    }
+}
+
+// Does nothing: no picture, no keys pressed, silence and no waiting
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Null;
+
+impl DisplaySink for Null {
+    fn present(&mut self, _screen: &Screen) {}
+}
+
+impl InputSource for Null {
+    fn poll(&mut self, keys: &mut [bool; 16]) {
+        *keys = [false; 16];
+    }
+}
+
+impl AudioSink for Null {
+    fn set_tone(&mut self, _on: bool) {}
+}
+
+impl Clock for Null {
+    fn wait(&mut self) {}
+}
+
+impl Host<Null, Null, Null, Null> {
+    // Runs as fast as possible with nothing attached
+    pub fn null() -> Host<Null, Null, Null, Null> {
+        Host::new(Null, Null, Null, Null)
+    }
+}
+
+// Keeps to a fixed frame rate by sleeping, catching up if it falls behind
+pub struct RealTime {
+    frame_time: Duration,
+    next_frame: Instant,
+}
+
+impl RealTime {
+    pub fn new(framerate: u32) -> RealTime {
+        RealTime {
+            frame_time: Duration::from_secs(1) / framerate,
+            next_frame: Instant::now(),
+        }
+    }
+}
+
+impl Clock for RealTime {
+    fn wait(&mut self) {
+        self.next_frame += self.frame_time;
+        let now = Instant::now();
+        if self.next_frame > now {
+            thread::sleep(self.next_frame - now);
+        } else {
+            self.next_frame = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Presses key 5 on every frame and keeps the last screen and tone
+    #[derive(Default)]
+    struct Probe {
+        screen: Option<Screen>,
+        tone: bool,
+        frames: usize,
+    }
+
+    impl DisplaySink for Probe {
+        fn present(&mut self, screen: &Screen) {
+            self.screen = Some(*screen);
+        }
+    }
+
+    impl InputSource for Probe {
+        fn poll(&mut self, keys: &mut [bool; 16]) {
+            keys[5] = true;
+        }
+    }
+
+    impl AudioSink for Probe {
+        fn set_tone(&mut self, on: bool) {
+            self.tone = on;
+        }
+    }
+
+    impl Clock for Probe {
+        fn wait(&mut self) {
+            self.frames += 1;
+        }
+    }
+
+    #[test]
+    fn test_host_frame() {
+        // LD V0, 5; SKNP V0; LD ST, V0; LD F, V0; DRW V1, V1, 5; JP 0x20A
+        let rom = [
+            0x60, 0x05, 0xE0, 0xA1, 0xF0, 0x18, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x0A,
+        ];
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        let mut host = Host::new(
+            Probe::default(),
+            Probe::default(),
+            Probe::default(),
+            Probe::default(),
+        );
+        host.run_frame(&mut chip8, 8).unwrap();
+        assert!(chip8.keys[5]);
+        assert!(host.display.screen.unwrap().pixel(0, 0));
+        assert!(host.audio.tone);
+        assert_eq!(host.clock.frames, 1);
+    }
+
+    #[test]
+    fn test_null_host() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&[0x12, 0x00]).unwrap();
+        chip8.keys[3] = true;
+        let mut host = Host::null();
+        for _ in 0..10 {
+            host.run_frame(&mut chip8, 8).unwrap();
+        }
+        assert_eq!(chip8.keys, [false; 16]);
+        assert_eq!(chip8.cycles(), 80);
+    }
+}
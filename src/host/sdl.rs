@@ -0,0 +1,250 @@
+// The reference frontend parts, built on SDL2
+
+use sdl2;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::EventPollIterator;
+use sdl2::keyboard::Scancode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use sdl2::{EventPump, Sdl};
+
+use display::{Filter, Framebuffer};
+use host::{AudioSink, DisplaySink, InputSource};
+use image::Image;
+use palette::{Palette, Rgb};
+use screen::Screen;
+
+// The hex keyboard laid out as a 4x4 block on the left of a QWERTY
+// keyboard: 1-4, Q-R, A-F and Z-V
+const KEYPAD: [(Scancode, u8); 16] = [
+    (Scancode::Num1, 0x1),
+    (Scancode::Num2, 0x2),
+    (Scancode::Num3, 0x3),
+    (Scancode::Num4, 0xC),
+    (Scancode::Q, 0x4),
+    (Scancode::W, 0x5),
+    (Scancode::E, 0x6),
+    (Scancode::R, 0xD),
+    (Scancode::A, 0x7),
+    (Scancode::S, 0x8),
+    (Scancode::D, 0x9),
+    (Scancode::F, 0xE),
+    (Scancode::Z, 0xA),
+    (Scancode::X, 0x0),
+    (Scancode::C, 0xB),
+    (Scancode::V, 0xF),
+];
+
+// The hex key a key on the keyboard stands for, leaving out --keys bindings
+pub fn hex_key(scancode: Scancode) -> Option<u8> {
+    KEYPAD
+        .iter()
+        .find(|&&(keypad, _)| keypad == scancode)
+        .map(|&(_, key)| key)
+}
+
+// A window showing the screen scaled up to fit, with a border around it
+pub struct SdlDisplay {
+    canvas: Canvas<Window>,
+    textures: TextureCreator<WindowContext>,
+    // Colours used by present
+    pub palette: Palette,
+}
+
+impl SdlDisplay {
+    pub fn new(sdl: &Sdl, title: &str, width: u32, height: u32) -> Result<SdlDisplay, String> {
+        if !sdl2::hint::set("SDL_HINT_RENDER_SCALE_QUALITY", "0") {
+            println!("Failed to set render scaling method");
+        }
+        let window = sdl
+            .video()?
+            .window(title, width, height)
+            .position_centered()
+            .resizable()
+            .build()
+            .map_err(|e| e.to_string())?;
+        let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+        let textures = canvas.texture_creator();
+        let mut display = SdlDisplay {
+            canvas,
+            textures,
+            palette: Palette::default(),
+        };
+        display.clear();
+        Ok(display)
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        if let Err(e) = self.canvas.window_mut().set_title(title) {
+            println!("Failed to set the window title: {}", e);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.canvas.set_draw_color(rgb(self.palette.border));
+        self.canvas.clear();
+        self.canvas.present();
+    }
+
+    // Shows a finished picture, for frontends that do their own filtering
+    // and effects. It is scaled to the window keeping its aspect ratio.
+    pub fn show(&mut self, image: &Image) -> Result<(), String> {
+        let (width, height) = (image.width as u32, image.height as u32);
+        let mut texture = self
+            .textures
+            .create_texture_streaming(PixelFormatEnum::RGBA32, width, height)
+            .map_err(|e| e.to_string())?;
+        texture
+            .update(None, &image.pixels, image.width * 4)
+            .map_err(|e| e.to_string())?;
+        self.canvas.set_draw_color(rgb(self.palette.border));
+        self.canvas.clear();
+        let viewport = letterbox(self.canvas.output_size()?, (width, height));
+        self.canvas.copy(&texture, None, viewport)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+impl DisplaySink for SdlDisplay {
+    fn present(&mut self, screen: &Screen) {
+        let mut framebuffer = Framebuffer::new(Filter::None);
+        framebuffer.update(screen, 0.0);
+        let image = framebuffer.to_image(self.palette.foreground(), self.palette.background());
+        if let Err(e) = self.show(&image) {
+            println!("Failed to draw the screen: {}", e);
+        }
+    }
+}
+
+fn rgb(colour: Rgb) -> Color {
+    Color::RGB(colour[0], colour[1], colour[2])
+}
+
+// The largest rectangle with the picture's aspect ratio that fits in the
+// window, centred so the border fills the rest
+fn letterbox(window: (u32, u32), picture: (u32, u32)) -> Rect {
+    let (window_width, window_height) = window;
+    let (picture_width, picture_height) = picture;
+    let (width, height) = if window_width * picture_height > window_height * picture_width {
+        (
+            window_height * picture_width / picture_height,
+            window_height,
+        )
+    } else {
+        (window_width, window_width * picture_height / picture_width)
+    };
+    Rect::new(
+        ((window_width - width) / 2) as i32,
+        ((window_height - height) / 2) as i32,
+        width,
+        height,
+    )
+}
+
+// The keyboard, read through SDL's event pump. The pump is owned here, so
+// frontends take their other events from poll_events.
+pub struct SdlInput {
+    events: EventPump,
+    bindings: Vec<(u8, Scancode)>,
+}
+
+impl SdlInput {
+    pub fn new(sdl: &Sdl) -> Result<SdlInput, String> {
+        Ok(SdlInput {
+            events: sdl.event_pump()?,
+            bindings: Vec::new(),
+        })
+    }
+
+    // Extra keys for the hex keys, as SDL scancode names such as "Left".
+    // Names SDL doesn't know are skipped.
+    pub fn set_bindings(&mut self, keys: &[(u8, String)]) {
+        self.bindings.clear();
+        for &(key, ref name) in keys {
+            match Scancode::from_name(name) {
+                Some(scancode) => self.bindings.push((key, scancode)),
+                None => eprintln!("Unknown key name in binding: {}", name),
+            }
+        }
+    }
+
+    pub fn poll_events(&mut self) -> EventPollIterator<'_> {
+        self.events.poll_iter()
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self, keys: &mut [bool; 16]) {
+        let state = self.events.keyboard_state();
+        *keys = [false; 16];
+        for &(scancode, key) in KEYPAD.iter() {
+            if state.is_scancode_pressed(scancode) {
+                keys[key as usize] = true;
+            }
+        }
+        for &(key, scancode) in &self.bindings {
+            if state.is_scancode_pressed(scancode) {
+                keys[key as usize] = true;
+            }
+        }
+    }
+}
+
+// A 440 Hz square wave
+pub struct SdlAudio {
+    device: AudioDevice<SquareWave>,
+}
+
+impl SdlAudio {
+    pub fn new(sdl: &Sdl) -> Result<SdlAudio, String> {
+        let desired_spec = AudioSpecDesired {
+            freq: Some(44100),
+            channels: Some(1), // mono
+            samples: None,     // default sample size
+        };
+        let device = sdl
+            .audio()?
+            .open_playback(None, &desired_spec, |spec| SquareWave {
+                phase_inc: 440.0 / spec.freq as f32,
+                phase: 0.0,
+                volume: 0.25,
+            })?;
+        Ok(SdlAudio { device })
+    }
+}
+
+impl AudioSink for SdlAudio {
+    fn set_tone(&mut self, on: bool) {
+        if on {
+            self.device.resume();
+        } else {
+            self.device.pause();
+        }
+    }
+}
+
+// Totally copy pasted example from the sdl2 docs
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        // Generate a square wave
+        for x in out.iter_mut() {
+            *x = if self.phase <= 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
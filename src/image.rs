@@ -0,0 +1,98 @@
+use std::io;
+use std::io::prelude::*;
+
+// An RGBA image, 4 bytes per pixel, rows stored top to bottom
+#[derive(Clone, Debug, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![0; width * height * 4],
+        }
+    }
+
+    pub fn from_rgba(width: usize, height: usize, pixels: Vec<u8>) -> Image {
+        assert_eq!(pixels.len(), width * height * 4);
+        Image {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 4] {
+        let offset = (y * self.width + x) * 4;
+        let mut pixel = [0; 4];
+        pixel.copy_from_slice(&self.pixels[offset..offset + 4]);
+        pixel
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, pixel: [u8; 4]) {
+        let offset = (y * self.width + x) * 4;
+        self.pixels[offset..offset + 4].copy_from_slice(&pixel);
+    }
+
+    // Fills a rectangle, clipped to the image
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, pixel: [u8; 4]) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.set(column, row, pixel);
+            }
+        }
+    }
+
+    // Nearest neighbour upscale by a whole number factor, which keeps the
+    // chip-8 pixels crisp
+    pub fn scale(&self, factor: usize) -> Image {
+        let mut scaled = Image::new(self.width * factor, self.height * factor);
+        for y in 0..scaled.height {
+            for x in 0..scaled.width {
+                scaled.set(x, y, self.get(x / factor, y / factor));
+            }
+        }
+        scaled
+    }
+
+    // Writes the image as a binary PPM (P6), dropping the alpha channel
+    pub fn write_ppm<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for pixel in self.pixels.chunks(4) {
+            rgb.extend_from_slice(&pixel[0..3]);
+        }
+        out.write_all(&rgb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale() {
+        let mut image = Image::new(2, 1);
+        image.set(1, 0, [1, 2, 3, 4]);
+        let scaled = image.scale(3);
+        assert_eq!(scaled.width, 6);
+        assert_eq!(scaled.height, 3);
+        assert_eq!(scaled.get(2, 2), [0, 0, 0, 0]);
+        assert_eq!(scaled.get(3, 0), [1, 2, 3, 4]);
+        assert_eq!(scaled.get(5, 2), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_write_ppm() {
+        let mut image = Image::new(1, 1);
+        image.set(0, 0, [10, 20, 30, 255]);
+        let mut out = Vec::new();
+        image.write_ppm(&mut out).unwrap();
+        assert_eq!(out, b"P6\n1 1\n255\n\x0a\x14\x1e".to_vec());
+    }
+}
@@ -0,0 +1,138 @@
+// Decoding opcodes into instructions, shared by the machine, which runs
+// them, and the disassembler and analysis, which show them.
+
+// A decoded chip-8 instruction, named after the mnemonics in Cowgod's
+// technical reference. X and Y are register numbers, NNN an address, KK a
+// byte and N a nibble.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    // 0NNN, machine code routines on the original hardware
+    Sys(u16),
+    // 00E0
+    Cls,
+    // 00EE
+    Ret,
+    // 1NNN
+    Jp(u16),
+    // 2NNN
+    Call(u16),
+    // 3XKK
+    SeByte(u8, u8),
+    // 4XKK
+    SneByte(u8, u8),
+    // 5XY0
+    SeReg(u8, u8),
+    // 6XKK
+    LdByte(u8, u8),
+    // 7XKK
+    AddByte(u8, u8),
+    // 8XY0 to 8XYE
+    LdReg(u8, u8),
+    Or(u8, u8),
+    And(u8, u8),
+    Xor(u8, u8),
+    AddReg(u8, u8),
+    Sub(u8, u8),
+    Shr(u8, u8),
+    Subn(u8, u8),
+    Shl(u8, u8),
+    // 9XY0
+    SneReg(u8, u8),
+    // ANNN
+    LdI(u16),
+    // BNNN
+    JpV0(u16),
+    // CXKK
+    Rnd(u8, u8),
+    // DXYN
+    Drw(u8, u8, u8),
+    // EX9E and EXA1
+    Skp(u8),
+    Sknp(u8),
+    // FX07 to FX65
+    LdVxDt(u8),
+    LdVxK(u8),
+    LdDtVx(u8),
+    LdStVx(u8),
+    AddI(u8),
+    LdF(u8),
+    LdB(u8),
+    LdIVx(u8),
+    LdVxI(u8),
+    // Anything else, which is data or a bug in the game
+    Unknown(u16),
+}
+
+pub fn decode(opcode: u16) -> Instruction {
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let n = (opcode & 0x000F) as u8;
+    let kk = (opcode & 0x00FF) as u8;
+    let nnn = opcode & 0x0FFF;
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            _ => Instruction::Sys(nnn),
+        },
+        0x1000 => Instruction::Jp(nnn),
+        0x2000 => Instruction::Call(nnn),
+        0x3000 => Instruction::SeByte(x, kk),
+        0x4000 => Instruction::SneByte(x, kk),
+        0x5000 if n == 0 => Instruction::SeReg(x, y),
+        0x6000 => Instruction::LdByte(x, kk),
+        0x7000 => Instruction::AddByte(x, kk),
+        0x8000 => match n {
+            0x0 => Instruction::LdReg(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::Xor(x, y),
+            0x4 => Instruction::AddReg(x, y),
+            0x5 => Instruction::Sub(x, y),
+            0x6 => Instruction::Shr(x, y),
+            0x7 => Instruction::Subn(x, y),
+            0xE => Instruction::Shl(x, y),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x9000 if n == 0 => Instruction::SneReg(x, y),
+        0xA000 => Instruction::LdI(nnn),
+        0xB000 => Instruction::JpV0(nnn),
+        0xC000 => Instruction::Rnd(x, kk),
+        0xD000 => Instruction::Drw(x, y, n),
+        0xE000 => match kk {
+            0x9E => Instruction::Skp(x),
+            0xA1 => Instruction::Sknp(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        0xF000 => match kk {
+            0x07 => Instruction::LdVxDt(x),
+            0x0A => Instruction::LdVxK(x),
+            0x15 => Instruction::LdDtVx(x),
+            0x18 => Instruction::LdStVx(x),
+            0x1E => Instruction::AddI(x),
+            0x29 => Instruction::LdF(x),
+            0x33 => Instruction::LdB(x),
+            0x55 => Instruction::LdIVx(x),
+            0x65 => Instruction::LdVxI(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(0x00E0), Instruction::Cls);
+        assert_eq!(decode(0x2ABC), Instruction::Call(0xABC));
+        assert_eq!(decode(0x8126), Instruction::Shr(1, 2));
+        assert_eq!(decode(0xD125), Instruction::Drw(1, 2, 5));
+        assert_eq!(decode(0xF355), Instruction::LdIVx(3));
+        assert_eq!(decode(0x5121), Instruction::Unknown(0x5121));
+        assert_eq!(decode(0x8128), Instruction::Unknown(0x8128));
+        assert_eq!(decode(0xE1FF), Instruction::Unknown(0xE1FF));
+    }
+}
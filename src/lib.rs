@@ -1,4 +1,74 @@
+// Without the std feature the core builds with #![no_std] and never
+// allocates, so it can run on microcontrollers. That leaves the machine
+// itself, its bus, quirks and faults; everything else needs std.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(feature = "std")]
 extern crate rand;
+#[cfg(feature = "sdl")]
+extern crate sdl2;
+
+use core::fmt;
+
+use bus::{Bus, Ram, MEMORY_SIZE};
+#[cfg(feature = "std")]
+use engine::{DecodeCache, Engine};
+use fault::{Fault, OutOfRange};
+use instruction::Instruction;
+use quirks::Quirks;
+use screen::{Screen, HEIGHT, WIDTH};
+use sha1::{Digest, Sha1};
+use timing::Timing;
+#[cfg(feature = "std")]
+use trace::{Step, Tracer};
+
+#[cfg(feature = "std")]
+pub mod analysis;
+#[cfg(feature = "std")]
+pub mod browser;
+pub mod bus;
+#[cfg(feature = "std")]
+pub mod cheat;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod decompile;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod display;
+#[cfg(feature = "std")]
+pub mod effects;
+#[cfg(feature = "std")]
+pub mod engine;
+pub mod fault;
+#[cfg(feature = "std")]
+pub mod gym;
+#[cfg(feature = "std")]
+pub mod host;
+#[cfg(feature = "std")]
+pub mod image;
+pub mod instruction;
+#[cfg(feature = "std")]
+pub mod movie;
+#[cfg(feature = "std")]
+pub mod palette;
+pub mod quirks;
+#[cfg(feature = "std")]
+pub mod remote;
+#[cfg(feature = "std")]
+pub mod romdb;
+pub mod screen;
+pub mod sha1;
+#[cfg(feature = "std")]
+pub mod state;
+#[cfg(feature = "std")]
+pub mod text;
+pub mod timing;
+#[cfg(feature = "std")]
+pub mod trace;
 
 /* memory */
 /* mem map taken from http://devernay.free.fr/hacks/chip8/C8TECH10.HTM */
@@ -26,16 +96,41 @@ extern crate rand;
    |  interpreter  |
    +---------------+= 0x000 (0) Start of Chip-8 RAM
 */
-pub struct Chip8 {
-    // For better or worse, use matrix notation for now.
-    // The screen is 64x32 pixels, i.e 64 wide 32 tall. There are 32 rows,
-    // 64 columns. I try to stick to that notation here but it should probably
-    // be changed to screen[width][height] like most graphics apis seem to be.
-    // Each entry represents if the pixel is currently set (i.e. is white)
-    // or is not set (i.e. is black, the background).
-    pub screen: [[bool; 64]; 32],
+// Largest ROM that fits between 0x200 and the end of memory
+pub const MAX_ROM_SIZE: usize = MEMORY_SIZE - 0x200;
+
+// A ROM passed to load_rom that is bigger than MAX_ROM_SIZE
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RomTooBig {
+    pub size: usize,
+}
+
+impl fmt::Display for RomTooBig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ROM is {} bytes, the most that fits in memory is {}",
+            self.size, MAX_ROM_SIZE
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<RomTooBig> for String {
+    fn from(error: RomTooBig) -> String {
+        error.to_string()
+    }
+}
+
+// The interpreter, generic over the memory bus. Plain RAM is the default so
+// hooks cost nothing unless a bus with them is asked for.
+pub struct Chip8<B: Bus = Ram> {
+    // The screen is 64x32 pixels, i.e 64 wide 32 tall, packed a row to a
+    // u64. A set pixel is lit (i.e. is white), a clear one is the
+    // background.
+    pub screen: Screen,
     pub keys: [bool; 0xF + 1], // Input is a hex keyboard
-    memory: [u8; 4096],
+    bus: B,
     registers: [u8; 16],
     instruction_reg: u16,
     delay_timer: u8,
@@ -43,14 +138,65 @@ pub struct Chip8 {
     pc: u16,
     sp: u8,
     stack: [u16; 16],
+    pub quirks: Quirks,
+    // How accesses outside memory or the stack are handled
+    pub out_of_range: OutOfRange,
+    // How run_frame decides how many instructions make a frame
+    pub timing: Timing,
+    // Machine cycles the last frame ran over its budget by with the VIP
+    // timing, which come out of the next one
+    overrun: u16,
+    // Hash of the last ROM loaded, for looking the game up in the ROM
+    // database
+    rom_hash: Digest,
+    // Set when a sprite is drawn, for the display wait quirk
+    drew: bool,
+    // The opcode of the last instruction run, for working out what it cost
+    opcode: u16,
+    // State of the random number generator used by CXNN. Seeding it makes
+    // runs repeatable, which movies rely on.
+    rng: u64,
+    // Instructions run since the machine was created
+    cycles: u64,
+    // Optional per-instruction trace. When it is off the only cost is
+    // checking for it once per instruction.
+    #[cfg(feature = "std")]
+    tracer: Option<Tracer>,
+    // Instructions already decoded, when running the cached engine
+    #[cfg(feature = "std")]
+    cache: Option<DecodeCache>,
 }
 
+#[cfg(feature = "std")]
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
+}
+
+#[cfg(feature = "std")]
 impl Chip8 {
     pub fn new() -> Chip8 {
+        Chip8::with_bus(Ram::new())
+    }
+}
+
+impl<B: Bus> Chip8<B> {
+    // A machine whose memory accesses all go through the given bus, with
+    // the random number generator seeded from the operating system
+    #[cfg(feature = "std")]
+    pub fn with_bus(bus: B) -> Chip8<B> {
+        Chip8::with_seed(bus, rand::random::<u64>())
+    }
+
+    // A machine on the given bus with the random number generator seeded by
+    // the caller. Without std there is nowhere else to get a seed from, so
+    // boards pass in one from a hardware RNG, a floating ADC pin or similar.
+    pub fn with_seed(bus: B, seed: u64) -> Chip8<B> {
         let mut chip = Chip8 {
-            screen: [[false; 64]; 32],
+            screen: Screen::new(),
             keys: [false; 0xF + 1],
-            memory: [0; 4096],
+            bus,
             registers: [0; 16],
             instruction_reg: 0,
             delay_timer: 0,
@@ -58,19 +204,44 @@ impl Chip8 {
             pc: 0x200,
             sp: 0,
             stack: [0; 16],
+            quirks: Quirks::default(),
+            out_of_range: OutOfRange::default(),
+            timing: Timing::default(),
+            overrun: 0,
+            rom_hash: sha1::sha1(&[]),
+            drew: false,
+            opcode: 0,
+            rng: seed,
+            cycles: 0,
+            #[cfg(feature = "std")]
+            tracer: None,
+            #[cfg(feature = "std")]
+            cache: None,
         };
         chip.clear_screen();
         chip.load_fontset();
         chip
     }
 
-    // Clear the screen
-    fn clear_screen(&mut self) {
-        for (_i, row) in self.screen.iter_mut().enumerate() {
-            for pixel in row.iter_mut() {
-                *pixel = false;
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    // Anything could be written through the bus, so this empties the
+    // decode cache
+    pub fn bus_mut(&mut self) -> &mut B {
+        #[cfg(feature = "std")]
+        {
+            if let Some(ref mut cache) = self.cache {
+                cache.clear();
             }
         }
+        &mut self.bus
+    }
+
+    // Clear the screen
+    fn clear_screen(&mut self) {
+        self.screen.clear();
     }
 
     // Decrements timers at 60 Hz if not 0
@@ -84,14 +255,200 @@ impl Chip8 {
         }
     }
 
+    // Runs one 60 Hz frame: the given number of instructions followed by a
+    // tick of the timers. With the VIP timing the number of instructions is
+    // ignored and they run until the frame's machine cycles are used up.
+    // With the display wait quirk the frame ends early once a sprite is
+    // drawn. A fault stops the frame where it happened, without ticking the
+    // timers.
+    pub fn run_frame(&mut self, cycles: u32) -> Result<(), Fault> {
+        self.drew = false;
+        match self.timing {
+            Timing::Instructions => {
+                for _ in 0..cycles {
+                    self.cycle()?;
+                    if self.drew && self.quirks.display_wait {
+                        break;
+                    }
+                }
+            }
+            Timing::Vip => self.run_vip_frame()?,
+        }
+        self.update_timers();
+        Ok(())
+    }
+
+    // Runs instructions within the VIP's budget for a frame. Whatever the
+    // last one runs over by is taken from the next frame, so an instruction
+    // longer than a frame, like clearing the screen, stalls the game until
+    // it is paid off.
+    fn run_vip_frame(&mut self) -> Result<(), Fault> {
+        let mut budget = timing::BUDGET as i32 - self.overrun as i32;
+        while budget > 0 {
+            let cost = match self.timed_cycle() {
+                Ok(cost) => cost as i32,
+                Err(fault) => {
+                    // The frame still paid off what the last one ran over
+                    self.overrun = 0;
+                    return Err(fault);
+                }
+            };
+            if self.drew && self.quirks.display_wait {
+                // The sprite waits for the vertical blank, so the drawing
+                // happens in the next frame's time
+                budget = -cost;
+                break;
+            }
+            budget -= cost;
+        }
+        self.overrun = -budget as u16;
+        Ok(())
+    }
+
     // Loads a buffer into memory at location 0x200
     // which is where ROM data starts for chip-8
-    pub fn load_rom(&mut self, buffer: Vec<u8>) {
-        for (i, data) in buffer.iter().enumerate() {
-            self.memory[0x200 + i] = *data;
+    pub fn load_rom(&mut self, buffer: &[u8]) -> Result<(), RomTooBig> {
+        if buffer.len() > MAX_ROM_SIZE {
+            return Err(RomTooBig { size: buffer.len() });
+        }
+        self.bus_mut().memory_mut()[0x200..0x200 + buffer.len()].copy_from_slice(buffer);
+        self.rom_hash = sha1::sha1(buffer);
+        Ok(())
+    }
+
+    // The address offset bytes past I, wrapped or faulted if it falls
+    // outside memory. pc is the instruction doing the access.
+    fn address(&self, offset: usize, pc: u16) -> Result<u16, Fault> {
+        let address = self.instruction_reg as usize + offset;
+        if address < MEMORY_SIZE {
+            return Ok(address as u16);
+        }
+        match self.out_of_range {
+            OutOfRange::Wrap => Ok((address % MEMORY_SIZE) as u16),
+            OutOfRange::Fault => Err(Fault::MemoryOutOfRange { pc, address }),
+        }
+    }
+
+    // Restarts the random number generator from a known point
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = seed;
+    }
+
+    // The next byte from the random number generator, a SplitMix64
+    fn random_byte(&mut self) -> u8 {
+        self.rng = self.rng.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        (z ^ (z >> 31)) as u8
+    }
+
+    // SHA-1 of everything that affects how the machine runs from here on,
+    // for checking that two runs ended up in the same place
+    pub fn state_hash(&self) -> Digest {
+        let mut state = Sha1::new();
+        state.update(self.bus.memory());
+        state.update(&self.registers);
+        state.update(&self.instruction_reg.to_be_bytes());
+        state.update(&[self.delay_timer, self.sound_timer]);
+        state.update(&self.pc.to_be_bytes());
+        state.update(&[self.sp]);
+        for address in self.stack.iter() {
+            state.update(&address.to_be_bytes());
+        }
+        for pixel in self.screen.pixels() {
+            state.update(&[pixel as u8]);
+        }
+        state.update(&[self.drew as u8]);
+        state.update(&self.rng.to_be_bytes());
+        state.update(&self.overrun.to_be_bytes());
+        state.finish()
+    }
+
+    // Number of instructions run so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // V0 to VF
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    // The I register
+    pub fn index(&self) -> u16 {
+        self.instruction_reg
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn stack(&self) -> &[u16; 16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    // Starts or stops tracing each instruction
+    #[cfg(feature = "std")]
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    // Stops tracing, handing back the tracer so it can be finished or moved
+    // to another machine
+    #[cfg(feature = "std")]
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    // Switches between interpreting every instruction and running them from
+    // the decode cache
+    #[cfg(feature = "std")]
+    pub fn set_engine(&mut self, engine: Engine) {
+        self.cache = match engine {
+            Engine::Interpreter => None,
+            Engine::Cached => Some(DecodeCache::new()),
+        };
+    }
+
+    #[cfg(feature = "std")]
+    pub fn engine(&self) -> Engine {
+        match self.cache {
+            Some(_) => Engine::Cached,
+            None => Engine::Interpreter,
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn trace(&mut self, opcode: u16) {
+        let step = Step {
+            cycle: self.cycles,
+            pc: self.pc,
+            opcode,
+            registers: self.registers,
+            i: self.instruction_reg,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        };
+        if let Some(ref mut tracer) = self.tracer {
+            tracer.record(&step);
         }
     }
 
+    // SHA-1 of the ROM passed to load_rom
+    pub fn rom_hash(&self) -> Digest {
+        self.rom_hash
+    }
+
     // Loads the fontset chip 8 provides into memory
     fn load_fontset(&mut self) {
         // Load the fontset into the beginning of the interpreter memory
@@ -114,223 +471,302 @@ impl Chip8 {
             0xF0, 0x80, 0xF0, 0x80, 0x80, // F
         ];
         for (i, font) in fontset.iter().enumerate() {
-            self.memory[i] = *font;
+            self.bus.memory_mut()[i] = *font;
         }
     }
 
-    // Runs an instruction, updating registers as necessary
-    pub fn cycle(&mut self) {
-        let mut opcode: u16 = (self.memory[self.pc as usize] as u16) << 8;
-        opcode |= self.memory[(self.pc + 1) as usize] as u16;
+    // Runs a single instruction. After a fault the PC is left pointing at
+    // the instruction that caused it.
+    pub fn cycle(&mut self) -> Result<(), Fault> {
+        // A whole instruction has to fit before the end of memory
+        if self.pc as usize > MEMORY_SIZE - 2 {
+            match self.out_of_range {
+                OutOfRange::Wrap => self.pc %= MEMORY_SIZE as u16,
+                OutOfRange::Fault => return Err(Fault::PcOutOfRange { pc: self.pc }),
+            }
+        }
+        let pc = self.pc;
+        let result = self.step(pc);
+        match result {
+            Ok(()) => self.cycles += 1,
+            Err(_) => self.pc = pc,
+        }
+        result
+    }
 
-        // Calculate indexes of registers derived from opcode
-        let index: usize = ((opcode & 0x0F00) >> 8) as usize;
-        let index_x: usize = index;
-        let index_y: usize = ((opcode & 0x00F0) >> 4) as usize;
+    // Runs a single instruction like cycle, returning the machine cycles it
+    // took on the COSMAC VIP
+    pub fn timed_cycle(&mut self) -> Result<u32, Fault> {
+        let pc = self.pc as usize % MEMORY_SIZE;
+        self.cycle()?;
+        let vx = self.registers[(self.opcode >> 8 & 0xF) as usize];
+        let skipped = (self.pc as usize + MEMORY_SIZE - pc) % MEMORY_SIZE > 2;
+        Ok(timing::vip_cost(self.opcode, vx, skipped))
+    }
+
+    // Fetches and runs the instruction at pc. Tracing needs every opcode, so
+    // it always goes through the interpreter, even with the cached engine on.
+    fn step(&mut self, pc: u16) -> Result<(), Fault> {
+        #[cfg(feature = "std")]
+        {
+            if self.cache.is_some() && self.tracer.is_none() {
+                return self.step_cached(pc);
+            }
+        }
+        let mut opcode: u16 = (self.bus.fetch(pc) as u16) << 8;
+        opcode |= self.bus.fetch((pc + 1) % MEMORY_SIZE as u16) as u16;
+        #[cfg(feature = "std")]
+        {
+            if self.tracer.is_some() {
+                self.trace(opcode);
+            }
+        }
+        self.opcode = opcode;
+        self.execute(instruction::decode(opcode), pc)
+    }
 
-        // Calculate variable used in some opcodes
-        let kk: u8 = (opcode & 0x00FF) as u8;
+    // A write to memory by the game, which may be over code
+    fn store(&mut self, address: u16, value: u8) {
+        self.bus.write(address, value);
+        #[cfg(feature = "std")]
+        {
+            if let Some(ref mut cache) = self.cache {
+                cache.invalidate(address);
+            }
+        }
+    }
 
+    // Carries out an instruction fetched from pc. Anything that can fault is
+    // checked before the machine is changed.
+    fn execute(&mut self, instruction: Instruction, pc: u16) -> Result<(), Fault> {
         // Increment program counter now
-        self.pc += 2;
-
-        // Giant switch statement to determine opcode
-        match opcode & 0xF000 {
-            0x0000 => {
-                match opcode {
-                    0x00E0 => self.clear_screen(),
-                    0x00EE => {
-                        // Saves top of stack to program counter
-                        self.sp -= 1;
-                        self.pc = self.stack[self.sp as usize];
+        self.pc = pc + 2;
+
+        match instruction {
+            Instruction::Cls => self.clear_screen(),
+            Instruction::Ret => {
+                // Saves top of stack to program counter
+                if self.sp == 0 {
+                    match self.out_of_range {
+                        OutOfRange::Wrap => self.sp = self.stack.len() as u8,
+                        OutOfRange::Fault => return Err(Fault::StackUnderflow { pc }),
                     }
-                    _ => panic!("Unknown instruction: {:x}", opcode),
                 }
+                self.sp -= 1;
+                self.pc = self.stack[self.sp as usize];
             }
-            0x1000 => self.pc = opcode & 0x0FFF, // Jump to 0x0nnn
-            0x2000 => {
+            Instruction::Jp(nnn) => self.pc = nnn, // Jump to 0x0nnn
+            Instruction::Call(nnn) => {
                 // Push the program counter onto stack and then jump to 0x0nnn
+                if self.sp as usize == self.stack.len() {
+                    match self.out_of_range {
+                        OutOfRange::Wrap => self.sp = 0,
+                        OutOfRange::Fault => return Err(Fault::StackOverflow { pc }),
+                    }
+                }
                 self.stack[self.sp as usize] = self.pc;
                 self.sp += 1;
-                self.pc = opcode & 0x0FFF;
+                self.pc = nnn;
             }
-            0x3000 => {
-                // Skip next instruction if condition met
-                if kk == self.registers[index] {
+            // Skip next instruction if condition met
+            Instruction::SeByte(x, kk) => {
+                if self.registers[x as usize] == kk {
                     self.pc += 2;
                 }
             }
-            0x4000 => {
-                // Skip next instruction if condition met
-                if kk != self.registers[index] {
+            Instruction::SneByte(x, kk) => {
+                if self.registers[x as usize] != kk {
                     self.pc += 2;
                 }
             }
-            0x5000 => {
-                // Skip next instruction if condition met
-                if self.registers[index_x] == self.registers[index_y] {
+            Instruction::SeReg(x, y) => {
+                if self.registers[x as usize] == self.registers[y as usize] {
                     self.pc += 2;
                 }
             }
-            0x6000 => {
-                self.registers[index] = kk;
+            Instruction::SneReg(x, y) => {
+                if self.registers[x as usize] != self.registers[y as usize] {
+                    self.pc += 2;
+                }
             }
-            0x7000 => {
-                self.registers[index] = self.registers[index].wrapping_add(kk);
+            Instruction::LdByte(x, kk) => self.registers[x as usize] = kk,
+            Instruction::AddByte(x, kk) => {
+                self.registers[x as usize] = self.registers[x as usize].wrapping_add(kk);
             }
-            0x8000 => {
-                match opcode & 0x000F {
-                    // Binary operators
-                    0x0000 => {
-                        self.registers[index_x] = self.registers[index_y];
-                    }
-                    0x0001 => {
-                        self.registers[index_x] |= self.registers[index_y];
-                    }
-                    0x0002 => {
-                        self.registers[index_x] &= self.registers[index_y];
-                    }
-                    0x0003 => {
-                        self.registers[index_x] ^= self.registers[index_y];
-                    }
-                    // Overflow aware operators
-                    0x0004 => {
-                        let (vx, vf) =
-                            self.registers[index_x].overflowing_add(self.registers[index_y]);
-                        self.registers[index_x] = vx;
-                        self.registers[0xF] = vf as u8;
-                    }
-                    0x0005 => {
-                        let (vx, vf) =
-                            self.registers[index_x].overflowing_sub(self.registers[index_y]);
-                        self.registers[index_x] = vx;
-                        self.registers[0xF] = !vf as u8;
-                    }
-                    0x0006 => {
-                        // TODO: Carefully confirm this is not supposed to be index_y
-                        self.registers[0xF] = self.registers[index] & 0x01;
-                        self.registers[index] >>= 1;
-                    }
-                    0x0007 => {
-                        let (vx, vf) =
-                            self.registers[index_y].overflowing_sub(self.registers[index_x]);
-                        self.registers[index_x] = vx;
-                        self.registers[0xF] = !vf as u8;
-                    }
-                    0x000E => {
-                        // Conflicting docs TODO
-                        self.registers[0xF] = self.registers[index] >> 7;
-                        self.registers[index] <<= 1;
-                    }
-                    _ => panic!("Unknown instruction: {:x}", opcode),
+            // Binary operators
+            Instruction::LdReg(x, y) => self.registers[x as usize] = self.registers[y as usize],
+            Instruction::Or(x, y) => {
+                self.registers[x as usize] |= self.registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
                 }
             }
-            0x9000 => {
-                // Skip instruction if condition met
-                if self.registers[index_x] != self.registers[index_y] {
-                    self.pc += 2;
+            Instruction::And(x, y) => {
+                self.registers[x as usize] &= self.registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
+                }
+            }
+            Instruction::Xor(x, y) => {
+                self.registers[x as usize] ^= self.registers[y as usize];
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
                 }
             }
-            0xA000 => self.instruction_reg = 0x0FFF & opcode,
-            0xB000 => self.pc = (0x0FFF & opcode) + self.registers[0] as u16,
-            0xC000 => self.registers[index] = rand::random::<u8>() & kk, // random generator
-            0xD000 => {
+            // Overflow aware operators
+            Instruction::AddReg(x, y) => {
+                let (vx, vf) =
+                    self.registers[x as usize].overflowing_add(self.registers[y as usize]);
+                self.registers[x as usize] = vx;
+                self.registers[0xF] = vf as u8;
+            }
+            Instruction::Sub(x, y) => {
+                let (vx, vf) =
+                    self.registers[x as usize].overflowing_sub(self.registers[y as usize]);
+                self.registers[x as usize] = vx;
+                self.registers[0xF] = !vf as u8;
+            }
+            Instruction::Shr(x, y) => {
+                // The original VIP shifts VY, later interpreters shift VX
+                let source = if self.quirks.shift_vy { y } else { x } as usize;
+                let flag = self.registers[source] & 0x01;
+                self.registers[x as usize] = self.registers[source] >> 1;
+                self.registers[0xF] = flag;
+            }
+            Instruction::Subn(x, y) => {
+                let (vx, vf) =
+                    self.registers[y as usize].overflowing_sub(self.registers[x as usize]);
+                self.registers[x as usize] = vx;
+                self.registers[0xF] = !vf as u8;
+            }
+            Instruction::Shl(x, y) => {
+                // Same VX/VY ambiguity as 8XY6
+                let source = if self.quirks.shift_vy { y } else { x } as usize;
+                let flag = self.registers[source] >> 7;
+                self.registers[x as usize] = self.registers[source] << 1;
+                self.registers[0xF] = flag;
+            }
+            Instruction::LdI(nnn) => self.instruction_reg = nnn,
+            Instruction::JpV0(nnn) => {
+                // SCHIP treats this as BXNN, jumping to XNN + VX
+                let offset = if self.quirks.jump_vx {
+                    self.registers[(nnn >> 8) as usize]
+                } else {
+                    self.registers[0]
+                };
+                self.pc = nnn + offset as u16;
+            }
+            Instruction::Rnd(x, kk) => self.registers[x as usize] = self.random_byte() & kk, // random generator
+            Instruction::Drw(x, y, n) => {
                 // Draw a sprite, detecting collision
-                let height = (0x000F & opcode) as u8;
-                let x = self.registers[index_x];
-                let y = self.registers[index_y];
+                let height = n as usize;
+                if height > 0 {
+                    self.address(height - 1, pc)?;
+                }
+                // The starting position always wraps around the screen
+                let x = self.registers[x as usize] as usize % WIDTH;
+                let y = self.registers[y as usize] as usize % HEIGHT;
                 self.registers[0xF] = 0; // No collision detected initially
+                self.drew = true;
 
-                // Walk the length of the sprite (corresponding to height)
-                for current_height in 0..height {
-                    // Walk each sprite byte from MSB to LSB (corresponding to width)
-                    for current_width in 0..8 {
-                        // Isolate the current bit
-                        let sprite_byte: u8 =
-                            self.memory[(self.instruction_reg + current_height as u16) as usize];
-                        let mut pixel: u8 = 0x80 & (sprite_byte << current_width);
-                        pixel >>= 7;
-
-                        // Check for collision
-                        let y_offset = ((current_height + y) % 32) as usize;
-                        let x_offset = ((current_width + x) % 64) as usize;
-                        if self.screen[y_offset][x_offset] as u8 & pixel == 1 {
-                            self.registers[0xF] = 1;
-                        }
-                        // Pixels are XOR'ed onto the screen
-                        if self.screen[y_offset][x_offset] as u8 ^ pixel == 1 {
-                            self.screen[y_offset][x_offset] = true;
-                        } else {
-                            self.screen[y_offset][x_offset] = false;
-                        }
+                // Each byte of the sprite is a row, XOR'ed onto the screen
+                for row in 0..height {
+                    if self.quirks.clip_sprites && y + row >= HEIGHT {
+                        break;
+                    }
+                    let address = self.address(row, pc)?;
+                    let sprite = self.bus.read(address);
+                    let clip = self.quirks.clip_sprites;
+                    if self.screen.draw(x, (y + row) % HEIGHT, sprite, clip) {
+                        self.registers[0xF] = 1;
                     }
                 }
             }
-            0xE000 => match opcode & 0x00FF {
-                0x009E => {
-                    if self.keys[self.registers[index] as usize] {
-                        self.pc += 2;
-                    }
+            // Only the low nibble of VX picks the key, as the VIP's keypad
+            // scan only looks at those bits
+            Instruction::Skp(x) => {
+                if self.keys[(self.registers[x as usize] & 0xF) as usize] {
+                    self.pc += 2;
                 }
-                0x00A1 => {
-                    if !self.keys[self.registers[index] as usize] {
-                        self.pc += 2;
-                    }
+            }
+            Instruction::Sknp(x) => {
+                if !self.keys[(self.registers[x as usize] & 0xF) as usize] {
+                    self.pc += 2;
                 }
-                _ => panic!("Unknown instruction: {:x}", opcode),
-            },
-            0xF000 => {
-                match opcode & 0x00FF {
-                    0x0007 => self.registers[index] = self.delay_timer,
-                    0x000A => {
-                        // This should "block" until a key is pressed, storing the key
-                        // We "block" by rolling back the PC to this instruction again
-                        // allowing us to give control back to the main game loop to
-                        // grab any keyboard updates
-                        self.pc -= 2;
-                        for (i, key) in self.keys.iter().enumerate() {
-                            if *key {
-                                self.registers[index] = i as u8;
-                                self.pc += 2; // Break out of the waiting loop we've made
-                            }
-                        }
-                    }
-                    0x0015 => self.delay_timer = self.registers[index],
-                    0x0018 => self.sound_timer = self.registers[index],
-                    0x001E => self.instruction_reg += self.registers[index] as u16,
-                    0x0029 => {
-                        // set I = location of sprite registers[index]
-                        let character = self.registers[index];
-                        self.instruction_reg = character as u16 * 5;
-                    }
-                    0x0033 => {
-                        // The interpreter takes the decimal value of Vx, and places
-                        // the hundreds digit in memory at location in I, the tens digit at
-                        // location I+1, and the ones digit at location I+2.
-                        let hundreds = self.registers[index] / 100;
-                        let tens = (self.registers[index] / 10) % 10;
-                        let ones = self.registers[index] % 10;
-                        self.memory[self.instruction_reg as usize] = hundreds;
-                        self.memory[self.instruction_reg as usize + 1] = tens;
-                        self.memory[self.instruction_reg as usize + 2] = ones;
-                    }
-                    0x0055 => {
-                        let end = index + 1;
-                        for i in 0..end {
-                            self.memory[self.instruction_reg as usize + i] = self.registers[i];
-                        }
-                    }
-                    0x0065 => {
-                        let end = index + 1;
-                        for i in 0..end {
-                            self.registers[i] = self.memory[self.instruction_reg as usize + i];
-                        }
+            }
+            Instruction::LdVxDt(x) => self.registers[x as usize] = self.delay_timer,
+            Instruction::LdVxK(x) => {
+                // This should "block" until a key is pressed, storing the key
+                // We "block" by rolling back the PC to this instruction again
+                // allowing us to give control back to the main game loop to
+                // grab any keyboard updates
+                self.pc -= 2;
+                for (i, key) in self.keys.iter().enumerate() {
+                    if *key {
+                        self.registers[x as usize] = i as u8;
+                        self.pc += 2; // Break out of the waiting loop we've made
                     }
-                    _ => panic!("Unknown instruction: {:x}", opcode),
                 }
             }
-            _ => panic!("Unknown instruction: {:x}", opcode),
+            Instruction::LdDtVx(x) => self.delay_timer = self.registers[x as usize],
+            Instruction::LdStVx(x) => self.sound_timer = self.registers[x as usize],
+            Instruction::AddI(x) => {
+                self.instruction_reg = self
+                    .instruction_reg
+                    .wrapping_add(self.registers[x as usize] as u16)
+            }
+            Instruction::LdF(x) => {
+                // set I = location of sprite registers[x]
+                let character = self.registers[x as usize];
+                self.instruction_reg = character as u16 * 5;
+            }
+            Instruction::LdB(x) => {
+                // The interpreter takes the decimal value of Vx, and places
+                // the hundreds digit in memory at location in I, the tens digit at
+                // location I+1, and the ones digit at location I+2.
+                let value = self.registers[x as usize];
+                let addresses = [
+                    self.address(0, pc)?,
+                    self.address(1, pc)?,
+                    self.address(2, pc)?,
+                ];
+                self.store(addresses[0], value / 100);
+                self.store(addresses[1], (value / 10) % 10);
+                self.store(addresses[2], value % 10);
+            }
+            Instruction::LdIVx(x) => {
+                let end = x as usize + 1;
+                self.address(x as usize, pc)?;
+                for i in 0..end {
+                    let address = self.address(i, pc)?;
+                    self.store(address, self.registers[i]);
+                }
+                if self.quirks.increment_i {
+                    self.instruction_reg = self.instruction_reg.wrapping_add(end as u16);
+                }
+            }
+            Instruction::LdVxI(x) => {
+                let end = x as usize + 1;
+                self.address(x as usize, pc)?;
+                for i in 0..end {
+                    let address = self.address(i, pc)?;
+                    self.registers[i] = self.bus.read(address);
+                }
+                if self.quirks.increment_i {
+                    self.instruction_reg = self.instruction_reg.wrapping_add(end as u16);
+                }
+            }
+            // The VIP only looks at the top nibble of 5XYN and 9XYN, so
+            // those the decoder doesn't know run as 5XY0 and 9XY0
+            Instruction::Unknown(opcode) if matches!(opcode >> 12, 0x5 | 0x9) => {
+                return self.execute(instruction::decode(opcode & 0xFFF0), pc)
+            }
+            // Machine code routines can't be run
+            Instruction::Sys(opcode) | Instruction::Unknown(opcode) => {
+                return Err(Fault::UnknownOpcode { pc, opcode })
+            }
         }
+        Ok(())
     }
 }
 
@@ -339,7 +775,7 @@ mod tests {
     use super::*;
 
     // Helper function to convert opcode vector to u8 vector
-    fn opcodes_to_buffer(opcodes: &Vec<u16>) -> Vec<u8> {
+    fn opcodes_to_buffer(opcodes: &[u16]) -> Vec<u8> {
         let mut buffer: Vec<u8> = Vec::new();
         for opcode in opcodes.iter() {
             buffer.push(((opcode & 0xFF00) >> 8) as u8);
@@ -351,49 +787,49 @@ mod tests {
     #[test]
     fn test_clear_screen_works() {
         let mut chip8 = Chip8::new();
-        chip8.screen[1][2] = true;
+        chip8.screen.set(2, 1, true);
         chip8.clear_screen();
-        assert!(!chip8.screen[1][2]);
+        assert!(!chip8.screen.pixel(2, 1));
     }
 
     #[test]
     fn test_load_rom() {
         let mut chip8 = Chip8::new();
         let rom: Vec<u16> = vec![0x0001, 0x0203]; // Random ROM
-        chip8.load_rom(opcodes_to_buffer(&rom));
-        assert_eq!(chip8.memory[0x200], 0x00);
-        assert_eq!(chip8.memory[0x201], 0x01);
-        assert_eq!(chip8.memory[0x202], 0x02);
-        assert_eq!(chip8.memory[0x203], 0x03);
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
+        assert_eq!(chip8.bus.memory()[0x200], 0x00);
+        assert_eq!(chip8.bus.memory()[0x201], 0x01);
+        assert_eq!(chip8.bus.memory()[0x202], 0x02);
+        assert_eq!(chip8.bus.memory()[0x203], 0x03);
     }
 
     #[test]
     fn test_clear_screen_instruction() {
         let mut chip8 = Chip8::new();
-        chip8.screen[1][2] = true;
+        chip8.screen.set(2, 1, true);
         let rom: Vec<u16> = vec![0x00E0]; // Clear screen instruction
-        chip8.load_rom(opcodes_to_buffer(&rom));
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
 
-        assert!(chip8.screen[1][2]);
-        chip8.cycle();
+        assert!(chip8.screen.pixel(2, 1));
+        chip8.cycle().unwrap();
         assert_eq!(chip8.pc, 0x202);
-        assert!(!chip8.screen[1][2]);
+        assert!(!chip8.screen.pixel(2, 1));
     }
 
     #[test]
     fn test_push_and_pop_stack() {
         let mut chip8 = Chip8::new();
         let rom: Vec<u16> = vec![0x2666]; // Push pc to stack, jump to 0x666
-        chip8.load_rom(opcodes_to_buffer(&rom));
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
 
         // Lazily insert a pop stack at the jump address
-        chip8.memory[0x666] = 0x00;
-        chip8.memory[0x667] = 0xEE;
-        chip8.cycle();
+        chip8.bus.memory_mut()[0x666] = 0x00;
+        chip8.bus.memory_mut()[0x667] = 0xEE;
+        chip8.cycle().unwrap();
         assert_eq!(chip8.sp, 1);
         assert_eq!(chip8.stack[0], 0x202);
         assert_eq!(chip8.pc, 0x666);
-        chip8.cycle();
+        chip8.cycle().unwrap();
         assert_eq!(chip8.sp, 0);
         assert_eq!(chip8.pc, 0x202);
     }
@@ -402,8 +838,8 @@ mod tests {
     fn test_jump() {
         let mut chip8 = Chip8::new();
         let rom: Vec<u16> = vec![0x1666]; // Push pc to stack, jump to 0x666
-        chip8.load_rom(opcodes_to_buffer(&rom));
-        chip8.cycle();
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
+        chip8.cycle().unwrap();
         assert_eq!(chip8.pc, 0x666);
     }
 
@@ -412,10 +848,10 @@ mod tests {
         let mut chip8 = Chip8::new();
         // reg[0] -= reg[1], reg[0xF] = !borrow
         let rom: Vec<u16> = vec![0x8015];
-        chip8.load_rom(opcodes_to_buffer(&rom));
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
         chip8.registers[0] = 10;
         chip8.registers[1] = 3;
-        chip8.cycle();
+        chip8.cycle().unwrap();
         assert_eq!(chip8.registers[0], 7);
         assert_eq!(chip8.registers[0xF], 1);
     }
@@ -425,10 +861,10 @@ mod tests {
         let mut chip8 = Chip8::new();
         // reg[0] -= reg[1], reg[0xF] = !borrow
         let rom: Vec<u16> = vec![0x8015];
-        chip8.load_rom(opcodes_to_buffer(&rom));
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
         chip8.registers[0] = 0;
         chip8.registers[1] = 1;
-        chip8.cycle();
+        chip8.cycle().unwrap();
         assert_eq!(chip8.registers[0], 255);
         assert_eq!(chip8.registers[0xF], 0);
     }
@@ -438,10 +874,10 @@ mod tests {
         let mut chip8 = Chip8::new();
         // reg[0] = reg[1] - reg[0], reg[0xF] = !borrow
         let rom: Vec<u16> = vec![0x8017];
-        chip8.load_rom(opcodes_to_buffer(&rom));
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
         chip8.registers[0] = 3;
         chip8.registers[1] = 10;
-        chip8.cycle();
+        chip8.cycle().unwrap();
         assert_eq!(chip8.registers[0], 7);
         assert_eq!(chip8.registers[0xF], 1);
     }
@@ -451,11 +887,230 @@ mod tests {
         let mut chip8 = Chip8::new();
         // reg[0] = reg[1] - reg[0], reg[0xF] = !borrow
         let rom: Vec<u16> = vec![0x8017];
-        chip8.load_rom(opcodes_to_buffer(&rom));
+        chip8.load_rom(&opcodes_to_buffer(&rom)).unwrap();
         chip8.registers[1] = 0;
         chip8.registers[0] = 1;
-        chip8.cycle();
+        chip8.cycle().unwrap();
         assert_eq!(chip8.registers[0], 255);
         assert_eq!(chip8.registers[0xF], 0);
     }
+
+    #[test]
+    fn test_shift_quirk() {
+        // reg[0] = reg[1] >> 1
+        let rom = opcodes_to_buffer(&[0x8016]);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        chip8.registers[0] = 4;
+        chip8.registers[1] = 3;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.registers[0], 2);
+        assert_eq!(chip8.registers[0xF], 0);
+
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        chip8.quirks.shift_vy = true;
+        chip8.registers[0] = 4;
+        chip8.registers[1] = 3;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.registers[0], 1);
+        assert_eq!(chip8.registers[0xF], 1);
+    }
+
+    #[test]
+    fn test_increment_i_quirk() {
+        // Store V0..V2 at I
+        let rom = opcodes_to_buffer(&[0xF255]);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        chip8.quirks.increment_i = true;
+        chip8.instruction_reg = 0x300;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.instruction_reg, 0x303);
+    }
+
+    #[test]
+    fn test_jump_vx_quirk() {
+        let rom = opcodes_to_buffer(&[0xB210]);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        chip8.quirks.jump_vx = true;
+        chip8.registers[0] = 1;
+        chip8.registers[2] = 4;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.pc, 0x214);
+    }
+
+    #[test]
+    fn test_clip_quirk() {
+        // Draw the 0 glyph at (62, 0)
+        let rom = opcodes_to_buffer(&[0xD015]);
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&rom).unwrap();
+        chip8.quirks.clip_sprites = true;
+        chip8.registers[0] = 62;
+        chip8.cycle().unwrap();
+        assert!(chip8.screen.pixel(62, 0));
+        assert!(!chip8.screen.pixel(0, 0));
+    }
+
+    #[test]
+    fn test_rom_hash() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(b"abc").unwrap();
+        assert_eq!(chip8.rom_hash(), sha1::sha1(b"abc"));
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        // reg[0] = random & 0xFF, twice
+        let rom = opcodes_to_buffer(&[0xC0FF, 0xC1FF]);
+        let mut first = Chip8::new();
+        let mut second = Chip8::new();
+        for chip8 in [&mut first, &mut second].iter_mut() {
+            chip8.load_rom(&rom).unwrap();
+            chip8.seed(42);
+            chip8.cycle().unwrap();
+            chip8.cycle().unwrap();
+        }
+        assert_eq!(first.registers[..2], second.registers[..2]);
+        assert_eq!(first.state_hash(), second.state_hash());
+        first.keys[3] = true;
+        assert_eq!(first.state_hash(), second.state_hash());
+        first.seed(7);
+        assert_ne!(first.state_hash(), second.state_hash());
+    }
+
+    #[test]
+    fn test_with_seed() {
+        // reg[0] = random & 0xFF
+        let rom = opcodes_to_buffer(&[0xC0FF]);
+        let mut seeded = Chip8::with_seed(Ram::new(), 42);
+        let mut chip8 = Chip8::new();
+        chip8.seed(42);
+        for chip8 in [&mut seeded, &mut chip8].iter_mut() {
+            chip8.load_rom(&rom).unwrap();
+            chip8.cycle().unwrap();
+        }
+        assert_eq!(seeded.state_hash(), chip8.state_hash());
+    }
+
+    #[test]
+    fn test_trace_hook() {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&opcodes_to_buffer(&[0x6A2B, 0x1200]))
+            .unwrap();
+        let path = ::std::env::temp_dir().join(format!("rust8-trace-{}", ::std::process::id()));
+        let out = ::std::fs::File::create(&path).unwrap();
+        chip8.set_tracer(Some(Tracer::new(Box::new(out), trace::Format::Text)));
+        chip8.run_frame(3).unwrap();
+        chip8.take_tracer().unwrap().finish().unwrap();
+        assert_eq!(chip8.cycles(), 3);
+
+        let text = ::std::fs::read_to_string(&path).unwrap();
+        ::std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].contains(" 200: 6A2B  LD VA, 0x2B "));
+        assert!(lines[1].contains(" 202: 1200  JP 0x200 "));
+        assert!(lines[2].contains("V:00 00 00 00 00 00 00 00 00 00 2B"));
+    }
+
+    #[test]
+    fn test_oversized_rom() {
+        let mut chip8 = Chip8::new();
+        assert!(chip8.load_rom(&[0; MAX_ROM_SIZE]).is_ok());
+        assert_eq!(
+            chip8.load_rom(&[0; MAX_ROM_SIZE + 1]),
+            Err(RomTooBig {
+                size: MAX_ROM_SIZE + 1
+            })
+        );
+    }
+
+    #[test]
+    fn test_stack_faults() {
+        // Calls itself forever
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&opcodes_to_buffer(&[0x2200])).unwrap();
+        for _ in 0..16 {
+            chip8.cycle().unwrap();
+        }
+        assert_eq!(chip8.cycle(), Err(Fault::StackOverflow { pc: 0x200 }));
+        assert_eq!(chip8.pc, 0x200);
+        assert_eq!(chip8.sp, 16);
+
+        chip8.out_of_range = OutOfRange::Wrap;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.sp, 1);
+
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&opcodes_to_buffer(&[0x00EE])).unwrap();
+        assert_eq!(chip8.cycle(), Err(Fault::StackUnderflow { pc: 0x200 }));
+        chip8.out_of_range = OutOfRange::Wrap;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.sp, 15);
+    }
+
+    #[test]
+    fn test_memory_faults() {
+        // Store V0..V2 at I = 0xFFE
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&opcodes_to_buffer(&[0xAFFE, 0xF255]))
+            .unwrap();
+        chip8.registers[..3].copy_from_slice(&[1, 2, 3]);
+        chip8.cycle().unwrap();
+        assert_eq!(
+            chip8.cycle(),
+            Err(Fault::MemoryOutOfRange {
+                pc: 0x202,
+                address: 0x1000
+            })
+        );
+        // Nothing was written before the fault
+        assert_eq!(chip8.bus.memory()[0xFFE], 0);
+        assert_eq!(chip8.pc, 0x202);
+
+        chip8.out_of_range = OutOfRange::Wrap;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.bus.memory()[0xFFE..], [1, 2]);
+        assert_eq!(chip8.bus.memory()[0], 3);
+    }
+
+    #[test]
+    fn test_pc_faults() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&opcodes_to_buffer(&[0x1FFE])).unwrap();
+        chip8.cycle().unwrap();
+        // 0xFFE holds 0x0000, which would run on to 0x1000
+        chip8.bus.memory_mut()[0xFFE] = 0x60;
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.cycle(), Err(Fault::PcOutOfRange { pc: 0x1000 }));
+
+        // Wrapping runs on from 0x000, which normally holds the font
+        chip8.out_of_range = OutOfRange::Wrap;
+        chip8.bus.memory_mut()[..2].copy_from_slice(&[0x60, 0x00]);
+        chip8.cycle().unwrap();
+        assert_eq!(chip8.pc, 0x002);
+    }
+
+    #[test]
+    fn test_unknown_opcode() {
+        let mut chip8 = Chip8::new();
+        chip8
+            .load_rom(&opcodes_to_buffer(&[0x6001, 0x8128]))
+            .unwrap();
+        chip8.cycle().unwrap();
+        assert_eq!(
+            chip8.cycle(),
+            Err(Fault::UnknownOpcode {
+                pc: 0x202,
+                opcode: 0x8128
+            })
+        );
+        assert_eq!(chip8.pc(), 0x202);
+        assert_eq!(chip8.registers()[0], 1);
+    }
 }
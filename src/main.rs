@@ -1,207 +1,987 @@
+extern crate rand;
 extern crate rust8;
 extern crate sdl2;
 
-use std::env;
-use std::fs::File;
+mod options;
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
 use std::io::prelude::*;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::{Duration, Instant};
+
+use rust8::analysis;
+use rust8::browser::{Action, RomBrowser};
+use rust8::cheat::{Cheat, CheatFile, CheatList, Filter, Search};
+use rust8::decompile;
+use rust8::disasm;
+use rust8::display::Framebuffer;
+use rust8::fault::Fault;
+use rust8::host::sdl::{hex_key, SdlAudio, SdlDisplay, SdlInput};
+use rust8::host::{AudioSink, Clock, InputSource, RealTime};
+use rust8::image::Image;
+use rust8::movie::{Movie, Player, Recorder};
+use rust8::palette::Palette;
+use rust8::remote::{Request, Server};
+use rust8::romdb::RomDb;
+use rust8::sha1;
+use rust8::text;
+use rust8::trace::Tracer;
+
+use options::{usage, Command, Options, Settings};
 
-use sdl2::audio::AudioCallback;
-use sdl2::audio::AudioSpecDesired;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
-use sdl2::keyboard::Scancode;
-use sdl2::pixels::Color;
-use sdl2::rect::Point;
+
+// Calculate some constants
+const CLOCK_FREQUENCY: u32 = 500; // Hz
+const FRAMERATE: u32 = 60;
+const CLOCK_PERIOD: u32 = 1000 / CLOCK_FREQUENCY; // milliseconds
+const CYCLES_PER_FRAME: u32 = (1000 / FRAMERATE) / CLOCK_PERIOD;
 
 fn main() {
-    // Read in game
-    let args: Vec<String> = env::args().collect();
-    let filename = &args[1];
-    let mut f = File::open(filename).expect("File not found");
-    let mut buffer: Vec<u8> = Vec::new();
-    f.read_to_end(&mut buffer).unwrap();
-    let mut chip8 = rust8::Chip8::new();
-    chip8.load_rom(buffer);
+    let settings = Settings::from_args();
+    let options = settings.options();
+    let db = load_romdb(&options);
 
-    // Setup the window
-    if !sdl2::hint::set("SDL_HINT_RENDER_SCALE_QUALITY", "0") {
-        println!("Failed to set render scaling method");
+    // Read in game. Without one the window opens on the ROM browser.
+    let rom = options.rom.as_ref().map(|path| {
+        read_rom(Path::new(path)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        })
+    });
+
+    if rom.is_none() && (options.record.is_some() || options.play.is_some()) {
+        eprintln!("Movies need a ROM");
+        usage();
     }
-    let sdl_context = sdl2::init().unwrap();
-    let audio_subsystem = sdl_context.audio().unwrap();
-    let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1), // mono
-        samples: None,     // default sample size
-    };
 
-    let device = audio_subsystem
-        .open_playback(None, &desired_spec, |spec| {
-            // initialize the audio callback
-            SquareWave {
-                phase_inc: 440.0 / spec.freq as f32,
-                phase: 0.0,
-                volume: 0.25,
-            }
-        })
-        .unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window("rust8", 640, 320)
-        .position_centered()
-        .build()
-        .unwrap();
-    let mut canvas = window.into_canvas().build().unwrap();
-    canvas.set_logical_size(64, 32).unwrap();
-    canvas.set_draw_color(Color::RGB(0, 255, 255));
-    canvas.clear();
-    canvas.present();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-
-    // Calculate some constants
-    let mut ticks = 0;
-    let clock_frequency = 500; // Hz
-    let framerate = 60;
-    let clock_period = 1000 / clock_frequency; // milliseconds
-    let ticks_per_frame = (1000 / framerate) / clock_period;
-
-    // Run the game loop
-    'running: loop {
-        ticks += 1;
+    match options.command {
+        Command::Bench => run_bench(&settings, &db, &rom.unwrap_or_else(|| usage())),
+        Command::Disasm => disassemble(&options, &rom.unwrap_or_else(|| usage())),
+        Command::Decompile => {
+            let rom = rom.unwrap_or_else(|| usage());
+            print!("{}", decompile::decompile(&analysis::analyze(&rom)))
+        }
+        Command::Run if options.headless && options.remote.is_some() => {
+            run_remote(&settings, &db, rom)
+        }
+        Command::Run if options.headless => {
+            run_headless(&settings, &db, &rom.unwrap_or_else(|| usage()))
+        }
+        Command::Run => run_sdl(&settings, &db, rom),
+    }
+}
 
-        // Handle events from the user
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
-                } => break 'running,
-                _ => {}
-            }
+// The bundled ROM database with the user's own entries on top
+fn load_romdb(options: &Options) -> RomDb {
+    let mut db = RomDb::bundled();
+    let user = options::config_dir()
+        .map(|dir| dir.join("romdb.ini"))
+        .filter(|path| path.exists());
+    for path in user.into_iter().chain(options.romdb.clone()) {
+        match options::read_text(&path).and_then(|text| RomDb::parse(&text)) {
+            Ok(extra) => db.merge(extra),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
         }
-        update_keys(&event_pump, &mut chip8);
+    }
+    db
+}
 
-        chip8.cycle();
+// The user's cheats, with those in the --cheats file after them
+fn load_cheats(options: &Options) -> CheatFile {
+    let mut cheats = CheatFile::default();
+    let user = options::config_dir()
+        .map(|dir| dir.join("cheats.ini"))
+        .filter(|path| path.exists());
+    for path in user.into_iter().chain(options.cheats.clone()) {
+        match options::read_text(&path).and_then(|text| CheatFile::parse(&text)) {
+            Ok(extra) => cheats.merge(extra),
+            Err(e) => eprintln!("{}: {}", path.display(), e),
+        }
+    }
+    cheats
+}
 
-        // the 60 Hz mark
-        if ticks == ticks_per_frame {
-            ticks = 0;
-            chip8.update_timers();
+// The game's cheats, saying how many it has
+fn find_cheats(file: &CheatFile, chip8: &rust8::Chip8) -> CheatList {
+    let cheats = file.lookup(&chip8.rom_hash());
+    if !cheats.is_empty() {
+        eprintln!(
+            "{} cheats for this ROM, F6 turns them on and off",
+            cheats.cheats.len()
+        );
+    }
+    cheats
+}
 
-            // Handle audio
-            if chip8.sound_timer == 0 {
-                device.pause();
-            } else {
-                device.resume();
-            }
+// Adds a cheat to the user's cheat file, so the game has it next time
+fn save_cheat(hash: &sha1::Digest, cheat: &Cheat) -> Result<(), String> {
+    let dir = options::config_dir().ok_or_else(|| "No config directory".to_string())?;
+    let path = dir.join("cheats.ini");
+    fs::create_dir_all(&dir)
+        .and_then(|()| OpenOptions::new().create(true).append(true).open(&path))
+        .and_then(|mut file| writeln!(file, "\n[{}]\n{}", sha1::to_hex(hash), cheat))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
 
-            // Draw black to buffer
-            canvas.set_draw_color(Color::RGB(0, 0, 0));
-            canvas.clear();
+// Narrows down a RAM search, printing what is left once there are few
+// enough matches to go through by hand
+fn narrow_search(search: &mut Search, chip8: &rust8::Chip8, filter: Filter) -> String {
+    search.filter(chip8, filter);
+    if search.len() <= SEARCH_LISTED {
+        for &(target, value) in search.matches() {
+            eprintln!("{} = {:02X}", target, value);
+        }
+    }
+    format!("{} MATCHES", search.len())
+}
 
-            // Update with current video buffer
-            canvas.set_draw_color(Color::RGB(255, 255, 255));
-            for (y, row) in chip8.screen.iter().enumerate() {
-                for (x, pixel) in row.iter().enumerate() {
-                    if *pixel {
-                        canvas.draw_point(Point::new(x as i32, y as i32)).unwrap();
-                    }
+fn read_rom(path: &Path) -> Result<Vec<u8>, String> {
+    let mut buffer: Vec<u8> = Vec::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_end(&mut buffer))
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if buffer.len() > rust8::MAX_ROM_SIZE {
+        return Err(format!(
+            "{} is {} bytes, too big for chip-8 memory which holds {}",
+            path.display(),
+            buffer.len(),
+            rust8::MAX_ROM_SIZE
+        ));
+    }
+    Ok(buffer)
+}
+
+// Title for the window, showing which game is running
+fn window_title(path: &Path) -> String {
+    match path.file_name() {
+        Some(name) => format!("rust8 - {}", name.to_string_lossy()),
+        None => "rust8".to_string(),
+    }
+}
+
+// A fresh machine with the game loaded, ready to run
+fn machine(rom: &[u8], options: &Options) -> rust8::Chip8 {
+    let mut chip8 = rust8::Chip8::new();
+    chip8
+        .load_rom(rom)
+        .expect("ROM size is checked when it is read");
+    chip8.quirks = options.quirks;
+    chip8.out_of_range = options.out_of_range;
+    chip8.timing = options.timing;
+    chip8.set_engine(options.engine);
+    chip8
+}
+
+// Starts a new game, looking it up in the ROM database for the settings it
+// needs
+fn boot(settings: &Settings, db: &RomDb, rom: &[u8]) -> (rust8::Chip8, Options) {
+    let mut chip8 = machine(rom, &settings.options());
+    let hash = sha1::to_hex(&chip8.rom_hash());
+    let game = db.lookup(&chip8.rom_hash());
+    match game {
+        Some(game) => {
+            eprintln!(
+                "ROM {}: {}",
+                hash,
+                game.title.as_ref().map_or("untitled", |t| t.as_str())
+            );
+            if let Some(ref platform) = game.platform {
+                if platform != "chip-8" {
+                    eprintln!(
+                        "This game was written for {}, which rust8 does not fully support",
+                        platform
+                    );
                 }
             }
-
-            // Draw to screen
-            canvas.present();
         }
+        None => eprintln!("ROM {} is not in the ROM database", hash),
+    }
+    let options = settings.options_for(game);
+    chip8.quirks = options.quirks;
+    chip8.out_of_range = options.out_of_range;
+    chip8.timing = options.timing;
+    (chip8, options)
+}
 
-        // Sleep till next cycle
-        ::std::thread::sleep(Duration::from_millis(clock_period));
+fn load_movie(path: &Path) -> Movie {
+    match options::read_text(path).and_then(|text| Movie::parse(&text)) {
+        Ok(movie) => movie,
+        Err(e) => {
+            eprintln!("{}: {}", path.display(), e);
+            process::exit(1);
+        }
     }
 }
 
-// Update the chip8's keys
-fn update_keys(e: &sdl2::EventPump, chip8: &mut rust8::Chip8) {
-    for key in &mut chip8.keys {
-        *key = false;
+fn save_movie(movie: &Movie, path: &Path) {
+    let result = File::create(path).and_then(|mut f| movie.write(&mut f));
+    match result {
+        Ok(()) => eprintln!(
+            "Saved movie of {} frames to {}",
+            movie.frames,
+            path.display()
+        ),
+        Err(e) => eprintln!("Failed to save movie to {}: {}", path.display(), e),
     }
-    for scancode in e.keyboard_state().pressed_scancodes() {
-        // This basically hardcodes the controls to use a pseudo
-        // hexadecimal input. Basically a 4x4 keyboard, starting with
-        // 1-4, then Q-R, A-F, Z-V
-        match scancode {
-            Scancode::Num1 => {
-                chip8.keys[1] = true;
-            }
-            Scancode::Num2 => {
-                chip8.keys[2] = true;
-            }
-            Scancode::Num3 => {
-                chip8.keys[3] = true;
+}
+
+// The instruction tracer asked for on the command line, if any
+fn start_trace(options: &Options) -> Option<Tracer> {
+    let path = options.trace.as_ref()?;
+    let out: Box<dyn Write> = if path == "-" {
+        Box::new(io::stdout())
+    } else {
+        match File::create(path) {
+            Ok(f) => Box::new(io::BufWriter::new(f)),
+            Err(e) => {
+                eprintln!("Failed to create {}: {}", path, e);
+                process::exit(1);
             }
-            Scancode::Num4 => {
-                chip8.keys[0xC] = true;
+        }
+    };
+    let mut tracer = Tracer::new(out, options.trace_format);
+    tracer.set_addresses(options.trace_addresses.clone());
+    tracer.set_cycles(options.trace_cycles.clone());
+    Some(tracer)
+}
+
+fn finish_trace(chip8: &mut rust8::Chip8) {
+    if let Some(tracer) = chip8.take_tracer() {
+        if let Err(e) = tracer.finish() {
+            eprintln!("Failed to write trace: {}", e);
+        }
+    }
+}
+
+// Sets up a session for the movie options: a new recording using the
+// game's settings, or a movie to play back with the machine it starts from
+fn start_movie(options: &Options, rom: &[u8], chip8: &mut rust8::Chip8) -> Session {
+    if let Some(ref path) = options.play {
+        let movie = load_movie(path);
+        *chip8 = movie.start(rom).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path.display(), e);
+            process::exit(1);
+        });
+        chip8.set_engine(options.engine);
+        return Session::Playing(Player::new(movie));
+    }
+    if options.record.is_some() {
+        let movie = Movie::new(
+            chip8.rom_hash(),
+            rand::random::<u64>(),
+            options.quirks,
+            options.cycles,
+            options.out_of_range,
+            options.timing,
+        );
+        *chip8 = movie.start(rom).unwrap();
+        chip8.set_engine(options.engine);
+        return Session::Recording(Recorder::new(movie));
+    }
+    Session::Free
+}
+
+// Whether the machine is being driven by the user, recorded or played back
+// from a movie
+enum Session {
+    Free,
+    Recording(Recorder),
+    Playing(Player),
+}
+
+impl Session {
+    fn run_frame(&mut self, chip8: &mut rust8::Chip8, cycles: u32) -> Result<(), Fault> {
+        match *self {
+            Session::Free => chip8.run_frame(cycles),
+            Session::Recording(ref mut recorder) => recorder.run_frame(chip8),
+            Session::Playing(ref mut player) => player.run_frame(chip8),
+        }
+    }
+
+    fn is_free(&self) -> bool {
+        matches!(*self, Session::Free)
+    }
+
+    fn status(&self) -> Option<String> {
+        match *self {
+            Session::Free => None,
+            Session::Recording(ref recorder) => Some(format!("REC {}", recorder.frame())),
+            Session::Playing(ref player) => {
+                Some(format!("PLAY {}/{}", player.frame(), player.movie().frames))
             }
-            Scancode::Q => {
-                chip8.keys[4] = true;
+        }
+    }
+}
+
+// Turns the filtered framebuffer into the final scaled picture with any
+// post-processing effects applied
+fn render(options: &Options, palette: &Palette, framebuffer: &Framebuffer) -> Image {
+    let image = framebuffer.to_image(palette.foreground(), palette.background());
+    let mut scaled = image.scale(options.scale);
+    options.effects.apply(&mut scaled, options.scale);
+    scaled
+}
+
+fn save_screenshot(image: &Image, path: &str) {
+    let result = File::create(path).and_then(|mut f| image.write_ppm(&mut f));
+    match result {
+        Ok(()) => eprintln!("Saved screenshot to {}", path),
+        Err(e) => eprintln!("Failed to save screenshot to {}: {}", path, e),
+    }
+}
+
+// Runs the game for a fixed number of frames as fast as possible
+fn run_headless(settings: &Settings, db: &RomDb, rom: &[u8]) {
+    let (mut chip8, options) = boot(settings, db, rom);
+    let options = &options;
+    let mut session = start_movie(options, rom, &mut chip8);
+    chip8.set_tracer(start_trace(options));
+    // A movie is played to its end rather than for a set number of frames
+    let frames = match session {
+        Session::Playing(ref player) => player.movie().frames,
+        _ => options.frames,
+    };
+    let mut framebuffer = Framebuffer::new(options.filter);
+    let mut failed = false;
+    for _ in 0..frames {
+        let result = session.run_frame(&mut chip8, options.cycles);
+        framebuffer.update(&chip8.screen, 1000.0 / FRAMERATE as f32);
+        if let Err(fault) = result {
+            eprintln!("{}", fault);
+            failed = true;
+            break;
+        }
+    }
+    finish_trace(&mut chip8);
+    if let Some(ref path) = options.screenshot {
+        save_screenshot(&render(options, &options.palette(), &framebuffer), path);
+    }
+    match session {
+        // A movie that faulted where its recording did still passes
+        Session::Playing(ref player) => match player.verify(&chip8) {
+            Ok(()) => {
+                eprintln!("Movie verified");
+                failed = false;
             }
-            Scancode::W => {
-                chip8.keys[5] = true;
+            Err(e) => {
+                eprintln!("Movie failed to verify: {}", e);
+                failed = true;
             }
-            Scancode::E => {
-                chip8.keys[6] = true;
+        },
+        Session::Recording(recorder) => {
+            save_movie(&recorder.finish(&chip8), options.record.as_ref().unwrap())
+        }
+        Session::Free => {}
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+// Starts the remote control server asked for on the command line, if any
+fn start_server(options: &Options) -> Option<Server> {
+    let endpoint = options.remote.as_ref()?;
+    match Server::start(endpoint) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            eprintln!("Failed to listen on {}: {}", endpoint, e);
+            process::exit(1);
+        }
+    }
+}
+
+// Runs without a window, doing nothing but carry out remote commands until
+// told to quit or the client on standard input goes away
+fn run_remote(settings: &Settings, db: &RomDb, mut rom: Option<Vec<u8>>) {
+    let (mut chip8, mut options) = match rom {
+        Some(ref rom) => boot(settings, db, rom),
+        None => (rust8::Chip8::new(), settings.options()),
+    };
+    let mut server = start_server(&options).unwrap();
+    while let Some(call) = server.wait() {
+        let result = match call.request.clone() {
+            Ok(Request::Quit) => {
+                call.respond_last(Ok(String::new()));
+                return;
             }
-            Scancode::R => {
-                chip8.keys[0xD] = true;
+            Ok(Request::Load(path)) => read_rom(&path).map(|data| {
+                let (new_chip8, new_options) = boot(settings, db, &data);
+                chip8 = new_chip8;
+                options = new_options;
+                rom = Some(data);
+                server.hold_keys(&mut chip8.keys);
+                String::new()
+            }),
+            Ok(Request::Reset) => match rom {
+                Some(ref rom) => {
+                    chip8 = machine(rom, &options);
+                    server.hold_keys(&mut chip8.keys);
+                    Ok(String::new())
+                }
+                None => Err("No ROM loaded".to_string()),
+            },
+            Ok(request) => server.handle(&mut chip8, &request, options.cycles),
+            Err(e) => Err(e),
+        };
+        call.respond(result);
+    }
+}
+
+// Prints a listing of the ROM with code and data told apart, or its
+// control-flow graph
+fn disassemble(options: &Options, rom: &[u8]) {
+    let analysis = analysis::analyze(rom);
+    if options.dot {
+        print!("{}", analysis.to_dot());
+    } else {
+        print!("{}", disasm::listing(&analysis));
+    }
+}
+
+// Runs the game flat out, without drawing or waiting for frames, and
+// reports how fast the core went. No keys are pressed.
+fn run_bench(settings: &Settings, db: &RomDb, rom: &[u8]) {
+    let (mut chip8, options) = boot(settings, db, rom);
+    let limit = Duration::from_secs(options.seconds);
+    let start = Instant::now();
+    let mut frames: u64 = 0;
+    // A second of frames at a time, as reading the clock every frame would
+    // cost more than running a frame at the default speed
+    while start.elapsed() < limit {
+        for _ in 0..FRAMERATE {
+            if let Err(fault) = chip8.run_frame(options.cycles) {
+                eprintln!("{}", fault);
+                process::exit(1);
             }
-            Scancode::A => {
-                chip8.keys[7] = true;
+        }
+        frames += FRAMERATE as u64;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    println!(
+        "Ran {} frames of {} instructions in {:.2} s with the {} engine",
+        frames, options.cycles, seconds, options.engine
+    );
+    println!(
+        "{:.0} frames/s, {:.0}x real time",
+        frames as f64 / seconds,
+        frames as f64 / seconds / FRAMERATE as f64
+    );
+    println!(
+        "{:.1} million instructions/s",
+        chip8.cycles() as f64 / seconds / 1e6
+    );
+}
+
+// Speed multipliers stepped through with the - and = keys
+const SPEEDS: [f32; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+// Speed while the fast forward key is held down
+const TURBO_SPEED: f32 = 8.0;
+// RAM searches with this many matches or fewer print them
+const SEARCH_LISTED: usize = 16;
+// How long hotkey messages stay on screen
+const MESSAGE_TIME: Duration = Duration::from_secs(2);
+
+// Emulation state driven by the hotkeys
+struct Controls {
+    paused: bool,
+    // Run a single frame while paused
+    advance: bool,
+    speed: usize,
+    turbo: bool,
+    cycles: u32,
+    // Fractional frames carried over between real frames in slow motion
+    pending: f32,
+    message: Option<(String, Instant)>,
+}
+
+impl Controls {
+    fn new(cycles: u32) -> Controls {
+        Controls {
+            paused: false,
+            advance: false,
+            speed: NORMAL_SPEED,
+            turbo: false,
+            cycles,
+            pending: 0.0,
+            message: None,
+        }
+    }
+
+    fn speed(&self) -> f32 {
+        if self.turbo {
+            TURBO_SPEED
+        } else {
+            SPEEDS[self.speed]
+        }
+    }
+
+    // Shows a message on screen for a short time
+    fn notify(&mut self, message: String) {
+        self.message = Some((message, Instant::now()));
+    }
+
+    // How many emulated frames to run during this real frame
+    fn frames_to_run(&mut self) -> u32 {
+        if self.paused {
+            let frames = self.advance as u32;
+            self.advance = false;
+            return frames;
+        }
+        self.pending += self.speed();
+        let frames = self.pending as u32;
+        self.pending -= frames as f32;
+        frames
+    }
+
+    // Lines for the on screen indicator, the current state first and then
+    // any recent message
+    fn status(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.paused {
+            lines.push("PAUSED".to_string());
+        }
+        if self.speed() != 1.0 {
+            lines.push(format!("SPEED {}", speed_label(self.speed())));
+        }
+        if let Some((message, shown)) = self.message.take() {
+            if shown.elapsed() < MESSAGE_TIME {
+                lines.push(message.clone());
+                self.message = Some((message, shown));
             }
-            Scancode::S => {
-                chip8.keys[8] = true;
+        }
+        lines
+    }
+}
+
+fn speed_label(speed: f32) -> String {
+    if speed >= 1.0 {
+        format!("{}X", speed)
+    } else {
+        format!("1/{}X", 1.0 / speed)
+    }
+}
+
+// Draws the indicator lines in the top left corner, on a box of the
+// background colour so they stay readable over the game
+fn draw_status(image: &mut Image, lines: &[String], palette: &Palette, scale: usize) {
+    let size = (scale / 4).max(1);
+    let margin = size * 2;
+    let line_height = text::height(size) + size * 2;
+    for (i, line) in lines.iter().enumerate() {
+        let y = margin + i * line_height;
+        let background = palette.background();
+        image.fill_rect(
+            margin - size,
+            y - size,
+            text::width(line, size) + size * 2,
+            text::height(size) + size * 2,
+            [background[0], background[1], background[2], 0xFF],
+        );
+        text::draw(image, margin, y, line, size, palette.foreground());
+    }
+}
+
+fn run_sdl(settings: &Settings, db: &RomDb, mut rom: Option<Vec<u8>>) {
+    let (mut chip8, mut options) = match rom {
+        Some(ref rom) => boot(settings, db, rom),
+        None => (rust8::Chip8::new(), settings.options()),
+    };
+    let mut rom_path = options.rom.as_ref().map(PathBuf::from);
+    let mut session = match rom {
+        Some(ref rom) => start_movie(&options, rom, &mut chip8),
+        None => Session::Free,
+    };
+    chip8.set_tracer(start_trace(&options));
+    let mut browser = None;
+    if rom.is_none() {
+        match RomBrowser::open(&options.rom_dir) {
+            Ok(b) => browser = Some(b),
+            Err(e) => {
+                eprintln!("Failed to open {}: {}", options.rom_dir.display(), e);
+                process::exit(1);
             }
-            Scancode::D => {
-                chip8.keys[9] = true;
+        }
+    }
+
+    // Setup the window
+    let sdl_context = sdl2::init().unwrap();
+    let width = (rust8::display::WIDTH * options.scale) as u32;
+    let height = (rust8::display::HEIGHT * options.scale) as u32;
+    let title = match rom_path {
+        Some(ref path) => window_title(path),
+        None => "rust8".to_string(),
+    };
+    let mut palette = options.palette();
+    let mut display = SdlDisplay::new(&sdl_context, &title, width, height).unwrap();
+    display.palette = palette;
+    let mut input = SdlInput::new(&sdl_context).unwrap();
+    input.set_bindings(&options.keys);
+    let mut audio = SdlAudio::new(&sdl_context).unwrap();
+    let mut clock = RealTime::new(FRAMERATE);
+    let mut framebuffer = Framebuffer::new(options.filter);
+    let mut screenshots = 0;
+    let mut controls = Controls::new(options.cycles);
+    let mut fault: Option<Fault> = None;
+    let mut cheat_file = load_cheats(&options);
+    let mut cheats = match rom {
+        Some(_) => find_cheats(&cheat_file, &chip8),
+        None => CheatList::default(),
+    };
+    let mut search: Option<Search> = None;
+    // The hex digits typed so far of the value an equal search looks for
+    let mut typed: Option<Vec<u8>> = None;
+    let mut server = start_server(&options);
+    if let Session::Playing(ref player) = session {
+        controls.cycles = player.movie().cycles;
+    }
+
+    // Run the game loop, once per 60 Hz frame
+    'running: loop {
+        let mut take_screenshot = false;
+        let mut load = None;
+        let mut reset = false;
+
+        // Handle events from the user
+        for event in input.poll_events() {
+            match event {
+                Event::Quit { .. } => break 'running,
+                Event::DropFile { filename, .. } => load = Some(PathBuf::from(filename)),
+                // Anything that would restart or change the machine is
+                // disabled while a movie is running, so it stays in sync
+                Event::KeyDown {
+                    keycode:
+                        Some(
+                            Keycode::F3
+                            | Keycode::F5
+                            | Keycode::F6
+                            | Keycode::LeftBracket
+                            | Keycode::RightBracket,
+                        ),
+                    ..
+                } if !session.is_free() => controls.notify("NOT DURING A MOVIE".to_string()),
+                // While a search value is typed the hex keys give its
+                // digits, high one first, and any other key gives up
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat: false,
+                    ..
+                } if typed.is_some() => match hex_key(scancode) {
+                    Some(digit) => {
+                        let digits = typed.as_mut().unwrap();
+                        digits.push(digit);
+                        if digits.len() < 2 {
+                            controls.notify(format!("EQUAL TO {:X}_", digit));
+                        } else {
+                            let filter = Filter::Equal(digits[0] << 4 | digits[1]);
+                            typed = None;
+                            if let Some(ref mut search) = search {
+                                controls.notify(narrow_search(search, &chip8, filter));
+                            }
+                        }
+                    }
+                    None => {
+                        typed = None;
+                        controls.notify("SEARCH VALUE CANCELLED".to_string());
+                    }
+                },
+                Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => {
+                    // Escape backs out of the browser if a game is loaded
+                    if browser.is_some() && rom.is_some() {
+                        browser = None;
+                    } else {
+                        break 'running;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if browser.is_some() => match browser_key(browser.as_mut().unwrap(), keycode) {
+                    Ok(Some(path)) => load = Some(path),
+                    Ok(None) => {}
+                    Err(e) => controls.notify(format!("ERROR: {}", e)),
+                },
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat,
+                    ..
+                } => match keycode {
+                    Keycode::F12 => take_screenshot = true,
+                    Keycode::F2 => {
+                        // Cycle through the built in themes, keeping any
+                        // colours the user set over them
+                        options.theme = Palette::next_theme(&options.theme).to_string();
+                        palette = options.palette();
+                        display.palette = palette;
+                        controls.notify(format!("THEME {}", options.theme));
+                    }
+                    Keycode::P if !repeat => controls.paused = !controls.paused,
+                    Keycode::Period if controls.paused => controls.advance = true,
+                    Keycode::F5 if !repeat => reset = true,
+                    Keycode::F3 => {
+                        let dir = match rom_path {
+                            Some(ref path) => path
+                                .parent()
+                                .map(|p| p.to_path_buf())
+                                .unwrap_or_else(|| options.rom_dir.clone()),
+                            None => options.rom_dir.clone(),
+                        };
+                        match RomBrowser::open(&dir) {
+                            Ok(b) => browser = Some(b),
+                            Err(e) => controls.notify(format!("ERROR: {}", e)),
+                        }
+                    }
+                    Keycode::F6 if !repeat => {
+                        if cheats.is_empty() {
+                            controls.notify("NO CHEATS FOR THIS ROM".to_string());
+                        } else {
+                            cheats.active = !cheats.active;
+                            let state = if cheats.active { "ON" } else { "OFF" };
+                            controls.notify(format!("CHEATS {}", state));
+                        }
+                    }
+                    Keycode::F7 if !repeat => {
+                        let started = Search::new(&chip8);
+                        controls.notify(format!("SEARCH {} MATCHES", started.len()));
+                        search = Some(started);
+                    }
+                    Keycode::F8 | Keycode::F9 | Keycode::F10 if !repeat => {
+                        let filter = match keycode {
+                            Keycode::F8 => Filter::Changed,
+                            Keycode::F9 => Filter::Increased,
+                            _ => Filter::Decreased,
+                        };
+                        let message = match search {
+                            Some(ref mut search) => narrow_search(search, &chip8, filter),
+                            None => "F7 STARTS A SEARCH".to_string(),
+                        };
+                        controls.notify(message);
+                    }
+                    Keycode::F11 if !repeat => match search {
+                        Some(_) => {
+                            typed = Some(Vec::new());
+                            controls.notify("EQUAL TO __".to_string());
+                        }
+                        None => controls.notify("F7 STARTS A SEARCH".to_string()),
+                    },
+                    Keycode::F4 if !repeat => {
+                        // Freezes the one match left at the value it has now
+                        let message = match search {
+                            Some(ref search) if search.len() == 1 => {
+                                let target = search.matches()[0].0;
+                                let cheat = Cheat {
+                                    name: format!("Found {}", target),
+                                    target,
+                                    value: chip8.peek(target),
+                                    enabled: true,
+                                };
+                                let hash = chip8.rom_hash();
+                                if let Err(e) = save_cheat(&hash, &cheat) {
+                                    eprintln!("Failed to save cheat: {}", e);
+                                }
+                                cheat_file.add(hash, cheat.clone());
+                                cheats.cheats.push(cheat);
+                                cheats.active = true;
+                                format!("CHEAT ADDED FOR {}", target)
+                            }
+                            Some(_) => "SEARCH FOR ONE MATCH FIRST".to_string(),
+                            None => "F7 STARTS A SEARCH".to_string(),
+                        };
+                        controls.notify(message);
+                    }
+                    Keycode::Tab => controls.turbo = true,
+                    Keycode::Minus if controls.speed > 0 => controls.speed -= 1,
+                    Keycode::Equals if controls.speed < SPEEDS.len() - 1 => controls.speed += 1,
+                    Keycode::LeftBracket if controls.cycles > 1 => {
+                        controls.cycles -= 1;
+                        controls.notify(format!("{} CYCLES/FRAME", controls.cycles));
+                    }
+                    Keycode::RightBracket => {
+                        controls.cycles += 1;
+                        controls.notify(format!("{} CYCLES/FRAME", controls.cycles));
+                    }
+                    _ => {}
+                },
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => controls.turbo = false,
+                _ => {}
             }
-            Scancode::F => {
-                chip8.keys[0xE] = true;
+        }
+
+        // Carry out commands from scripts, which wait for their replies
+        if let Some(ref mut server) = server {
+            while let Some(call) = server.poll() {
+                let result = match call.request.clone() {
+                    Ok(Request::Quit) => {
+                        call.respond_last(Ok(String::new()));
+                        break 'running;
+                    }
+                    Ok(ref request) if request.changes_machine() && !session.is_free() => {
+                        Err("Not during a movie".to_string())
+                    }
+                    // Loading and resetting happen below, as for the keys
+                    Ok(Request::Load(path)) => read_rom(&path).map(|_| {
+                        load = Some(path);
+                        String::new()
+                    }),
+                    Ok(Request::Reset) if rom.is_none() => Err("No ROM loaded".to_string()),
+                    Ok(Request::Reset) => {
+                        reset = true;
+                        Ok(String::new())
+                    }
+                    Ok(request) => server.handle(&mut chip8, &request, controls.cycles),
+                    Err(e) => Err(e),
+                };
+                call.respond(result);
             }
-            Scancode::Z => {
-                chip8.keys[0xA] = true;
+        }
+        if reset {
+            if let Some(ref rom) = rom {
+                let tracer = chip8.take_tracer();
+                chip8 = machine(rom, &options);
+                chip8.set_tracer(tracer);
+                fault = None;
+                controls.notify("RESET".to_string());
             }
-            Scancode::X => {
-                chip8.keys[0] = true;
+        }
+        if load.is_some() && !session.is_free() {
+            controls.notify("NOT DURING A MOVIE".to_string());
+            load = None;
+        }
+        if let Some(path) = load {
+            // Start the new game from scratch
+            match read_rom(&path) {
+                Ok(data) => {
+                    let (new_chip8, new_options) = boot(settings, db, &data);
+                    let tracer = chip8.take_tracer();
+                    chip8 = new_chip8;
+                    chip8.set_tracer(tracer);
+                    fault = None;
+                    options = new_options;
+                    cheats = find_cheats(&cheat_file, &chip8);
+                    search = None;
+                    typed = None;
+                    rom = Some(data);
+                    browser = None;
+
+                    // Pick up the game's own settings
+                    palette = options.palette();
+                    display.palette = palette;
+                    input.set_bindings(&options.keys);
+                    framebuffer = Framebuffer::new(options.filter);
+                    controls = Controls::new(options.cycles);
+                    display.set_title(&window_title(&path));
+                    rom_path = Some(path);
+                }
+                Err(e) => {
+                    eprintln!("{}", e);
+                    controls.notify("FAILED TO LOAD ROM".to_string());
+                }
             }
-            Scancode::C => {
-                chip8.keys[0xB] = true;
+        }
+        input.poll(&mut chip8.keys);
+        if let Some(ref server) = server {
+            server.hold_keys(&mut chip8.keys);
+        }
+
+        // The game is on hold while the browser is open or a search value
+        // is typed on the game's keys
+        // The game is also stopped by a fault until it is reset
+        if browser.is_none() && typed.is_none() && fault.is_none() {
+            for _ in 0..controls.frames_to_run() {
+                // Cheats would put a movie out of sync
+                if session.is_free() {
+                    cheats.apply(&mut chip8);
+                }
+                let result = session.run_frame(&mut chip8, controls.cycles);
+                framebuffer.update(&chip8.screen, 1000.0 / FRAMERATE as f32);
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                    fault = Some(e);
+                    break;
+                }
             }
-            Scancode::V => {
-                chip8.keys[0xF] = true;
+        }
+
+        // Once a movie has finished the user takes over
+        if let Session::Playing(ref player) = session {
+            if player.is_finished() {
+                match player.verify(&chip8) {
+                    Ok(()) => controls.notify("MOVIE VERIFIED".to_string()),
+                    Err(e) => {
+                        eprintln!("Movie failed to verify: {}", e);
+                        controls.notify("MOVIE OUT OF SYNC".to_string());
+                    }
+                }
+                session = Session::Free;
             }
-            _ => {}
         }
+
+        // Handle audio
+        audio.set_tone(chip8.sound_timer > 0 && !controls.paused && browser.is_none());
+
+        // Run the current video buffer through the display filter and
+        // post-processing
+        let mut image = match browser {
+            Some(ref browser) => browser.render(
+                width as usize,
+                height as usize,
+                (options.scale / 4).max(1),
+                &palette,
+            ),
+            None => render(&options, &palette, &framebuffer),
+        };
+        if take_screenshot {
+            screenshots += 1;
+            save_screenshot(&image, &format!("rust8-{}.ppm", screenshots));
+        }
+        let mut status = controls.status();
+        if let Some(line) = session.status() {
+            status.insert(0, line);
+        }
+        if let Some(ref fault) = fault {
+            status.insert(0, format!("FAULT: {}", fault).to_uppercase());
+        }
+        draw_status(&mut image, &status, &palette, options.scale);
+
+        // Draw to screen
+        display.show(&image).unwrap();
+
+        // Sleep till the next frame, catching up if we fell behind
+        clock.wait();
     }
-}
 
-// Totally copy pasted example from the sdl2 docs
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
+    if let Session::Recording(recorder) = session {
+        save_movie(&recorder.finish(&chip8), options.record.as_ref().unwrap());
+    }
+    finish_trace(&mut chip8);
 }
 
-impl AudioCallback for SquareWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+// Navigates the ROM browser, returning the path of a ROM to load if one was
+// picked
+fn browser_key(browser: &mut RomBrowser, keycode: Keycode) -> io::Result<Option<PathBuf>> {
+    match keycode {
+        Keycode::Up => browser.move_selection(-1),
+        Keycode::Down => browser.move_selection(1),
+        Keycode::PageUp => browser.move_selection(-10),
+        Keycode::PageDown => browser.move_selection(10),
+        Keycode::Backspace => browser.parent()?,
+        Keycode::Return => {
+            if let Action::Load(path) = browser.activate()? {
+                return Ok(Some(path));
+            }
         }
+        _ => {}
     }
+    Ok(None)
 }
@@ -0,0 +1,426 @@
+use std::io;
+use std::io::prelude::*;
+
+use bus::Bus;
+use config;
+use fault::{Fault, OutOfRange};
+use quirks::Quirks;
+use sha1;
+use sha1::Digest;
+use timing::Timing;
+use Chip8;
+
+// A recording of a session: the game, everything needed to start the
+// machine exactly as it was, and every change to the keys with the frame it
+// happened on. Playing it back reproduces the session.
+//
+// Movies are written in the config format. The header settings come first,
+// then an [input] section with a line per frame where keys changed, e.g.
+//
+//     rom = 0123456789abcdef0123456789abcdef01234567
+//     seed = 1234
+//     quirks = vip
+//     cycles = 8
+//     out-of-range = fault
+//     timing = instructions
+//     frames = 600
+//     final = 89abcdef0123456789abcdef0123456789abcdef
+//
+//     [input]
+//     120 = +5
+//     131 = -5 +6
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_hash: Digest,
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub cycles: u32,
+    pub out_of_range: OutOfRange,
+    pub timing: Timing,
+    // Length of the movie in frames
+    pub frames: u64,
+    // State hash after the last frame, if the recording was finished
+    pub final_hash: Option<Digest>,
+    pub events: Vec<Event>,
+}
+
+// A key going down or up at the start of a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Event {
+    pub frame: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+impl Movie {
+    pub fn new(
+        rom_hash: Digest,
+        seed: u64,
+        quirks: Quirks,
+        cycles: u32,
+        out_of_range: OutOfRange,
+        timing: Timing,
+    ) -> Movie {
+        Movie {
+            rom_hash,
+            seed,
+            quirks,
+            cycles,
+            out_of_range,
+            timing,
+            frames: 0,
+            final_hash: None,
+            events: Vec::new(),
+        }
+    }
+
+    // A machine ready to record or play this movie, with the ROM loaded and
+    // the random number generator seeded. Fails if the ROM isn't the one
+    // the movie was made with.
+    pub fn start(&self, rom: &[u8]) -> Result<Chip8, String> {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(rom)?;
+        if chip8.rom_hash() != self.rom_hash {
+            return Err(format!(
+                "Movie was recorded with ROM {}, not {}",
+                sha1::to_hex(&self.rom_hash),
+                sha1::to_hex(&chip8.rom_hash())
+            ));
+        }
+        chip8.quirks = self.quirks;
+        chip8.out_of_range = self.out_of_range;
+        chip8.timing = self.timing;
+        chip8.seed(self.seed);
+        Ok(chip8)
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut rom_hash = None;
+        let mut seed = None;
+        let mut movie = Movie::new(
+            [0; 20],
+            0,
+            Quirks::default(),
+            0,
+            OutOfRange::default(),
+            Timing::default(),
+        );
+        for setting in config::parse(text)? {
+            let error = |e: String| format!("line {}: {}", setting.line, e);
+            let value = setting.value.as_str();
+            match setting.section.as_deref() {
+                None => match setting.key.as_str() {
+                    "rom" => rom_hash = Some(parse_hash(value).map_err(error)?),
+                    "seed" => seed = Some(parse(value).map_err(error)?),
+                    "quirks" => movie.quirks = parse(value).map_err(error)?,
+                    "cycles" => movie.cycles = parse(value).map_err(error)?,
+                    "out-of-range" => movie.out_of_range = parse(value).map_err(error)?,
+                    "timing" => movie.timing = parse(value).map_err(error)?,
+                    "frames" => movie.frames = parse(value).map_err(error)?,
+                    "final" => movie.final_hash = Some(parse_hash(value).map_err(error)?),
+                    key => return Err(error(format!("Unknown movie setting: {}", key))),
+                },
+                Some("input") => {
+                    let frame: u64 = parse(&setting.key).map_err(error)?;
+                    if movie.events.last().is_some_and(|e| e.frame > frame) {
+                        return Err(error("input is not in frame order".to_string()));
+                    }
+                    for change in value.split_whitespace() {
+                        let event = parse_event(frame, change).map_err(error)?;
+                        movie.events.push(event);
+                    }
+                }
+                Some(section) => {
+                    return Err(error(format!("Unknown movie section: {}", section)));
+                }
+            }
+        }
+        movie.rom_hash = rom_hash.ok_or("Movie has no rom hash")?;
+        movie.seed = seed.ok_or("Movie has no seed")?;
+        if movie.cycles == 0 {
+            return Err("Movie has no cycles setting".to_string());
+        }
+        if movie.events.last().is_some_and(|e| e.frame >= movie.frames) {
+            return Err("Movie has input after its last frame".to_string());
+        }
+        Ok(movie)
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "# rust8 movie")?;
+        writeln!(out, "rom = {}", sha1::to_hex(&self.rom_hash))?;
+        writeln!(out, "seed = {}", self.seed)?;
+        writeln!(out, "quirks = {}", self.quirks)?;
+        writeln!(out, "cycles = {}", self.cycles)?;
+        writeln!(out, "out-of-range = {}", self.out_of_range)?;
+        writeln!(out, "timing = {}", self.timing)?;
+        writeln!(out, "frames = {}", self.frames)?;
+        if let Some(ref hash) = self.final_hash {
+            writeln!(out, "final = {}", sha1::to_hex(hash))?;
+        }
+        writeln!(out)?;
+        writeln!(out, "[input]")?;
+        let mut events = self.events.iter().peekable();
+        while let Some(event) = events.next() {
+            write!(out, "{} = {}", event.frame, change(event))?;
+            while let Some(next) = events.next_if(|next| next.frame == event.frame) {
+                write!(out, " {}", change(next))?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+}
+
+fn parse<T: ::std::str::FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: ::std::fmt::Display,
+{
+    value.parse().map_err(|e| format!("{}: {}", value, e))
+}
+
+fn parse_hash(value: &str) -> Result<Digest, String> {
+    sha1::from_hex(value).ok_or_else(|| format!("Not a SHA-1 hash: {}", value))
+}
+
+// Key changes are written as + or - followed by the key in hex
+fn parse_event(frame: u64, change: &str) -> Result<Event, String> {
+    let pressed = match change.chars().next() {
+        Some('+') => true,
+        Some('-') => false,
+        _ => return Err(format!("Expected +key or -key: {}", change)),
+    };
+    let key = u8::from_str_radix(&change[1..], 16)
+        .ok()
+        .filter(|key| *key <= 0xF)
+        .ok_or_else(|| format!("Invalid chip-8 key: {}", change))?;
+    Ok(Event {
+        frame,
+        key,
+        pressed,
+    })
+}
+
+fn change(event: &Event) -> String {
+    format!("{}{:x}", if event.pressed { '+' } else { '-' }, event.key)
+}
+
+// Runs a machine while noting every change the frontend makes to its keys
+pub struct Recorder {
+    movie: Movie,
+    keys: [bool; 16],
+}
+
+impl Recorder {
+    pub fn new(movie: Movie) -> Recorder {
+        Recorder {
+            movie,
+            keys: [false; 16],
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.movie.frames
+    }
+
+    // Records the keys as they are now and runs one frame. A frame that
+    // faults still counts, so playback faults in the same place.
+    pub fn run_frame<B: Bus>(&mut self, chip8: &mut Chip8<B>) -> Result<(), Fault> {
+        for (key, (&now, was)) in chip8.keys.iter().zip(self.keys.iter_mut()).enumerate() {
+            if now != *was {
+                self.movie.events.push(Event {
+                    frame: self.movie.frames,
+                    key: key as u8,
+                    pressed: now,
+                });
+                *was = now;
+            }
+        }
+        let result = chip8.run_frame(self.movie.cycles);
+        self.movie.frames += 1;
+        result
+    }
+
+    // The finished movie, ending in the machine's current state
+    pub fn finish<B: Bus>(mut self, chip8: &Chip8<B>) -> Movie {
+        self.movie.final_hash = Some(chip8.state_hash());
+        self.movie
+    }
+}
+
+// Runs a machine with its keys driven by a movie
+pub struct Player {
+    movie: Movie,
+    frame: u64,
+    next: usize,
+    keys: [bool; 16],
+}
+
+impl Player {
+    pub fn new(movie: Movie) -> Player {
+        Player {
+            movie,
+            frame: 0,
+            next: 0,
+            keys: [false; 16],
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.frame >= self.movie.frames
+    }
+
+    // Sets the keys for the next frame of the movie and runs it. Does
+    // nothing once the movie has finished.
+    pub fn run_frame<B: Bus>(&mut self, chip8: &mut Chip8<B>) -> Result<(), Fault> {
+        if self.is_finished() {
+            return Ok(());
+        }
+        while let Some(event) = self.movie.events.get(self.next) {
+            if event.frame != self.frame {
+                break;
+            }
+            self.keys[event.key as usize] = event.pressed;
+            self.next += 1;
+        }
+        chip8.keys = self.keys;
+        let result = chip8.run_frame(self.movie.cycles);
+        self.frame += 1;
+        result
+    }
+
+    // Checks the machine ended up where it did when the movie was recorded
+    pub fn verify<B: Bus>(&self, chip8: &Chip8<B>) -> Result<(), String> {
+        if !self.is_finished() {
+            return Err(format!(
+                "Movie stopped at frame {} of {}",
+                self.frame, self.movie.frames
+            ));
+        }
+        match self.movie.final_hash {
+            Some(hash) if hash == chip8.state_hash() => Ok(()),
+            Some(hash) => Err(format!(
+                "Final state {} does not match the recording's {}",
+                sha1::to_hex(&chip8.state_hash()),
+                sha1::to_hex(&hash)
+            )),
+            None => Err("Movie has no final state to check".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Waits for key 5 then stores a random byte, forever
+    const ROM: [u8; 6] = [0xF0, 0x0A, 0xC1, 0xFF, 0x12, 0x00];
+
+    fn record() -> Movie {
+        let rom_hash = sha1::sha1(&ROM);
+        let movie = Movie::new(
+            rom_hash,
+            99,
+            Quirks::default(),
+            4,
+            OutOfRange::Fault,
+            Timing::Instructions,
+        );
+        let mut chip8 = movie.start(&ROM).unwrap();
+        let mut recorder = Recorder::new(movie);
+        for frame in 0..20 {
+            chip8.keys[5] = (3..6).contains(&frame);
+            chip8.keys[0xA] = frame == 3;
+            recorder.run_frame(&mut chip8).unwrap();
+        }
+        recorder.finish(&chip8)
+    }
+
+    #[test]
+    fn test_record() {
+        let movie = record();
+        assert_eq!(movie.frames, 20);
+        assert_eq!(
+            movie.events,
+            vec![
+                Event {
+                    frame: 3,
+                    key: 5,
+                    pressed: true
+                },
+                Event {
+                    frame: 3,
+                    key: 0xA,
+                    pressed: true
+                },
+                Event {
+                    frame: 4,
+                    key: 0xA,
+                    pressed: false
+                },
+                Event {
+                    frame: 6,
+                    key: 5,
+                    pressed: false
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_write_and_parse() {
+        let movie = record();
+        let mut text = Vec::new();
+        movie.write(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains("[input]\n3 = +5 +a\n4 = -a\n6 = -5\n"));
+        assert_eq!(Movie::parse(&text), Ok(movie));
+    }
+
+    #[test]
+    fn test_playback_verifies() {
+        let movie = record();
+        let mut chip8 = movie.start(&ROM).unwrap();
+        let mut player = Player::new(movie.clone());
+        while !player.is_finished() {
+            player.run_frame(&mut chip8).unwrap();
+        }
+        assert_eq!(player.verify(&chip8), Ok(()));
+
+        // A different seed gives a different random byte
+        let mut other = movie.clone();
+        other.seed = 100;
+        let mut chip8 = other.start(&ROM).unwrap();
+        let mut player = Player::new(other.clone());
+        while !player.is_finished() {
+            player.run_frame(&mut chip8).unwrap();
+        }
+        assert!(player.verify(&chip8).is_err());
+    }
+
+    #[test]
+    fn test_wrong_rom() {
+        assert!(record().start(&[0x12, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let header =
+            "rom = a9993e364706816aba3e25717850c26c9cd0d89d\nseed = 1\ncycles = 8\nframes = 10\n";
+        assert!(Movie::parse(header).is_ok());
+        assert!(Movie::parse("seed = 1\ncycles = 8\n").is_err());
+        assert!(Movie::parse(&format!("{}[input]\n5 = 5\n", header)).is_err());
+        assert!(Movie::parse(&format!("{}[input]\n5 = +g\n", header)).is_err());
+        assert!(Movie::parse(&format!("{}[input]\n5 = +1\n2 = -1\n", header)).is_err());
+        assert!(Movie::parse(&format!("{}[input]\n10 = +1\n", header)).is_err());
+        assert!(Movie::parse(&format!("{}speed = 2\n", header)).is_err());
+    }
+}
@@ -0,0 +1,489 @@
+use std::env;
+use std::fs::File;
+use std::io::prelude::*;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use rust8::config;
+use rust8::config::Setting;
+use rust8::display::Filter;
+use rust8::effects::{Effect, Effects};
+use rust8::engine::Engine;
+use rust8::fault::OutOfRange;
+use rust8::palette;
+use rust8::palette::{Palette, Rgb};
+use rust8::quirks::Quirks;
+use rust8::remote::Endpoint;
+use rust8::romdb::RomEntry;
+use rust8::timing::Timing;
+use rust8::trace;
+
+use CYCLES_PER_FRAME;
+
+// What rust8 was asked to do, picked by the first argument
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    // Play the game, in a window or headless
+    Run,
+    // Run flat out and report the speed
+    Bench,
+    // Print a listing of the ROM, or its control-flow graph with --dot
+    Disasm,
+    // Print the ROM as structured Octo-style code
+    Decompile,
+}
+
+// Settings picked on the command line, in the config file or by the ROM
+// database
+#[derive(Clone)]
+pub struct Options {
+    pub rom: Option<String>,
+    pub command: Command,
+    // How long the benchmark runs for
+    pub seconds: u64,
+    // Where the in-window ROM browser starts
+    pub rom_dir: PathBuf,
+    // Extra ROM database to read on top of the bundled and user ones
+    pub romdb: Option<PathBuf>,
+    // Extra cheat file to read on top of the user's
+    pub cheats: Option<PathBuf>,
+    pub filter: Filter,
+    pub effects: Effects,
+    pub scale: usize,
+    pub cycles: u32,
+    pub quirks: Quirks,
+    pub out_of_range: OutOfRange,
+    pub timing: Timing,
+    pub engine: Engine,
+    pub headless: bool,
+    // Where to listen for remote control commands
+    pub remote: Option<Endpoint>,
+    // Print rust8 disasm's control-flow graph instead of the listing
+    pub dot: bool,
+    pub frames: u64,
+    pub screenshot: Option<String>,
+    // Movie files to record the session to or play back
+    pub record: Option<PathBuf>,
+    pub play: Option<PathBuf>,
+    // Instruction trace file, "-" for standard output, and what goes in it
+    pub trace: Option<String>,
+    pub trace_format: trace::Format,
+    pub trace_addresses: Option<RangeInclusive<u16>>,
+    pub trace_cycles: Option<RangeInclusive<u64>>,
+    pub theme: String,
+    // Custom colours replacing those of the theme, indexed like
+    // Palette::planes with the border last
+    pub colours: [Option<Rgb>; 5],
+    // Extra key bindings on top of the default layout, as hex key and SDL
+    // key name
+    pub keys: Vec<(u8, String)>,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            rom: None,
+            command: Command::Run,
+            seconds: 5,
+            rom_dir: PathBuf::from("."),
+            romdb: None,
+            cheats: None,
+            filter: Filter::None,
+            effects: Effects::default(),
+            scale: 10,
+            cycles: CYCLES_PER_FRAME,
+            quirks: Quirks::default(),
+            out_of_range: OutOfRange::default(),
+            timing: Timing::default(),
+            engine: Engine::default(),
+            headless: false,
+            remote: None,
+            dot: false,
+            frames: 600,
+            screenshot: None,
+            record: None,
+            play: None,
+            trace: None,
+            trace_format: trace::Format::Text,
+            trace_addresses: None,
+            trace_cycles: None,
+            theme: "classic".to_string(),
+            colours: [None; 5],
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    // The theme with any custom colours applied on top
+    pub fn palette(&self) -> Palette {
+        let mut palette = Palette::theme(&self.theme).unwrap_or_default();
+        for (i, colour) in self.colours.iter().enumerate() {
+            if let Some(colour) = *colour {
+                if i < 4 {
+                    palette.planes[i] = colour;
+                } else {
+                    palette.border = colour;
+                }
+            }
+        }
+        palette
+    }
+}
+
+pub fn usage() -> ! {
+    println!("Usage: rust8 [OPTIONS] [ROM]");
+    println!("       rust8 bench [OPTIONS] ROM");
+    println!("       rust8 disasm [--dot] ROM");
+    println!("       rust8 decompile ROM");
+    println!("  --config FILE      read settings from FILE");
+    println!("  --rom-dir DIR      directory the ROM browser starts in");
+    println!("  --romdb FILE       extra ROM database to read");
+    println!("  --cheats FILE      extra cheat file to read");
+    println!("  --filter none|phosphor[:ms]|blend[:frames]|max2");
+    println!("  --effect scanlines|grid|bloom|curvature[:amount]  (repeatable)");
+    println!("  --scale N          size of each chip-8 pixel (default 10)");
+    println!(
+        "  --cycles N         instructions per 60 Hz frame (default {})",
+        CYCLES_PER_FRAME
+    );
+    println!(
+        "  --quirks PRESET[,+QUIRK|,-QUIRK...]  {}",
+        Quirks::preset_names().join("|")
+    );
+    println!("  --out-of-range fault|wrap  what memory, stack and PC overruns do");
+    println!("  --timing instructions|vip  fixed instructions per frame or VIP speed");
+    println!("  --engine interpreter|cached  how instructions are run");
+    println!("  --theme NAME       {}", Palette::theme_names().join("|"));
+    println!("  --background HEX   colour of unlit pixels");
+    println!("  --foreground HEX   colour of lit pixels");
+    println!("  --plane2 HEX       colour of XO-CHIP plane 2 pixels");
+    println!("  --plane3 HEX       colour of pixels in both XO-CHIP planes");
+    println!("  --border HEX       colour around the picture");
+    println!("  --keys BINDINGS    extra key bindings, e.g. \"4:Left 6:Right\"");
+    println!("  --headless         run without a window");
+    println!("  --remote stdio|tcp:PORT|unix:PATH  take commands from scripts");
+    println!("  --frames N         frames to run when headless (default 600)");
+    println!("  --screenshot FILE  save a PPM of the last frame when headless");
+    println!("  --record FILE      record the keys pressed to a movie file");
+    println!("  --play FILE        play back a movie file");
+    println!("  --trace FILE       write each instruction run to FILE, - for stdout");
+    println!("  --trace-format text|csv|json");
+    println!("  --trace-addresses START-END  only trace code in this hex range");
+    println!("  --trace-cycles START-END     only trace this window of instructions");
+    println!("  --seconds N        how long rust8 bench runs for (default 5)");
+    println!("  --dot              print rust8 disasm's control-flow graph as DOT");
+    process::exit(1);
+}
+
+// Options that don't take a value on the command line. In the config file
+// they are written as "name = true".
+const FLAGS: [&str; 2] = ["headless", "dot"];
+
+// The settings a ROM database entry may change. The rest only make sense
+// for the whole session.
+const GAME_SETTINGS: [&str; 13] = [
+    "quirks",
+    "out-of-range",
+    "timing",
+    "cycles",
+    "filter",
+    "effect",
+    "theme",
+    "background",
+    "foreground",
+    "plane2",
+    "plane3",
+    "border",
+    "keys",
+];
+
+fn parse<T: ::std::str::FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: ::std::fmt::Display,
+{
+    value.parse().map_err(|e| format!("{}: {}", value, e))
+}
+
+// Key bindings are written as space separated hexkey:KeyName pairs
+fn parse_keys(value: &str) -> Result<Vec<(u8, String)>, String> {
+    let mut keys = Vec::new();
+    for binding in value.split_whitespace() {
+        let mut parts = binding.splitn(2, ':');
+        let key = parts.next().unwrap();
+        let name = match parts.next() {
+            Some(name) if !name.is_empty() => name,
+            _ => return Err(format!("Expected hexkey:KeyName: {}", binding)),
+        };
+        let key = u8::from_str_radix(key, 16)
+            .ok()
+            .filter(|key| *key <= 0xF)
+            .ok_or_else(|| format!("Invalid chip-8 key: {}", key))?;
+        keys.push((key, name.to_string()));
+    }
+    Ok(keys)
+}
+
+// Applies a single setting, from the command line, config file or ROM
+// database
+fn set_option(options: &mut Options, key: &str, value: &str) -> Result<(), String> {
+    match key {
+        "rom-dir" => options.rom_dir = PathBuf::from(value),
+        "romdb" => options.romdb = Some(PathBuf::from(value)),
+        "cheats" => options.cheats = Some(PathBuf::from(value)),
+        "filter" => options.filter = parse(value)?,
+        "effect" => options.effects.enable(parse::<Effect>(value)?),
+        "scale" => {
+            options.scale = parse(value)?;
+            if options.scale == 0 {
+                return Err("scale must be at least 1".to_string());
+            }
+        }
+        "cycles" => {
+            options.cycles = parse(value)?;
+            if options.cycles == 0 {
+                return Err("cycles must be at least 1".to_string());
+            }
+        }
+        "quirks" => options.quirks = parse(value)?,
+        "out-of-range" => options.out_of_range = parse(value)?,
+        "timing" => options.timing = parse(value)?,
+        "engine" => options.engine = parse(value)?,
+        "headless" => options.headless = parse(value)?,
+        "remote" => options.remote = Some(parse(value)?),
+        "dot" => options.dot = parse(value)?,
+        "frames" => options.frames = parse(value)?,
+        "seconds" => {
+            options.seconds = parse(value)?;
+            if options.seconds == 0 {
+                return Err("seconds must be at least 1".to_string());
+            }
+        }
+        "screenshot" => options.screenshot = Some(value.to_string()),
+        "record" => options.record = Some(PathBuf::from(value)),
+        "play" => options.play = Some(PathBuf::from(value)),
+        "trace" => options.trace = Some(value.to_string()),
+        "trace-format" => options.trace_format = parse(value)?,
+        "trace-addresses" => options.trace_addresses = Some(trace::parse_addresses(value)?),
+        "trace-cycles" => options.trace_cycles = Some(trace::parse_cycles(value)?),
+        "theme" => {
+            parse::<Palette>(value)?;
+            options.theme = value.to_string();
+        }
+        "background" => options.colours[0] = Some(palette::parse_hex(value)?),
+        "foreground" => options.colours[1] = Some(palette::parse_hex(value)?),
+        "plane2" => options.colours[2] = Some(palette::parse_hex(value)?),
+        "plane3" => options.colours[3] = Some(palette::parse_hex(value)?),
+        "border" => options.colours[4] = Some(palette::parse_hex(value)?),
+        "keys" => options.keys = parse_keys(value)?,
+        _ => return Err(format!("Unknown option: {}", key)),
+    }
+    Ok(())
+}
+
+// The rust8 directory under $XDG_CONFIG_HOME, falling back to ~/.config
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("rust8"))
+}
+
+pub fn read_text(path: &Path) -> Result<String, String> {
+    let mut text = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(text)
+}
+
+// Everything the user asked for, kept as raw settings so each game's
+// database entry can be layered between the config file and the command line
+pub struct Settings {
+    config_path: Option<PathBuf>,
+    config: Vec<Setting>,
+    args: Vec<(String, String)>,
+    rom: Option<String>,
+    command: Command,
+}
+
+impl Settings {
+    // Reads the config file and command line, exiting with a message if
+    // either has a mistake in it
+    pub fn from_args() -> Settings {
+        let mut settings = Settings {
+            config_path: None,
+            config: Vec::new(),
+            args: Vec::new(),
+            rom: None,
+            command: Command::Run,
+        };
+
+        let mut args = env::args().skip(1).peekable();
+        let command = match args.peek().map(|arg| arg.as_str()) {
+            Some("bench") => Some(Command::Bench),
+            Some("disasm") => Some(Command::Disasm),
+            Some("decompile") => Some(Command::Decompile),
+            _ => None,
+        };
+        if let Some(command) = command {
+            args.next();
+            settings.command = command;
+        }
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--") {
+                settings.rom = Some(arg);
+                continue;
+            }
+            let key = arg[2..].to_string();
+            let value = if FLAGS.contains(&key.as_str()) {
+                "true".to_string()
+            } else {
+                args.next().unwrap_or_else(|| usage())
+            };
+            if key == "config" {
+                settings.config_path = Some(PathBuf::from(value));
+            } else {
+                settings.args.push((key, value));
+            }
+        }
+
+        // The config file is applied first so the command line can override
+        // it
+        let path = match settings.config_path {
+            Some(ref path) => Some(path.clone()),
+            None => config_dir()
+                .map(|dir| dir.join("rust8.conf"))
+                .filter(|path| path.exists()),
+        };
+        if let Some(path) = path {
+            let parsed = read_text(&path).and_then(|text| config::parse(&text));
+            match parsed {
+                Ok(config) => settings.config = config,
+                Err(e) => {
+                    println!("{}: {}", path.display(), e);
+                    process::exit(1);
+                }
+            }
+            settings.config_path = Some(path);
+        }
+
+        if let Err(e) = settings.apply(&[]) {
+            println!("{}", e);
+            usage();
+        }
+        settings
+    }
+
+    // Options before any game is loaded
+    pub fn options(&self) -> Options {
+        self.options_for(None)
+    }
+
+    // Options for a game, with its database settings applied on top of the
+    // config file and under the command line. Bad database settings are
+    // reported and skipped.
+    pub fn options_for(&self, game: Option<&RomEntry>) -> Options {
+        let mut wanted = Vec::new();
+        if let Some(game) = game {
+            for setting in &game.settings {
+                if !GAME_SETTINGS.contains(&setting.key.as_str()) {
+                    eprintln!(
+                        "ROM database: line {}: {} can't be set per game",
+                        setting.line, setting.key
+                    );
+                    continue;
+                }
+                let mut test = Options::default();
+                if let Err(e) = set_option(&mut test, &setting.key, &setting.value) {
+                    eprintln!("ROM database: line {}: {}", setting.line, e);
+                    continue;
+                }
+                wanted.push(setting.clone());
+            }
+        }
+        self.apply(&wanted)
+            .expect("Settings were checked when they were read")
+    }
+
+    fn apply(&self, game: &[Setting]) -> Result<Options, String> {
+        let mut options = Options::default();
+        let path = self
+            .config_path
+            .as_ref()
+            .map(|p| p.display().to_string())
+            .unwrap_or_default();
+        for setting in &self.config {
+            if setting.section.is_some() {
+                return Err(format!(
+                    "{}: line {}: sections are not allowed here",
+                    path, setting.line
+                ));
+            }
+            set_option(&mut options, &setting.key, &setting.value)
+                .map_err(|e| format!("{}: line {}: {}", path, setting.line, e))?;
+        }
+        for setting in game {
+            set_option(&mut options, &setting.key, &setting.value)?;
+        }
+        for (key, value) in &self.args {
+            set_option(&mut options, key, value)?;
+        }
+        options.rom = self.rom.clone();
+        options.command = self.command;
+        Ok(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(
+            parse_keys("4:Left  a:Space"),
+            Ok(vec![(4, "Left".to_string()), (0xA, "Space".to_string())])
+        );
+        assert!(parse_keys("10:Left").is_err());
+        assert!(parse_keys("4").is_err());
+        assert!(parse_keys("4:").is_err());
+    }
+
+    #[test]
+    fn test_game_settings_layering() {
+        let settings = Settings {
+            config_path: None,
+            config: config::parse("cycles = 10\ntheme = green\n").unwrap(),
+            args: vec![("theme".to_string(), "lcd".to_string())],
+            rom: None,
+            command: Command::Run,
+        };
+        let game = RomEntry {
+            title: None,
+            platform: None,
+            settings: config::parse("cycles = 20\ntheme = amber\nscale = 3\n").unwrap(),
+        };
+        let options = settings.options_for(Some(&game));
+        // The game beats the config file, the command line beats the game
+        assert_eq!(options.cycles, 20);
+        assert_eq!(options.theme, "lcd");
+        // Session wide settings are ignored in the database
+        assert_eq!(options.scale, 10);
+    }
+
+    #[test]
+    fn test_palette_keeps_colours() {
+        // Custom colours stay on top of whichever theme is picked
+        let mut options = Options::default();
+        options.colours[0] = Some(palette::parse_hex("#101010").unwrap());
+        options.theme = Palette::next_theme(&options.theme).to_string();
+        let theme = Palette::theme(&options.theme).unwrap();
+        let palette = options.palette();
+        assert_eq!(palette.planes[0], palette::parse_hex("#101010").unwrap());
+        assert_eq!(palette.planes[1], theme.planes[1]);
+        assert_ne!(palette.planes[1], Palette::default().planes[1]);
+    }
+}
@@ -0,0 +1,168 @@
+use std::str::FromStr;
+
+pub type Rgb = [u8; 3];
+
+// The colours used to draw the screen. XO-CHIP has two bit planes, so each
+// pixel can be in one of four states. Plain chip-8 only uses the first two:
+// planes[0] is the background and planes[1] is a lit pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    // Indexed by plane bits, 0 = neither plane, 1 = plane 1, 2 = plane 2,
+    // 3 = both planes
+    pub planes: [Rgb; 4],
+    // Colour of the area around the picture
+    pub border: Rgb,
+}
+
+// The built in themes, in the order the palette hotkey cycles through them
+const THEMES: [(&str, Palette); 6] = [
+    (
+        "classic",
+        Palette {
+            planes: [[0, 0, 0], [255, 255, 255], [170, 170, 170], [85, 85, 85]],
+            border: [0, 0, 0],
+        },
+    ),
+    (
+        "amber",
+        Palette {
+            planes: [[26, 16, 0], [255, 176, 0], [178, 104, 0], [255, 214, 128]],
+            border: [13, 8, 0],
+        },
+    ),
+    (
+        "green",
+        Palette {
+            planes: [[5, 20, 5], [51, 255, 51], [0, 153, 51], [170, 255, 170]],
+            border: [2, 10, 2],
+        },
+    ),
+    (
+        "lcd",
+        Palette {
+            planes: [[155, 188, 15], [15, 56, 15], [48, 98, 48], [139, 172, 15]],
+            border: [139, 172, 15],
+        },
+    ),
+    (
+        "high-contrast",
+        Palette {
+            planes: [[0, 0, 0], [255, 255, 0], [0, 255, 255], [255, 255, 255]],
+            border: [40, 40, 40],
+        },
+    ),
+    // Okabe-Ito colours, distinguishable with the common forms of colour
+    // blindness
+    (
+        "colorblind",
+        Palette {
+            planes: [[0, 0, 0], [230, 159, 0], [86, 180, 233], [240, 228, 66]],
+            border: [0, 0, 0],
+        },
+    ),
+];
+
+impl Default for Palette {
+    fn default() -> Palette {
+        THEMES[0].1
+    }
+}
+
+impl Palette {
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES
+            .iter()
+            .find(|&&(theme, _)| theme == name)
+            .map(|&(_, palette)| palette)
+    }
+
+    pub fn theme_names() -> Vec<&'static str> {
+        THEMES.iter().map(|&(name, _)| name).collect()
+    }
+
+    // The name of the built in theme after the given one, wrapping around
+    pub fn next_theme(name: &str) -> &'static str {
+        let current = THEMES.iter().position(|&(theme, _)| theme == name);
+        match current {
+            Some(i) => THEMES[(i + 1) % THEMES.len()].0,
+            None => THEMES[0].0,
+        }
+    }
+
+    pub fn background(&self) -> Rgb {
+        self.planes[0]
+    }
+
+    pub fn foreground(&self) -> Rgb {
+        self.planes[1]
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        Palette::theme(s).ok_or_else(|| {
+            format!(
+                "Unknown theme {}, expected one of: {}",
+                s,
+                Palette::theme_names().join(", ")
+            )
+        })
+    }
+}
+
+// Parses colours written as hex, "#RRGGBB" or the short form "#RGB". The
+// leading '#' is optional.
+pub fn parse_hex(s: &str) -> Result<Rgb, String> {
+    let digits = s.trim_start_matches('#');
+    let invalid = || format!("Invalid colour: {}", s);
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    match digits.len() {
+        3 => {
+            let mut rgb = [0; 3];
+            for (i, c) in digits.chars().enumerate() {
+                let value = c.to_digit(16).unwrap() as u8;
+                rgb[i] = value << 4 | value;
+            }
+            Ok(rgb)
+        }
+        6 => {
+            let mut rgb = [0; 3];
+            for (i, channel) in rgb.iter_mut().enumerate() {
+                *channel =
+                    u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+            }
+            Ok(rgb)
+        }
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex() {
+        assert_eq!(parse_hex("#ffb000"), Ok([0xFF, 0xB0, 0x00]));
+        assert_eq!(parse_hex("0a0B0c"), Ok([0x0A, 0x0B, 0x0C]));
+        assert_eq!(parse_hex("#fa0"), Ok([0xFF, 0xAA, 0x00]));
+        assert!(parse_hex("#ffb00").is_err());
+        assert!(parse_hex("#gg0000").is_err());
+    }
+
+    #[test]
+    fn test_themes() {
+        assert_eq!("classic".parse::<Palette>(), Ok(Palette::default()));
+        assert_eq!(
+            "amber".parse::<Palette>().unwrap().foreground(),
+            [255, 176, 0]
+        );
+        assert!("sepia".parse::<Palette>().is_err());
+        assert_eq!(Palette::next_theme("classic"), "amber");
+        assert_eq!(Palette::next_theme("colorblind"), "classic");
+    }
+}
@@ -0,0 +1,202 @@
+#[cfg(feature = "std")]
+use std::fmt;
+#[cfg(feature = "std")]
+use std::str::FromStr;
+
+// Behaviours that differ between chip-8 interpreters. Games were written
+// against whichever interpreter their author had, so they need the matching
+// set of quirks to run correctly. The default is how rust8 has always
+// behaved.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    // 8XY6/8XYE shift VY and store the result in VX, as on the COSMAC VIP,
+    // instead of shifting VX in place
+    pub shift_vy: bool,
+    // FX55/FX65 leave I pointing just past the last register transferred
+    pub increment_i: bool,
+    // 8XY1/8XY2/8XY3 reset VF to 0
+    pub vf_reset: bool,
+    // BNNN jumps to XNN + VX instead of NNN + V0
+    pub jump_vx: bool,
+    // Sprites are clipped at the edges of the screen instead of wrapping
+    // around. The starting position still wraps.
+    pub clip_sprites: bool,
+    // DXYN waits for the next 60 Hz frame before drawing, so at most one
+    // sprite is drawn per frame
+    pub display_wait: bool,
+}
+
+// Named sets of quirks for the common platforms
+const PRESETS: [(&str, Quirks); 4] = [
+    (
+        "default",
+        Quirks {
+            shift_vy: false,
+            increment_i: false,
+            vf_reset: false,
+            jump_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+        },
+    ),
+    (
+        "vip",
+        Quirks {
+            shift_vy: true,
+            increment_i: true,
+            vf_reset: true,
+            jump_vx: false,
+            clip_sprites: true,
+            display_wait: true,
+        },
+    ),
+    (
+        "schip",
+        Quirks {
+            shift_vy: false,
+            increment_i: false,
+            vf_reset: false,
+            jump_vx: true,
+            clip_sprites: true,
+            display_wait: false,
+        },
+    ),
+    (
+        "xo-chip",
+        Quirks {
+            shift_vy: true,
+            increment_i: true,
+            vf_reset: false,
+            jump_vx: false,
+            clip_sprites: false,
+            display_wait: false,
+        },
+    ),
+];
+
+#[cfg(feature = "std")]
+const NAMES: [&str; 6] = [
+    "shift-vy",
+    "increment-i",
+    "vf-reset",
+    "jump-vx",
+    "clip",
+    "display-wait",
+];
+
+impl Quirks {
+    pub fn preset(name: &str) -> Option<Quirks> {
+        PRESETS
+            .iter()
+            .find(|&&(preset, _)| preset == name)
+            .map(|&(_, quirks)| quirks)
+    }
+
+    #[cfg(feature = "std")]
+    pub fn preset_names() -> Vec<&'static str> {
+        PRESETS.iter().map(|&(name, _)| name).collect()
+    }
+
+    #[cfg(feature = "std")]
+    fn flag(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "shift-vy" => Some(&mut self.shift_vy),
+            "increment-i" => Some(&mut self.increment_i),
+            "vf-reset" => Some(&mut self.vf_reset),
+            "jump-vx" => Some(&mut self.jump_vx),
+            "clip" => Some(&mut self.clip_sprites),
+            "display-wait" => Some(&mut self.display_wait),
+            _ => None,
+        }
+    }
+}
+
+// Quirks are written as a preset optionally followed by individual quirks
+// to turn on or off, e.g. "vip,-display-wait" or "default,+shift-vy"
+#[cfg(feature = "std")]
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Quirks, String> {
+        let mut parts = s.split(',');
+        let preset = parts.next().unwrap_or("").trim();
+        let mut quirks = Quirks::preset(preset).ok_or_else(|| {
+            format!(
+                "Unknown quirks preset {}, expected one of: {}",
+                preset,
+                Quirks::preset_names().join(", ")
+            )
+        })?;
+        for part in parts {
+            let part = part.trim();
+            let (value, name) = if let Some(name) = part.strip_prefix('+') {
+                (true, name)
+            } else if let Some(name) = part.strip_prefix('-') {
+                (false, name)
+            } else {
+                return Err(format!("Expected +quirk or -quirk: {}", part));
+            };
+            match quirks.flag(name) {
+                Some(flag) => *flag = value,
+                None => {
+                    return Err(format!(
+                        "Unknown quirk {}, expected one of: {}",
+                        name,
+                        NAMES.join(", ")
+                    ))
+                }
+            }
+        }
+        Ok(quirks)
+    }
+}
+
+// Prints the closest preset followed by the differences from it, in the same
+// form FromStr accepts
+#[cfg(feature = "std")]
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut best = String::new();
+        for &(name, preset) in PRESETS.iter() {
+            let mut text = name.to_string();
+            let mut preset = preset;
+            let mut current = *self;
+            for quirk in NAMES.iter() {
+                let wanted = *current.flag(quirk).unwrap();
+                if *preset.flag(quirk).unwrap() != wanted {
+                    text.push_str(if wanted { ",+" } else { ",-" });
+                    text.push_str(quirk);
+                }
+            }
+            if best.is_empty() || text.len() < best.len() {
+                best = text;
+            }
+        }
+        write!(f, "{}", best)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quirks() {
+        assert_eq!("default".parse::<Quirks>(), Ok(Quirks::default()));
+        let quirks: Quirks = "vip,-display-wait,+jump-vx".parse().unwrap();
+        assert!(quirks.shift_vy);
+        assert!(!quirks.display_wait);
+        assert!(quirks.jump_vx);
+        assert!("amiga".parse::<Quirks>().is_err());
+        assert!("vip,display-wait".parse::<Quirks>().is_err());
+        assert!("vip,+sparkles".parse::<Quirks>().is_err());
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        assert_eq!(Quirks::preset("vip").unwrap().to_string(), "vip");
+        let quirks: Quirks = "schip,+shift-vy".parse().unwrap();
+        assert_eq!(quirks.to_string(), "schip,+shift-vy");
+        assert_eq!(quirks.to_string().parse::<Quirks>(), Ok(quirks));
+    }
+}
@@ -0,0 +1,538 @@
+// Remote control, so scripts and bots can drive the emulator. Clients send
+// one command per line and get one reply per line, starting with "ok" and
+// any data or "error" and what went wrong:
+//
+//     load PATH          load a ROM file and start it
+//     reset              restart the ROM from scratch
+//     step [N]           run N instructions, 1 by default
+//     frame [N]          run N frames, timers included, 1 by default
+//     press KEY          hold down a hex key
+//     release KEY        let go of a hex key
+//     screen             the 32 rows of the screen, each 16 hex digits with
+//                        the leftmost pixel in the top bit
+//     registers          pc=XXXX i=XXXX sp=X dt=XX st=XX v=<V0 to VF in hex>
+//     peek ADDR [LEN]    LEN bytes of memory from ADDR in hex, 1 by default
+//     poke ADDR BYTES    write the hex bytes to memory from ADDR
+//     save-state         the machine's save state in hex
+//     load-state HEX     put the machine back to a saved state
+//     quit               stop the emulator
+//
+// Addresses, keys and bytes are hex and counts are decimal. The server
+// listens on standard input and output, a TCP port on the loopback
+// interface or a Unix socket. Each client is read on its own thread and its
+// commands are handed to the frontend, which carries them out between
+// frames on the thread that owns the machine.
+
+use std::fmt;
+use std::fmt::Write as FmtWrite;
+use std::fs;
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+#[cfg(unix)]
+use std::os::unix::fs::FileTypeExt;
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use bus::{Bus, MEMORY_SIZE};
+use fault::Fault;
+use Chip8;
+
+// Where the server listens
+#[derive(Clone, Debug, PartialEq)]
+pub enum Endpoint {
+    Stdio,
+    Tcp(u16),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Endpoint::Stdio => write!(f, "stdio"),
+            Endpoint::Tcp(port) => write!(f, "tcp:{}", port),
+            #[cfg(unix)]
+            Endpoint::Unix(ref path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl FromStr for Endpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Endpoint, String> {
+        if s == "stdio" {
+            return Ok(Endpoint::Stdio);
+        }
+        if let Some(port) = s.strip_prefix("tcp:") {
+            return port
+                .parse()
+                .map(Endpoint::Tcp)
+                .map_err(|_| format!("Invalid port: {}", port));
+        }
+        #[cfg(unix)]
+        {
+            if let Some(path) = s.strip_prefix("unix:") {
+                if path.is_empty() {
+                    return Err("Missing socket path".to_string());
+                }
+                return Ok(Endpoint::Unix(PathBuf::from(path)));
+            }
+        }
+        Err(format!("Expected stdio, tcp:PORT or unix:PATH: {}", s))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Request {
+    Load(PathBuf),
+    Reset,
+    Step(u32),
+    Frame(u32),
+    Press(u8),
+    Release(u8),
+    Screen,
+    Registers,
+    Peek(u16, usize),
+    Poke(u16, Vec<u8>),
+    SaveState,
+    LoadState(Vec<u8>),
+    Quit,
+}
+
+impl Request {
+    // Whether the request changes the machine, rather than just looking
+    pub fn changes_machine(&self) -> bool {
+        !matches!(
+            *self,
+            Request::Screen | Request::Registers | Request::Peek(..) | Request::SaveState
+        )
+    }
+}
+
+fn parse_key(s: &str) -> Result<u8, String> {
+    u8::from_str_radix(s, 16)
+        .ok()
+        .filter(|key| *key <= 0xF)
+        .ok_or_else(|| format!("Invalid key: {}", s))
+}
+
+fn parse_address(s: &str) -> Result<u16, String> {
+    u16::from_str_radix(s, 16)
+        .ok()
+        .filter(|address| (*address as usize) < MEMORY_SIZE)
+        .ok_or_else(|| format!("Invalid address: {}", s))
+}
+
+// Counts default to 1 when left off
+fn parse_count<T: FromStr>(s: Option<&str>) -> Result<T, String> {
+    s.unwrap_or("1")
+        .parse()
+        .map_err(|_| format!("Invalid count: {}", s.unwrap_or("")))
+}
+
+fn parse_bytes(s: &str) -> Result<Vec<u8>, String> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return Err("Expected pairs of hex digits".to_string());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| format!("Invalid hex: {}", s)))
+        .collect()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{:02X}", byte).unwrap();
+    }
+    hex
+}
+
+impl FromStr for Request {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Request, String> {
+        let words: Vec<&str> = s.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Err("Empty command".to_string()),
+        };
+        if command == "load" {
+            // The path is the rest of the line, so it may have spaces
+            let path = s.trim_start()[4..].trim();
+            if path.is_empty() {
+                return Err(format!("Missing argument: {}", s));
+            }
+            return Ok(Request::Load(PathBuf::from(path)));
+        }
+        let most = match command {
+            "reset" | "screen" | "registers" | "save-state" | "quit" => 0,
+            "peek" | "poke" => 2,
+            _ => 1,
+        };
+        if args.len() > most {
+            return Err(format!("Too many arguments: {}", s));
+        }
+        let arg = |i: usize| args.get(i).cloned();
+        let needed = |i: usize| arg(i).ok_or_else(|| format!("Missing argument: {}", s));
+        Ok(match command {
+            "reset" => Request::Reset,
+            "step" => Request::Step(parse_count(arg(0))?),
+            "frame" => Request::Frame(parse_count(arg(0))?),
+            "press" => Request::Press(parse_key(needed(0)?)?),
+            "release" => Request::Release(parse_key(needed(0)?)?),
+            "screen" => Request::Screen,
+            "registers" => Request::Registers,
+            "peek" => Request::Peek(parse_address(needed(0)?)?, parse_count(arg(1))?),
+            "poke" => Request::Poke(parse_address(needed(0)?)?, parse_bytes(needed(1)?)?),
+            "save-state" => Request::SaveState,
+            "load-state" => Request::LoadState(parse_bytes(needed(0)?)?),
+            "quit" => Request::Quit,
+            _ => return Err(format!("Unknown command: {}", command)),
+        })
+    }
+}
+
+// A command from a client, waiting for the frontend to carry it out
+pub struct Call {
+    pub request: Result<Request, String>,
+    reply: Sender<String>,
+    written: Receiver<()>,
+}
+
+impl Call {
+    // Sends the result back to the client
+    pub fn respond(self, result: Result<String, String>) {
+        self.send(result);
+    }
+
+    // Sends the last result before the frontend exits, waiting until the
+    // client has been sent it, as exiting would cut the reply off
+    pub fn respond_last(self, result: Result<String, String>) {
+        self.send(result);
+        let _ = self.written.recv();
+    }
+
+    fn send(&self, result: Result<String, String>) {
+        let line = match result {
+            Ok(ref data) if data.is_empty() => "ok".to_string(),
+            Ok(data) => format!("ok {}", data),
+            Err(e) => format!("error {}", e),
+        };
+        // The client may have gone away, which is its own business
+        let _ = self.reply.send(line);
+    }
+}
+
+// Reads commands from one client, passing each to the frontend and writing
+// back its reply before reading the next
+fn serve<R: BufRead, W: Write>(reader: R, mut writer: W, calls: &Sender<Call>) {
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        let (reply, response) = channel();
+        let (done, written) = channel();
+        let call = Call {
+            request: line.parse(),
+            reply,
+            written,
+        };
+        if calls.send(call).is_err() {
+            return;
+        }
+        let response = match response.recv() {
+            Ok(response) => response,
+            Err(_) => return,
+        };
+        if writeln!(writer, "{}", response)
+            .and_then(|()| writer.flush())
+            .is_err()
+        {
+            return;
+        }
+        let _ = done.send(());
+    }
+}
+
+// Accepts clients on a background thread, serving each on its own thread
+pub struct Server {
+    calls: Receiver<Call>,
+    // Keys held down by clients, kept apart from the machine's keys so the
+    // frontend's own input doesn't let go of them
+    pub keys: [bool; 16],
+    // The Unix socket listened on, removed again when the server goes
+    socket: Option<PathBuf>,
+}
+
+impl Server {
+    pub fn start(endpoint: &Endpoint) -> io::Result<Server> {
+        let (sender, calls) = channel();
+        let mut socket = None;
+        match *endpoint {
+            Endpoint::Stdio => {
+                thread::spawn(move || {
+                    let stdin = io::stdin();
+                    serve(stdin.lock(), io::stdout(), &sender)
+                });
+            }
+            Endpoint::Tcp(port) => {
+                let listener = TcpListener::bind(("127.0.0.1", port))?;
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        let sender = sender.clone();
+                        thread::spawn(move || {
+                            if let Ok(reader) = stream.try_clone() {
+                                serve(BufReader::new(reader), stream, &sender);
+                            }
+                        });
+                    }
+                });
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(ref path) => {
+                // A socket left behind by a run that didn't get to clean up
+                // would stop the bind. One that still answers belongs to a
+                // live server and is left alone.
+                if path
+                    .symlink_metadata()
+                    .is_ok_and(|meta| meta.file_type().is_socket())
+                {
+                    match UnixStream::connect(path) {
+                        Ok(_) => {
+                            return Err(io::Error::new(
+                                io::ErrorKind::AddrInUse,
+                                "another server is listening there",
+                            ))
+                        }
+                        Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                            fs::remove_file(path)?
+                        }
+                        Err(_) => {}
+                    }
+                }
+                let listener = UnixListener::bind(path)?;
+                socket = Some(path.clone());
+                thread::spawn(move || {
+                    for stream in listener.incoming().flatten() {
+                        let sender = sender.clone();
+                        thread::spawn(move || {
+                            if let Ok(reader) = stream.try_clone() {
+                                serve(BufReader::new(reader), stream, &sender);
+                            }
+                        });
+                    }
+                });
+            }
+        }
+        Ok(Server {
+            calls,
+            keys: [false; 16],
+            socket,
+        })
+    }
+
+    // The next command if one is waiting
+    pub fn poll(&self) -> Option<Call> {
+        self.calls.try_recv().ok()
+    }
+
+    // Waits for the next command. None once no more can come, which is
+    // when the only client, standard input, has closed.
+    pub fn wait(&self) -> Option<Call> {
+        self.calls.recv().ok()
+    }
+
+    // Carries out a command that only needs the machine. Loading and
+    // resetting are up to the frontend, which knows the ROM and settings,
+    // as is quitting.
+    pub fn handle<B: Bus>(
+        &mut self,
+        chip8: &mut Chip8<B>,
+        request: &Request,
+        cycles: u32,
+    ) -> Result<String, String> {
+        let fault = |fault: Fault| fault.to_string();
+        match *request {
+            Request::Step(n) => {
+                for _ in 0..n {
+                    chip8.cycle().map_err(fault)?;
+                }
+            }
+            Request::Frame(n) => {
+                for _ in 0..n {
+                    chip8.run_frame(cycles).map_err(fault)?;
+                }
+            }
+            Request::Press(key) | Request::Release(key) => {
+                let down = matches!(*request, Request::Press(_));
+                self.keys[key as usize] = down;
+                chip8.keys[key as usize] = down;
+            }
+            Request::Screen => {
+                let rows: Vec<String> = chip8
+                    .screen
+                    .rows()
+                    .iter()
+                    .map(|row| format!("{:016X}", row))
+                    .collect();
+                return Ok(rows.join(" "));
+            }
+            Request::Registers => {
+                return Ok(format!(
+                    "pc={:04X} i={:04X} sp={:X} dt={:02X} st={:02X} v={}",
+                    chip8.pc(),
+                    chip8.index(),
+                    chip8.sp(),
+                    chip8.delay_timer(),
+                    chip8.sound_timer,
+                    to_hex(chip8.registers())
+                ))
+            }
+            Request::Peek(address, len) => {
+                let start = address as usize;
+                let bytes = start
+                    .checked_add(len)
+                    .and_then(|end| chip8.bus().memory().get(start..end))
+                    .ok_or_else(|| "Past the end of memory".to_string())?;
+                return Ok(to_hex(bytes));
+            }
+            Request::Poke(address, ref bytes) => {
+                let start = address as usize;
+                if start + bytes.len() > MEMORY_SIZE {
+                    return Err("Past the end of memory".to_string());
+                }
+                chip8.bus_mut().memory_mut()[start..start + bytes.len()].copy_from_slice(bytes);
+            }
+            Request::SaveState => return Ok(to_hex(&chip8.save_state())),
+            Request::LoadState(ref state) => chip8.load_state(state)?,
+            Request::Load(_) | Request::Reset | Request::Quit => {
+                return Err("Not handled by the machine".to_string())
+            }
+        }
+        Ok(String::new())
+    }
+
+    // Adds the keys clients are holding to those read by the frontend
+    pub fn hold_keys(&self, keys: &mut [bool; 16]) {
+        for (key, &held) in keys.iter_mut().zip(self.keys.iter()) {
+            *key |= held;
+        }
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.socket {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        for endpoint in ["stdio", "tcp:8800", "unix:/tmp/rust8.sock"].iter() {
+            assert_eq!(endpoint.parse::<Endpoint>().unwrap().to_string(), *endpoint);
+        }
+        assert!("tcp:80000".parse::<Endpoint>().is_err());
+        assert!("unix:".parse::<Endpoint>().is_err());
+        assert!("udp:8800".parse::<Endpoint>().is_err());
+    }
+
+    #[test]
+    fn test_parse_request() {
+        assert_eq!(
+            "load roms/my game.ch8".parse(),
+            Ok(Request::Load(PathBuf::from("roms/my game.ch8")))
+        );
+        assert_eq!("step".parse(), Ok(Request::Step(1)));
+        assert_eq!("frame 60".parse(), Ok(Request::Frame(60)));
+        assert_eq!("press a".parse(), Ok(Request::Press(0xA)));
+        assert_eq!("peek 200 4".parse(), Ok(Request::Peek(0x200, 4)));
+        assert_eq!(
+            "poke 300 00ff".parse(),
+            Ok(Request::Poke(0x300, vec![0x00, 0xFF]))
+        );
+        for bad in [
+            "",
+            "load",
+            "jump",
+            "step x",
+            "press 10",
+            "peek 1000",
+            "poke 300 0ff",
+            "reset 1",
+            "frame 1 2",
+        ]
+        .iter()
+        {
+            assert!(bad.parse::<Request>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_handle() {
+        let mut server = Server {
+            calls: channel().1,
+            keys: [false; 16],
+            socket: None,
+        };
+        let mut chip8 = Chip8::new();
+        // LD V0, 0x12, then wait for keys in V1 forever
+        chip8
+            .load_rom(&[0x60, 0x12, 0xF1, 0x0A, 0x12, 0x02])
+            .unwrap();
+        let mut run = |line: &str| server.handle(&mut chip8, &line.parse().unwrap(), 10);
+
+        assert_eq!(run("step 2"), Ok(String::new()));
+        let registers = run("registers").unwrap();
+        assert!(registers.starts_with("pc=0202 i=0000 sp=0 dt=00 st=00 v=1200"));
+        assert_eq!(run("peek 200 4"), Ok("6012F10A".to_string()));
+        assert_eq!(run("peek FFF"), Ok("00".to_string()));
+        assert!(run("peek FFF 2").is_err());
+        assert!(run("peek 200 18446744073709551615").is_err());
+        assert_eq!(run("poke 300 ABCD"), Ok(String::new()));
+        assert_eq!(run("peek 300 2"), Ok("ABCD".to_string()));
+
+        let state = run("save-state").unwrap();
+        run("press 5").unwrap();
+        run("frame").unwrap();
+        assert!(run("registers").unwrap().contains("v=1205"));
+        run(&format!("load-state {}", state)).unwrap();
+        assert!(run("registers").unwrap().contains("v=1200"));
+
+        let screen = run("screen").unwrap();
+        assert_eq!(screen, vec!["0000000000000000"; 32].join(" "));
+        assert!(server.keys[5]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_removed() {
+        let path = ::std::env::temp_dir().join(format!("rust8-{}.sock", ::std::process::id()));
+        let endpoint = Endpoint::Unix(path.clone());
+        // Left behind as if by a run that was killed
+        let stale = UnixListener::bind(&path).unwrap();
+        drop(stale);
+        let server = Server::start(&endpoint).unwrap();
+        assert!(path.exists());
+        // A second server can't take the socket from a live one
+        let error = Server::start(&endpoint).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::AddrInUse);
+        assert!(UnixStream::connect(&path).is_ok());
+        drop(server);
+        assert!(!path.exists());
+        drop(Server::start(&endpoint).unwrap());
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use config;
+use config::Setting;
+use sha1::Digest;
+
+// The database that ships with rust8
+const BUNDLED: &str = include_str!("../data/romdb.ini");
+
+// What we know about a game: its name, the platform it was written for and
+// the settings it needs, stored as config settings so a frontend can apply
+// them the same way as its own options
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RomEntry {
+    pub title: Option<String>,
+    pub platform: Option<String>,
+    pub settings: Vec<Setting>,
+}
+
+// Games keyed by the SHA-1 of their ROM image. The file format is the config
+// format with one section per game, named by the hash in hex.
+#[derive(Clone, Debug, Default)]
+pub struct RomDb {
+    entries: HashMap<Digest, RomEntry>,
+}
+
+impl RomDb {
+    pub fn new() -> RomDb {
+        RomDb::default()
+    }
+
+    pub fn bundled() -> RomDb {
+        RomDb::parse(BUNDLED).expect("Bundled ROM database is invalid")
+    }
+
+    pub fn parse(text: &str) -> Result<RomDb, String> {
+        let mut db = RomDb::new();
+        for setting in config::parse(text)? {
+            let entry = db.entries.entry(setting.game()?).or_default();
+            match setting.key.as_str() {
+                "title" => entry.title = Some(setting.value.clone()),
+                "platform" => entry.platform = Some(setting.value.clone()),
+                _ => entry.settings.push(setting),
+            }
+        }
+        Ok(db)
+    }
+
+    // Adds the entries of another database, replacing any for the same game
+    pub fn merge(&mut self, other: RomDb) {
+        self.entries.extend(other.entries);
+    }
+
+    pub fn lookup(&self, hash: &Digest) -> Option<&RomEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1;
+
+    const PONG: &str = "[0123456789ABCDEF0123456789abcdef01234567]
+title = Pong
+platform = chip-8
+quirks = vip
+cycles = 15
+";
+
+    #[test]
+    fn test_bundled_parses() {
+        RomDb::bundled();
+    }
+
+    #[test]
+    fn test_lookup_test_roms() {
+        let dir = ::std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+        let db = RomDb::parse(&::std::fs::read_to_string(dir.join("romdb.ini")).unwrap()).unwrap();
+        for &(file, title) in [
+            ("alu.ch8", "rust8 ALU test"),
+            ("display-wait.ch8", "rust8 display wait test"),
+            ("font.ch8", "rust8 font test"),
+            ("memory.ch8", "rust8 memory test"),
+            ("sprites.ch8", "rust8 sprite test"),
+        ]
+        .iter()
+        {
+            let rom = ::std::fs::read(dir.join(file)).unwrap();
+            let entry = db.lookup(&sha1::sha1(&rom)).unwrap();
+            assert_eq!(entry.title, Some(title.to_string()), "{}", file);
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = RomDb::parse(PONG).unwrap();
+        let hash = sha1::from_hex("0123456789abcdef0123456789abcdef01234567").unwrap();
+        let entry = db.lookup(&hash).unwrap();
+        assert_eq!(entry.title, Some("Pong".to_string()));
+        assert_eq!(entry.platform, Some("chip-8".to_string()));
+        assert_eq!(entry.settings.len(), 2);
+        assert_eq!(entry.settings[0].key, "quirks");
+        assert!(db.lookup(&[0; 20]).is_none());
+    }
+
+    #[test]
+    fn test_merge_replaces() {
+        let mut db = RomDb::parse(PONG).unwrap();
+        let user = PONG.replace("title = Pong", "title = My Pong");
+        db.merge(RomDb::parse(&user).unwrap());
+        assert_eq!(db.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(RomDb::parse("title = Pong").is_err());
+        assert!(RomDb::parse("[pong]\ntitle = Pong").is_err());
+    }
+}
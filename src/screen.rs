@@ -0,0 +1,103 @@
+// The chip-8 display, packed one u64 per row with the leftmost pixel in the
+// most significant bit. Drawing a row of a sprite is then a shift to line it
+// up, an AND to find collisions and an XOR to draw it, instead of a test and
+// a toggle for each pixel.
+
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Screen {
+    rows: [u64; HEIGHT],
+}
+
+impl Screen {
+    pub fn new() -> Screen {
+        Screen::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.rows = [0; HEIGHT];
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.rows[y] >> (WIDTH - 1 - x) & 1 == 1
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, lit: bool) {
+        let bit = 1 << (WIDTH - 1 - x);
+        if lit {
+            self.rows[y] |= bit;
+        } else {
+            self.rows[y] &= !bit;
+        }
+    }
+
+    // The packed rows, top to bottom
+    pub fn rows(&self) -> &[u64; HEIGHT] {
+        &self.rows
+    }
+
+    // Every pixel a row at a time, starting from the top left
+    pub fn pixels(&self) -> impl Iterator<Item = bool> + '_ {
+        self.rows
+            .iter()
+            .flat_map(|&row| (0..WIDTH).map(move |x| row >> (WIDTH - 1 - x) & 1 == 1))
+    }
+
+    // XORs one row of a sprite onto row y with its left edge at column x.
+    // The part past the right edge wraps around to the left, or is dropped
+    // when clipping. Returns whether any lit pixel was turned off.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: u8, clip: bool) -> bool {
+        let sprite = (sprite as u64) << (WIDTH - 8);
+        let sprite = if clip {
+            sprite >> x
+        } else {
+            sprite.rotate_right(x as u32)
+        };
+        let row = &mut self.rows[y];
+        let collision = *row & sprite != 0;
+        *row ^= sprite;
+        collision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pixels() {
+        let mut screen = Screen::new();
+        screen.set(0, 0, true);
+        screen.set(63, 31, true);
+        assert!(screen.pixel(0, 0));
+        assert!(screen.pixel(63, 31));
+        assert!(!screen.pixel(1, 0));
+        assert_eq!(screen.rows()[0], 1 << 63);
+        assert_eq!(screen.pixels().filter(|&lit| lit).count(), 2);
+        assert_eq!(screen.pixels().position(|lit| lit), Some(0));
+        screen.set(0, 0, false);
+        assert_eq!(
+            screen.pixels().position(|lit| lit),
+            Some(WIDTH * HEIGHT - 1)
+        );
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut screen = Screen::new();
+        assert!(!screen.draw(60, 2, 0b1100_0011, false));
+        // Wrapped past the right edge
+        assert!(screen.pixel(60, 2) && screen.pixel(61, 2));
+        assert!(screen.pixel(2, 2) && screen.pixel(3, 2));
+        assert!(!screen.pixel(62, 2) && !screen.pixel(0, 2));
+        // Drawing it again erases it and reports the collision
+        assert!(screen.draw(60, 2, 0b1100_0011, false));
+        assert_eq!(screen, Screen::new());
+
+        assert!(!screen.draw(60, 2, 0b1100_0011, true));
+        assert!(screen.pixel(60, 2) && screen.pixel(61, 2));
+        assert!(!screen.pixel(2, 2) && !screen.pixel(3, 2));
+    }
+}
@@ -0,0 +1,173 @@
+// SHA-1, used to identify ROM images. It matches the hashes used by other
+// chip-8 tools and databases. This is not used for anything security
+// related.
+
+pub type Digest = [u8; 20];
+
+pub fn sha1(data: &[u8]) -> Digest {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finish()
+}
+
+// SHA-1 of data fed in a piece at a time, without buffering it all, so the
+// core can hash without allocating
+pub struct Sha1 {
+    state: [u32; 5],
+    block: [u8; 64],
+    // Bytes waiting in block
+    filled: usize,
+    length: u64,
+}
+
+impl Default for Sha1 {
+    fn default() -> Sha1 {
+        Sha1::new()
+    }
+}
+
+impl Sha1 {
+    pub fn new() -> Sha1 {
+        Sha1 {
+            state: [
+                0x6745_2301,
+                0xEFCD_AB89,
+                0x98BA_DCFE,
+                0x1032_5476,
+                0xC3D2_E1F0,
+            ],
+            block: [0; 64],
+            filled: 0,
+            length: 0,
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.filled).min(data.len());
+            self.block[self.filled..self.filled + n].copy_from_slice(&data[..n]);
+            self.filled += n;
+            data = &data[n..];
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Digest {
+        // Pad with a 1 bit, zeros, then the length in bits, to a multiple of
+        // 64 bytes
+        let bits = self.length * 8;
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+
+        let mut digest = [0; 20];
+        for (i, word) in self.state.iter().enumerate() {
+            digest[i * 4..i * 4 + 4].copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 80];
+        for (i, word) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = self.state;
+        for (i, word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _ => (b ^ c ^ d, 0xCA62_C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (s, v) in self.state.iter_mut().zip([a, b, c, d, e].iter()) {
+            *s = s.wrapping_add(*v);
+        }
+    }
+}
+
+// Lower case hex, the form hashes are written in the ROM database
+#[cfg(feature = "std")]
+pub fn to_hex(digest: &Digest) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Reads a digest back from the form to_hex writes, in either case
+pub fn from_hex(hex: &str) -> Option<Digest> {
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut digest = [0; 20];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_hashes() {
+        assert_eq!(
+            to_hex(&sha1(b"")),
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709"
+        );
+        assert_eq!(
+            to_hex(&sha1(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        // Long enough to need a second block for the padding
+        assert_eq!(
+            to_hex(&sha1(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+    }
+
+    #[test]
+    fn test_from_hex() {
+        let digest = sha1(b"abc");
+        assert_eq!(from_hex(&to_hex(&digest)), Some(digest));
+        assert_eq!(
+            from_hex("A9993E364706816ABA3E25717850C26C9CD0D89D"),
+            Some(digest)
+        );
+        assert_eq!(from_hex("a9993e"), None);
+        assert_eq!(from_hex("g9993e364706816aba3e25717850c26c9cd0d89d"), None);
+    }
+
+    #[test]
+    fn test_pieces() {
+        let data = [7u8; 200];
+        let mut hasher = Sha1::new();
+        for piece in data.chunks(33) {
+            hasher.update(piece);
+        }
+        assert_eq!(hasher.finish(), sha1(&data));
+    }
+}
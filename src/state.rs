@@ -0,0 +1,170 @@
+// Save states: a snapshot of everything the game can see or change, so a
+// machine can be put back exactly where it was. Settings such as the quirks
+// and the keys held down are left out; they belong to the frontend.
+
+use bus::{Bus, MEMORY_SIZE};
+use screen::{HEIGHT, WIDTH};
+use Chip8;
+
+const MAGIC: &[u8; 4] = b"R8ST";
+const VERSION: u8 = 2;
+
+// Every state is the same size, which libretro and similar frontends need
+pub const STATE_SIZE: usize = 4 + 1 // magic and version
+    + 20 // ROM hash
+    + MEMORY_SIZE
+    + 16 + 2 // V0-VF and I
+    + 2 // timers
+    + 2 + 1 + 16 * 2 // PC, SP and stack
+    + WIDTH * HEIGHT // screen, a byte per pixel
+    + 1 + 8 + 8 // drew, random state and cycles
+    + 2; // VIP timing overrun
+
+// Where SP is, checked before anything is loaded
+const SP_OFFSET: usize = 4 + 1 + 20 + MEMORY_SIZE + 16 + 2 + 2 + 2;
+
+// Reads fields back out of a state in the order they were written
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> &'a [u8] {
+        let (head, tail) = self.data.split_at(n);
+        self.data = tail;
+        head
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.take(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        let mut bytes = [0; 2];
+        bytes.copy_from_slice(self.take(2));
+        u16::from_be_bytes(bytes)
+    }
+
+    fn u64(&mut self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8));
+        u64::from_be_bytes(bytes)
+    }
+}
+
+impl<B: Bus> Chip8<B> {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_SIZE);
+        state.extend_from_slice(MAGIC);
+        state.push(VERSION);
+        state.extend_from_slice(&self.rom_hash);
+        state.extend_from_slice(self.bus.memory());
+        state.extend_from_slice(&self.registers);
+        state.extend_from_slice(&self.instruction_reg.to_be_bytes());
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+        state.extend_from_slice(&self.pc.to_be_bytes());
+        state.push(self.sp);
+        for address in self.stack.iter() {
+            state.extend_from_slice(&address.to_be_bytes());
+        }
+        state.extend(self.screen.pixels().map(|pixel| pixel as u8));
+        state.push(self.drew as u8);
+        state.extend_from_slice(&self.rng.to_be_bytes());
+        state.extend_from_slice(&self.cycles.to_be_bytes());
+        state.extend_from_slice(&self.overrun.to_be_bytes());
+        state
+    }
+
+    // Restores a state saved by save_state. States from another ROM are
+    // refused, and the machine is left alone if the state is rejected.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.len() != STATE_SIZE || &state[..4] != MAGIC {
+            return Err("Not a rust8 save state".to_string());
+        }
+        if state[4] != VERSION {
+            return Err(format!("Unsupported save state version {}", state[4]));
+        }
+        let mut reader = Reader { data: &state[5..] };
+        if reader.take(20) != self.rom_hash {
+            return Err("Save state is for a different ROM".to_string());
+        }
+        // SP past the end of the stack would have the next CALL or RET
+        // reach outside it
+        if state[SP_OFFSET] as usize > self.stack.len() {
+            return Err(format!("Save state has a bad SP {}", state[SP_OFFSET]));
+        }
+        self.bus_mut()
+            .memory_mut()
+            .copy_from_slice(reader.take(MEMORY_SIZE));
+        self.registers.copy_from_slice(reader.take(16));
+        self.instruction_reg = reader.u16();
+        self.delay_timer = reader.u8();
+        self.sound_timer = reader.u8();
+        self.pc = reader.u16();
+        self.sp = reader.u8();
+        for address in self.stack.iter_mut() {
+            *address = reader.u16();
+        }
+        for y in 0..HEIGHT {
+            for (x, &byte) in reader.take(WIDTH).iter().enumerate() {
+                self.screen.set(x, y, byte != 0);
+            }
+        }
+        self.drew = reader.u8() != 0;
+        self.rng = reader.u64();
+        self.cycles = reader.u64();
+        self.overrun = reader.u16();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Draws random sprites forever
+    const ROM: [u8; 8] = [0xC0, 0xFF, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x00];
+
+    #[test]
+    fn test_save_and_load() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&ROM).unwrap();
+        chip8.seed(1);
+        chip8.run_frame(20).unwrap();
+        let state = chip8.save_state();
+        assert_eq!(state.len(), STATE_SIZE);
+        chip8.run_frame(20).unwrap();
+        let expected = chip8.state_hash();
+
+        let mut restored = Chip8::new();
+        restored.load_rom(&ROM).unwrap();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.cycles(), 20);
+        restored.run_frame(20).unwrap();
+        assert_eq!(restored.state_hash(), expected);
+    }
+
+    #[test]
+    fn test_rejected_states() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&ROM).unwrap();
+        let mut state = chip8.save_state();
+        assert!(chip8.load_state(&state[1..]).is_err());
+        state[4] = 1;
+        assert!(chip8.load_state(&state).is_err());
+
+        let mut state = chip8.save_state();
+        state[SP_OFFSET] = 17;
+        assert!(chip8.load_state(&state).is_err());
+        state[SP_OFFSET] = 16;
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.sp(), 16);
+
+        let mut other = Chip8::new();
+        other.load_rom(&[0x12, 0x00]).unwrap();
+        let before = other.state_hash();
+        assert!(other.load_state(&chip8.save_state()).is_err());
+        assert_eq!(other.state_hash(), before);
+    }
+}
@@ -0,0 +1,126 @@
+use image::Image;
+
+// A small bitmap font for on screen messages, in the same 4x5 style as the
+// chip-8 fontset. The hex digits are exactly the chip-8 ones; the other
+// letters and punctuation are drawn to match. Each byte is one row with the
+// pixels in the high nibble.
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 5;
+// Horizontal distance from the start of one character to the next
+pub const ADVANCE: usize = GLYPH_WIDTH + 1;
+
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0xF0, 0x90, 0x90, 0x90, 0xF0],
+        '1' => [0x20, 0x60, 0x20, 0x20, 0x70],
+        '2' => [0xF0, 0x10, 0xF0, 0x80, 0xF0],
+        '3' => [0xF0, 0x10, 0xF0, 0x10, 0xF0],
+        '4' => [0x90, 0x90, 0xF0, 0x10, 0x10],
+        '5' => [0xF0, 0x80, 0xF0, 0x10, 0xF0],
+        '6' => [0xF0, 0x80, 0xF0, 0x90, 0xF0],
+        '7' => [0xF0, 0x10, 0x20, 0x40, 0x40],
+        '8' => [0xF0, 0x90, 0xF0, 0x90, 0xF0],
+        '9' => [0xF0, 0x90, 0xF0, 0x10, 0xF0],
+        'A' => [0xF0, 0x90, 0xF0, 0x90, 0x90],
+        'B' => [0xE0, 0x90, 0xE0, 0x90, 0xE0],
+        'C' => [0xF0, 0x80, 0x80, 0x80, 0xF0],
+        'D' => [0xE0, 0x90, 0x90, 0x90, 0xE0],
+        'E' => [0xF0, 0x80, 0xF0, 0x80, 0xF0],
+        'F' => [0xF0, 0x80, 0xF0, 0x80, 0x80],
+        'G' => [0xF0, 0x80, 0xB0, 0x90, 0xF0],
+        'H' => [0x90, 0x90, 0xF0, 0x90, 0x90],
+        'I' => [0xE0, 0x40, 0x40, 0x40, 0xE0],
+        'J' => [0x70, 0x20, 0x20, 0xA0, 0xE0],
+        'K' => [0x90, 0xA0, 0xC0, 0xA0, 0x90],
+        'L' => [0x80, 0x80, 0x80, 0x80, 0xF0],
+        'M' => [0x90, 0xF0, 0xF0, 0x90, 0x90],
+        'N' => [0x90, 0xD0, 0xB0, 0x90, 0x90],
+        'O' => [0x60, 0x90, 0x90, 0x90, 0x60],
+        'P' => [0xE0, 0x90, 0xE0, 0x80, 0x80],
+        'Q' => [0x60, 0x90, 0x90, 0xB0, 0x70],
+        'R' => [0xE0, 0x90, 0xE0, 0xA0, 0x90],
+        'S' => [0x70, 0x80, 0x60, 0x10, 0xE0],
+        'T' => [0xE0, 0x40, 0x40, 0x40, 0x40],
+        'U' => [0x90, 0x90, 0x90, 0x90, 0xF0],
+        'V' => [0x90, 0x90, 0x90, 0x90, 0x60],
+        'W' => [0x90, 0x90, 0xF0, 0xF0, 0x90],
+        'X' => [0x90, 0x90, 0x60, 0x90, 0x90],
+        'Y' => [0xA0, 0xA0, 0x40, 0x40, 0x40],
+        'Z' => [0xF0, 0x10, 0x60, 0x80, 0xF0],
+        '.' => [0x00, 0x00, 0x00, 0x00, 0x40],
+        ',' => [0x00, 0x00, 0x00, 0x40, 0x80],
+        ':' => [0x00, 0x40, 0x00, 0x40, 0x00],
+        '-' => [0x00, 0x00, 0xF0, 0x00, 0x00],
+        '+' => [0x00, 0x40, 0xE0, 0x40, 0x00],
+        '=' => [0x00, 0xF0, 0x00, 0xF0, 0x00],
+        '*' => [0x00, 0xA0, 0x40, 0xA0, 0x00],
+        '/' => [0x10, 0x10, 0x20, 0x40, 0x80],
+        '_' => [0x00, 0x00, 0x00, 0x00, 0xF0],
+        '<' => [0x20, 0x40, 0x80, 0x40, 0x20],
+        '>' => [0x80, 0x40, 0x20, 0x40, 0x80],
+        '(' => [0x20, 0x40, 0x40, 0x40, 0x20],
+        ')' => [0x40, 0x20, 0x20, 0x20, 0x40],
+        '[' => [0x60, 0x40, 0x40, 0x40, 0x60],
+        ']' => [0x60, 0x20, 0x20, 0x20, 0x60],
+        '!' => [0x40, 0x40, 0x40, 0x00, 0x40],
+        '#' => [0xA0, 0xF0, 0xA0, 0xF0, 0xA0],
+        '\'' => [0x40, 0x40, 0x00, 0x00, 0x00],
+        // Anything we don't have a glyph for
+        _ => [0xE0, 0x10, 0x60, 0x00, 0x40],
+    }
+}
+
+// Width in pixels of the text when drawn at the given size
+pub fn width(text: &str, size: usize) -> usize {
+    let count = text.chars().count();
+    if count == 0 {
+        0
+    } else {
+        (count * ADVANCE - 1) * size
+    }
+}
+
+pub fn height(size: usize) -> usize {
+    GLYPH_HEIGHT * size
+}
+
+// Draws text with its top left corner at (x, y). Each font pixel becomes a
+// size x size block. Anything falling outside the image is clipped.
+pub fn draw(image: &mut Image, x: usize, y: usize, text: &str, size: usize, colour: [u8; 3]) {
+    let pixel = [colour[0], colour[1], colour[2], 0xFF];
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i * ADVANCE * size;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0x80 >> column) == 0 {
+                    continue;
+                }
+                image.fill_rect(left + column * size, y + row * size, size, size, pixel);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width() {
+        assert_eq!(width("", 2), 0);
+        assert_eq!(width("A", 1), 4);
+        assert_eq!(width("AB", 2), 18);
+    }
+
+    #[test]
+    fn test_draw() {
+        let mut image = Image::new(10, 5);
+        draw(&mut image, 0, 0, "1", 1, [255, 255, 255]);
+        // The 1 glyph is 0x20 on the top row
+        assert_eq!(image.get(2, 0), [255, 255, 255, 255]);
+        assert_eq!(image.get(0, 0), [0, 0, 0, 0]);
+        // Text running off the edge is clipped rather than panicking
+        draw(&mut image, 8, 3, "88", 2, [255, 255, 255]);
+    }
+}
@@ -0,0 +1,208 @@
+// How the time a 60 Hz frame gives the game is shared out between
+// instructions. Frontends normally run a fixed number of instructions a
+// frame, as most chip-8 interpreters do. The VIP timing instead charges
+// each instruction what it took on the COSMAC VIP and gives every frame the
+// machine cycles the VIP had left over from the display, so that games that
+// relied on the speed of the original run as they did.
+//
+// The costs are approximations from disassembling the VIP interpreter:
+// they ignore the odd cycle an instruction takes depending on where its
+// operands sit in memory, and a sprite's cost in reality also depends on
+// its column.
+use core::fmt;
+#[cfg(feature = "std")]
+use std::str::FromStr;
+
+// The VIP's 1.76 MHz clock takes 8 ticks per machine cycle, giving 3668 of
+// them every 60 Hz frame
+pub const FRAME_CYCLES: u32 = 3668;
+// Machine cycles a frame spends on the display's DMA and the interrupt
+// routine that ticks the timers, which the interpreter can't use
+pub const DISPLAY_CYCLES: u32 = 1832;
+// What the interpreter gets each frame
+pub const BUDGET: u32 = FRAME_CYCLES - DISPLAY_CYCLES;
+
+// Fetching and decoding, which every instruction pays on top of its own
+// cost
+const FETCH: u32 = 40;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Timing {
+    // Every instruction takes the same time, with a fixed number of them a
+    // frame
+    #[default]
+    Instructions,
+    // Each instruction takes what it did on the COSMAC VIP, within a fixed
+    // budget of machine cycles a frame
+    Vip,
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Timing::Instructions => write!(f, "instructions"),
+            Timing::Vip => write!(f, "vip"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Timing, String> {
+        match s {
+            "instructions" => Ok(Timing::Instructions),
+            "vip" => Ok(Timing::Vip),
+            _ => Err(format!("Expected instructions or vip: {}", s)),
+        }
+    }
+}
+
+// Machine cycles the VIP took to run an instruction, given VX and whether a
+// skip instruction skipped. VX only matters to FX33, which leaves it alone,
+// so it can be read before or after the instruction runs.
+pub fn vip_cost(opcode: u16, vx: u8, skipped: bool) -> u32 {
+    let x = (opcode >> 8 & 0xF) as u32;
+    let n = (opcode & 0xF) as u32;
+    let skip = if skipped { 4 } else { 0 };
+    FETCH
+        + match opcode >> 12 {
+            0x0 => match opcode {
+                // Clearing the screen writes all 256 bytes of it
+                0x00E0 => 3078,
+                0x00EE => 10,
+                _ => 0,
+            },
+            0x1 => 12,
+            0x2 => 26,
+            0x3 | 0x4 => 10 + skip,
+            0x5 | 0x9 => 14 + skip,
+            0x6 => 6,
+            0x7 => 10,
+            0x8 if n == 0 => 12,
+            0x8 => 44,
+            0xA => 12,
+            0xB => 22,
+            0xC => 36,
+            // Sprites are shifted into place and drawn a row at a time, so
+            // DXY0 draws nothing
+            0xD => 26 + 46 * n,
+            0xE => 14 + skip,
+            _ => match opcode & 0xFF {
+                0x1E | 0x29 => 16,
+                // The digits are found by counting down, a loop per unit of
+                // each of them
+                0x33 => {
+                    let digits = vx / 100 + vx / 10 % 10 + vx % 10;
+                    80 + 16 * digits as u32
+                }
+                0x55 | 0x65 => 14 + 14 * (x + 1),
+                _ => 10,
+            },
+        }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bus::{Hooked, Hooks, Ram};
+    use Chip8;
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8::with_seed(Ram::new(), 1);
+        chip8.load_rom(rom).unwrap();
+        chip8.timing = Timing::Vip;
+        chip8
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!("vip".parse(), Ok(Timing::Vip));
+        assert_eq!(Timing::Instructions.to_string(), "instructions");
+        assert!("cycles".parse::<Timing>().is_err());
+    }
+
+    #[test]
+    fn test_costs() {
+        assert_eq!(vip_cost(0x6012, 0, false), 46);
+        assert_eq!(vip_cost(0x3012, 0, false), 50);
+        assert_eq!(vip_cost(0x3012, 0x12, true), 54);
+        assert_eq!(vip_cost(0xD015, 0, false), 40 + 26 + 46 * 5);
+        assert_eq!(vip_cost(0xD010, 0, false), 40 + 26);
+        assert_eq!(vip_cost(0xF033, 199, false), 40 + 80 + 16 * 19);
+        assert_eq!(vip_cost(0xF355, 0, false), 40 + 14 + 14 * 4);
+        // Clearing the screen takes longer than a frame's budget
+        assert!(vip_cost(0x00E0, 0, false) > BUDGET);
+    }
+
+    #[test]
+    fn test_frame_budget() {
+        // Counts in V0 in a loop costing 102 machine cycles
+        let mut chip8 = machine(&[0x70, 0x01, 0x12, 0x00]);
+        chip8.run_frame(1000).unwrap();
+        assert_eq!(chip8.registers()[0], 18);
+        chip8.run_frame(1).unwrap();
+        assert_eq!(chip8.registers()[0], 36);
+    }
+
+    #[test]
+    fn test_draw_waits_for_vblank() {
+        let mut chip8 = machine(&[0xD0, 0x01, 0x12, 0x00]);
+        chip8.quirks.display_wait = true;
+        chip8.run_frame(8).unwrap();
+        assert_eq!(chip8.cycles(), 1);
+        chip8.run_frame(8).unwrap();
+        assert_eq!(chip8.cycles(), 3);
+    }
+
+    #[test]
+    fn test_overrun_carries_over() {
+        // Clearing the screen runs into the next frame, and twice in a row
+        // leaves no time at all for the frame after
+        let mut chip8 = machine(&[0x00, 0xE0, 0x12, 0x00]);
+        chip8.run_frame(8).unwrap();
+        assert_eq!(chip8.cycles(), 1);
+        chip8.run_frame(8).unwrap();
+        assert_eq!(chip8.cycles(), 3);
+        chip8.run_frame(8).unwrap();
+        assert_eq!(chip8.cycles(), 3);
+        chip8.run_frame(8).unwrap();
+        assert_eq!(chip8.cycles(), 5);
+    }
+
+    #[test]
+    fn test_fault_pays_off_overrun() {
+        // Clears the screen, running over into the next frame, which then
+        // faults on an unknown opcode without running anything else. Only
+        // the overrun changes, and the state and its hash have to show it.
+        let mut chip8 = machine(&[0x00, 0xE0, 0x00, 0x00]);
+        chip8.run_frame(8).unwrap();
+        let before = chip8.state_hash();
+        assert!(chip8.run_frame(8).is_err());
+        assert_ne!(chip8.state_hash(), before);
+        let state = chip8.save_state();
+        assert_eq!(state[state.len() - 2..], [0, 0]);
+    }
+
+    // Fetches a clear screen in place of whatever is at 0x200
+    struct Patch;
+
+    impl Hooks for Patch {
+        fn fetch(&mut self, address: u16, value: u8) -> u8 {
+            match address {
+                0x200 => 0x00,
+                0x201 => 0xE0,
+                _ => value,
+            }
+        }
+    }
+
+    #[test]
+    fn test_cost_of_hooked_fetch() {
+        let mut chip8 = Chip8::with_seed(Hooked::new(Patch), 1);
+        chip8.load_rom(&[0x60, 0x12]).unwrap();
+        assert_eq!(chip8.timed_cycle(), Ok(vip_cost(0x00E0, 0, false)));
+        assert_eq!(chip8.registers()[0], 0);
+    }
+}
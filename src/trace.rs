@@ -0,0 +1,318 @@
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use disasm;
+
+// How trace lines are written. All of them put one instruction per line so
+// traces from two runs can be compared with diff.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Format {
+    // Fixed width columns for reading
+    #[default]
+    Text,
+    // Comma separated with a header line, for spreadsheets and scripts
+    Csv,
+    // One JSON object per line
+    Json,
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Format::Text => write!(f, "text"),
+            Format::Csv => write!(f, "csv"),
+            Format::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown trace format: {}", s)),
+        }
+    }
+}
+
+// The machine state just before an instruction runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Step {
+    // Instructions run before this one
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub registers: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+}
+
+// Writes a line for each instruction the machine runs. Steps outside the
+// address range or cycle window are skipped. Write errors stop the trace
+// and are reported by finish.
+pub struct Tracer {
+    out: Box<dyn Write>,
+    format: Format,
+    addresses: Option<RangeInclusive<u16>>,
+    cycles: Option<RangeInclusive<u64>>,
+    started: bool,
+    error: Option<io::Error>,
+}
+
+impl Tracer {
+    pub fn new(out: Box<dyn Write>, format: Format) -> Tracer {
+        Tracer {
+            out,
+            format,
+            addresses: None,
+            cycles: None,
+            started: false,
+            error: None,
+        }
+    }
+
+    // Only trace instructions at these addresses
+    pub fn set_addresses(&mut self, addresses: Option<RangeInclusive<u16>>) {
+        self.addresses = addresses;
+    }
+
+    // Only trace instructions in this window of cycles
+    pub fn set_cycles(&mut self, cycles: Option<RangeInclusive<u64>>) {
+        self.cycles = cycles;
+    }
+
+    pub fn record(&mut self, step: &Step) {
+        if self.error.is_some() {
+            return;
+        }
+        if self
+            .addresses
+            .as_ref()
+            .is_some_and(|r| !r.contains(&step.pc))
+            || self
+                .cycles
+                .as_ref()
+                .is_some_and(|r| !r.contains(&step.cycle))
+        {
+            return;
+        }
+        if let Err(e) = self.write(step) {
+            self.error = Some(e);
+        }
+    }
+
+    fn write(&mut self, step: &Step) -> io::Result<()> {
+        let mnemonic = disasm::decode(step.opcode).to_string();
+        let registers: Vec<String> = step
+            .registers
+            .iter()
+            .map(|v| format!("{:02X}", v))
+            .collect();
+        match self.format {
+            Format::Text => writeln!(
+                self.out,
+                "{:>10} {:03X}: {:04X}  {:<16} V:{} I:{:03X} SP:{:X} DT:{:02X} ST:{:02X}",
+                step.cycle,
+                step.pc,
+                step.opcode,
+                mnemonic,
+                registers.join(" "),
+                step.i,
+                step.sp,
+                step.delay_timer,
+                step.sound_timer
+            ),
+            Format::Csv => {
+                if !self.started {
+                    let names: Vec<String> = (0..16).map(|x| format!("v{:x}", x)).collect();
+                    writeln!(
+                        self.out,
+                        "cycle,pc,opcode,mnemonic,{},i,sp,dt,st",
+                        names.join(",")
+                    )?;
+                    self.started = true;
+                }
+                writeln!(
+                    self.out,
+                    "{},{:03X},{:04X},\"{}\",{},{:03X},{:X},{:02X},{:02X}",
+                    step.cycle,
+                    step.pc,
+                    step.opcode,
+                    mnemonic,
+                    registers.join(","),
+                    step.i,
+                    step.sp,
+                    step.delay_timer,
+                    step.sound_timer
+                )
+            }
+            Format::Json => {
+                let registers: Vec<String> = step.registers.iter().map(|v| v.to_string()).collect();
+                writeln!(
+                    self.out,
+                    "{{\"cycle\":{},\"pc\":{},\"opcode\":{},\"mnemonic\":\"{}\",\"v\":[{}],\"i\":{},\"sp\":{},\"dt\":{},\"st\":{}}}",
+                    step.cycle,
+                    step.pc,
+                    step.opcode,
+                    mnemonic,
+                    registers.join(","),
+                    step.i,
+                    step.sp,
+                    step.delay_timer,
+                    step.sound_timer
+                )
+            }
+        }
+    }
+
+    // Flushes the output, returning the first error hit while tracing
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+// Parses an inclusive range of hex addresses such as "200-2FF". The end can
+// be left off to trace to the end of memory.
+pub fn parse_addresses(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |part: &str| {
+        let part = part.trim_start_matches("0x");
+        u16::from_str_radix(part, 16)
+            .ok()
+            .filter(|address| *address <= 0xFFF)
+            .ok_or_else(|| format!("Invalid address: {}", part))
+    };
+    let (start, end) = split_range(s)?;
+    let start = parse(start)?;
+    let end = if end.is_empty() { 0xFFF } else { parse(end)? };
+    if end < start {
+        return Err(format!("Empty address range: {}", s));
+    }
+    Ok(start..=end)
+}
+
+// Parses an inclusive window of cycles such as "1000-2000". The end can be
+// left off to trace until the machine stops.
+pub fn parse_cycles(s: &str) -> Result<RangeInclusive<u64>, String> {
+    let parse = |part: &str| {
+        part.parse::<u64>()
+            .map_err(|_| format!("Invalid cycle: {}", part))
+    };
+    let (start, end) = split_range(s)?;
+    let start = parse(start)?;
+    let end = if end.is_empty() {
+        u64::MAX
+    } else {
+        parse(end)?
+    };
+    if end < start {
+        return Err(format!("Empty cycle window: {}", s));
+    }
+    Ok(start..=end)
+}
+
+fn split_range(s: &str) -> Result<(&str, &str), String> {
+    let mut parts = s.splitn(2, '-');
+    let start = parts.next().unwrap().trim();
+    match parts.next() {
+        Some(end) => Ok((start, end.trim())),
+        None => Err(format!("Expected START-END: {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A writer the test can read back after handing it to the tracer
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn step(cycle: u64, pc: u16) -> Step {
+        let mut registers = [0; 16];
+        registers[0xA] = 0x2B;
+        Step {
+            cycle,
+            pc,
+            opcode: 0x6A2B,
+            registers,
+            i: 0x2F0,
+            sp: 1,
+            delay_timer: 0x3C,
+            sound_timer: 0,
+        }
+    }
+
+    fn trace(format: Format, steps: &[Step]) -> String {
+        let out = Shared::default();
+        let mut tracer = Tracer::new(Box::new(out.clone()), format);
+        tracer.set_addresses(Some(0x200..=0x2FF));
+        tracer.set_cycles(Some(1..=u64::MAX));
+        for step in steps {
+            tracer.record(step);
+        }
+        tracer.finish().unwrap();
+        let text = out.0.borrow().clone();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn test_formats() {
+        let steps = [step(1, 0x204)];
+        assert_eq!(
+            trace(Format::Text, &steps),
+            "         1 204: 6A2B  LD VA, 0x2B      \
+             V:00 00 00 00 00 00 00 00 00 00 2B 00 00 00 00 00 I:2F0 SP:1 DT:3C ST:00\n"
+        );
+        let csv = trace(Format::Csv, &steps);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("cycle,pc,opcode,mnemonic,v0,v1,"));
+        assert!(lines[1].starts_with("1,204,6A2B,\"LD VA, 0x2B\",00,"));
+        assert!(trace(Format::Json, &steps)
+            .starts_with("{\"cycle\":1,\"pc\":516,\"opcode\":27179,\"mnemonic\":\"LD VA, 0x2B\","));
+    }
+
+    #[test]
+    fn test_filters() {
+        let steps = [step(0, 0x200), step(1, 0x300), step(2, 0x202)];
+        let text = trace(Format::Text, &steps);
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.contains(" 202: "));
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        assert_eq!(parse_addresses("200-2ff"), Ok(0x200..=0x2FF));
+        assert_eq!(parse_addresses("0x300-"), Ok(0x300..=0xFFF));
+        assert!(parse_addresses("300-200").is_err());
+        assert!(parse_addresses("200").is_err());
+        assert!(parse_addresses("200-1000").is_err());
+        assert_eq!(parse_cycles("10-20"), Ok(10..=20));
+        assert_eq!(parse_cycles("500-"), Ok(500..=u64::MAX));
+        assert!(parse_cycles("a-b").is_err());
+    }
+}
@@ -0,0 +1,227 @@
+// Conformance tests: runs each test ROM in tests/conformance headless and
+// compares the screen against a golden image.
+//
+// Every ROM has an .ini file next to it, in the config format, with a
+// section per case giving the quirks, out-of-range behaviour, instructions
+// per frame and number of frames to run, e.g.
+//
+//     [vip]
+//     quirks = vip
+//     cycles = 100
+//     frames = 60
+//
+// The golden screen for case "vip" of alu.ch8 is alu.vip.pbm, a plain PBM
+// with lit pixels as 1s. To regenerate the goldens after an intended
+// change, run
+//
+//     RUST8_BLESS=1 cargo test --test conformance
+
+extern crate rust8;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rust8::config;
+use rust8::display::{HEIGHT, WIDTH};
+use rust8::fault::OutOfRange;
+use rust8::quirks::Quirks;
+use rust8::screen::Screen;
+use rust8::Chip8;
+
+struct Case {
+    name: String,
+    quirks: Quirks,
+    out_of_range: OutOfRange,
+    cycles: u32,
+    frames: u32,
+}
+
+fn dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance")
+}
+
+fn read_cases(path: &Path) -> Result<Vec<Case>, String> {
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut cases: Vec<Case> = Vec::new();
+    for setting in config::parse(&text)? {
+        let section = setting
+            .section
+            .clone()
+            .ok_or(format!("line {}: setting outside a case", setting.line))?;
+        if cases.last().map(|c| &c.name) != Some(&section) {
+            cases.push(Case {
+                name: section,
+                quirks: Quirks::default(),
+                out_of_range: OutOfRange::default(),
+                cycles: 8,
+                frames: 60,
+            });
+        }
+        let case = cases.last_mut().unwrap();
+        let error = |e: String| format!("line {}: {}", setting.line, e);
+        match setting.key.as_str() {
+            "quirks" => case.quirks = setting.value.parse().map_err(error)?,
+            "out-of-range" => case.out_of_range = setting.value.parse().map_err(error)?,
+            "cycles" => {
+                case.cycles = setting
+                    .value
+                    .parse()
+                    .map_err(|_| error(setting.value.clone()))?
+            }
+            "frames" => {
+                case.frames = setting
+                    .value
+                    .parse()
+                    .map_err(|_| error(setting.value.clone()))?
+            }
+            key => return Err(error(format!("Unknown setting: {}", key))),
+        }
+    }
+    Ok(cases)
+}
+
+fn run(rom: &[u8], case: &Case) -> Result<Screen, String> {
+    let mut chip8 = Chip8::new();
+    chip8.load_rom(rom)?;
+    chip8.quirks = case.quirks;
+    chip8.out_of_range = case.out_of_range;
+    chip8.seed(0);
+    for _ in 0..case.frames {
+        chip8.run_frame(case.cycles).map_err(|e| e.to_string())?;
+    }
+    Ok(chip8.screen)
+}
+
+fn to_pbm(screen: &Screen) -> String {
+    let mut pbm = format!("P1\n{} {}\n", WIDTH, HEIGHT);
+    for y in 0..HEIGHT {
+        let pixels: Vec<&str> = (0..WIDTH)
+            .map(|x| if screen.pixel(x, y) { "1" } else { "0" })
+            .collect();
+        pbm.push_str(&pixels.join(" "));
+        pbm.push('\n');
+    }
+    pbm
+}
+
+fn from_pbm(text: &str) -> Result<Screen, String> {
+    // Comments run from '#' to the end of the line
+    let mut tokens = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap())
+        .flat_map(|line| line.split_whitespace());
+    if tokens.next() != Some("P1") {
+        return Err("not a plain PBM".to_string());
+    }
+    let size = (tokens.next(), tokens.next());
+    if size != (Some(&WIDTH.to_string()[..]), Some(&HEIGHT.to_string()[..])) {
+        return Err(format!("expected a {}x{} image", WIDTH, HEIGHT));
+    }
+    // Pixels may be written with or without spaces between them
+    let mut pixels = tokens.flat_map(|token| token.chars());
+    let mut screen = Screen::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            let lit = match pixels.next() {
+                Some('0') => false,
+                Some('1') => true,
+                Some(c) => return Err(format!("invalid pixel: {}", c)),
+                None => return Err("image is too short".to_string()),
+            };
+            screen.set(x, y, lit);
+        }
+    }
+    Ok(screen)
+}
+
+// Draws both screens in one picture: '#' lit in both, '+' only lit in the
+// actual screen, '-' only lit in the golden one
+fn diff(expected: &Screen, actual: &Screen) -> String {
+    let mut text = String::new();
+    for y in 0..HEIGHT {
+        let row: String = (0..WIDTH)
+            .map(|x| match (expected.pixel(x, y), actual.pixel(x, y)) {
+                (true, true) => '#',
+                (false, true) => '+',
+                (true, false) => '-',
+                (false, false) => '.',
+            })
+            .collect();
+        text.push_str(&row);
+        text.push('\n');
+    }
+    text
+}
+
+// Runs every case, returning a description of each failure
+fn check(rom_path: &Path, bless: bool) -> Vec<String> {
+    let name = rom_path.file_stem().unwrap().to_string_lossy().into_owned();
+    let rom = fs::read(rom_path).unwrap();
+    let cases = match read_cases(&rom_path.with_extension("ini")) {
+        Ok(cases) if !cases.is_empty() => cases,
+        Ok(_) => return vec![format!("{}: no cases", name)],
+        Err(e) => return vec![format!("{}.ini: {}", name, e)],
+    };
+
+    let mut failures = Vec::new();
+    for case in cases {
+        let label = format!("{}.{}", name, case.name);
+        let golden = dir().join(format!("{}.pbm", label));
+        let screen = match run(&rom, &case) {
+            Ok(screen) => screen,
+            Err(e) => {
+                failures.push(format!("{}: {}", label, e));
+                continue;
+            }
+        };
+        if bless {
+            fs::write(&golden, to_pbm(&screen)).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden)
+            .map_err(|e| format!("{}: {}", golden.display(), e))
+            .and_then(|text| from_pbm(&text));
+        match expected {
+            Ok(ref expected) if *expected == screen => {}
+            Ok(ref expected) => failures.push(format!(
+                "{}: screen differs from the golden image ('+' extra, '-' missing)\n{}",
+                label,
+                diff(expected, &screen)
+            )),
+            Err(e) => failures.push(format!("{}: {}", label, e)),
+        }
+    }
+    failures
+}
+
+#[test]
+fn conformance() {
+    let bless = env::var_os("RUST8_BLESS").is_some();
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "ch8"))
+        .collect();
+    roms.sort();
+    assert!(!roms.is_empty(), "No test ROMs found");
+
+    let failures: Vec<String> = roms.iter().flat_map(|rom| check(rom, bless)).collect();
+    if !failures.is_empty() {
+        panic!(
+            "{} conformance failures:\n\n{}\nIf the changes are intended, run with RUST8_BLESS=1",
+            failures.len(),
+            failures.join("\n")
+        );
+    }
+}
+
+#[test]
+fn test_pbm_round_trips() {
+    let mut screen = Screen::new();
+    screen.set(5, 3, true);
+    screen.set(63, 31, true);
+    assert_eq!(from_pbm(&to_pbm(&screen)), Ok(screen));
+    assert!(from_pbm("P1\n# comment\n2 2\n01\n10\n").is_err());
+    assert!(from_pbm("P4\n64 32\n").is_err());
+}
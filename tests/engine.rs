@@ -0,0 +1,252 @@
+// Runs the interpreter and the cached engine side by side, an instruction at
+// a time, and checks they never disagree. The ROMs are the conformance ROMs
+// under every quirks preset, a ROM that rewrites its own code, one that
+// rewrites an instruction wrapping round the end of memory, and random
+// programs that store into their own code with FX33 and FX55.
+
+extern crate rust8;
+
+use std::fs;
+use std::path::Path;
+
+use rust8::bus::{Bus, Ram};
+use rust8::disasm::{self, Instruction};
+use rust8::engine::Engine;
+use rust8::fault::{Fault, OutOfRange};
+use rust8::quirks::Quirks;
+use rust8::Chip8;
+
+// Each iteration stores the counter into the LD V1 after it, which then
+// has to load the new value. A stale decode would keep loading the old one
+// and fall through to the 0000 at 0x20C.
+const SELF_MODIFYING: [u8; 16] = [
+    0x60, 0x00, // 200: LD V0, 0
+    0x70, 0x01, // 202: ADD V0, 1
+    0xA2, 0x09, // 204: LD I, 0x209
+    0xF0, 0x55, // 206: LD [I], V0
+    0x61, 0x00, // 208: LD V1, 0x00
+    0x51, 0x00, // 20A: SE V1, V0
+    0x00, 0x00, // 20C: unknown opcode
+    0x12, 0x02, // 20E: JP 0x202
+];
+
+// Runs the instruction at 0xFFF, whose second byte wraps round to 0, then
+// changes byte 0 and runs it again. The second time it has to load 0x22
+// into V0 rather than the 0x11 it loaded the first time.
+const WRAPPED_INSTRUCTION: [u8; 34] = [
+    0xAF, 0xFF, // 200: LD I, 0xFFF
+    0x60, 0x60, // 202: LD V0, 0x60
+    0x61, 0x11, // 204: LD V1, 0x11
+    0x62, 0x12, // 206: LD V2, 0x12
+    0x63, 0x10, // 208: LD V3, 0x10
+    0xF3, 0x55, // 20A: LD [I], V3, making FFF: LD V0, 0x11 and 001: JP 0x210
+    0x1F, 0xFF, // 20C: JP 0xFFF
+    0x00, 0x00, // 20E: unknown opcode
+    0x74, 0x01, // 210: ADD V4, 1
+    0x34, 0x02, // 212: SE V4, 2
+    0x12, 0x18, // 214: JP 0x218
+    0x12, 0x16, // 216: JP 0x216
+    0xA0, 0x00, // 218: LD I, 0
+    0x60, 0x22, // 21A: LD V0, 0x22
+    0xF0, 0x55, // 21C: LD [I], V0
+    0x1F, 0xFF, // 21E: JP 0xFFF
+    0x00, 0x00, // 220: unknown opcode
+];
+
+fn machine(rom: &[u8], quirks: Quirks, out_of_range: OutOfRange, engine: Engine) -> Chip8 {
+    let mut chip8 = Chip8::with_seed(Ram::new(), 1);
+    chip8.load_rom(rom).unwrap();
+    chip8.quirks = quirks;
+    chip8.out_of_range = out_of_range;
+    chip8.set_engine(engine);
+    chip8
+}
+
+fn assert_same(interpreter: &Chip8, cached: &Chip8, context: &str) {
+    assert_eq!(interpreter.pc(), cached.pc(), "PC {}", context);
+    assert_eq!(interpreter.registers(), cached.registers(), "V {}", context);
+    assert_eq!(interpreter.index(), cached.index(), "I {}", context);
+    assert_eq!(interpreter.sp(), cached.sp(), "SP {}", context);
+    assert_eq!(interpreter.stack(), cached.stack(), "stack {}", context);
+    assert_eq!(
+        interpreter.delay_timer(),
+        cached.delay_timer(),
+        "DT {}",
+        context
+    );
+    assert_eq!(
+        interpreter.sound_timer, cached.sound_timer,
+        "ST {}",
+        context
+    );
+    assert!(interpreter.screen == cached.screen, "screen {}", context);
+    assert!(
+        interpreter.bus().memory()[..] == cached.bus().memory()[..],
+        "memory {}",
+        context
+    );
+}
+
+// Runs both engines for the given number of frames, pressing keys from the
+// schedule, until they finish or fault. Returns how the run ended.
+fn lockstep(
+    name: &str,
+    rom: &[u8],
+    quirks: Quirks,
+    out_of_range: OutOfRange,
+    frames: u32,
+    cycles: u32,
+) -> Result<(), Fault> {
+    let mut interpreter = machine(rom, quirks, out_of_range, Engine::Interpreter);
+    let mut cached = machine(rom, quirks, out_of_range, Engine::Cached);
+    for frame in 0..frames {
+        // Some key held down for half of each second
+        let key = (frame / 30 % 16) as usize;
+        for chip8 in [&mut interpreter, &mut cached].iter_mut() {
+            chip8.keys = [false; 16];
+            chip8.keys[key] = frame % 60 < 30;
+        }
+        for cycle in 0..cycles {
+            let context = format!("in {} at frame {} cycle {}", name, frame, cycle);
+            let result = interpreter.cycle();
+            assert_eq!(result, cached.cycle(), "result {}", context);
+            assert_same(&interpreter, &cached, &context);
+            result?;
+        }
+        interpreter.update_timers();
+        cached.update_timers();
+    }
+    assert_eq!(interpreter.state_hash(), cached.state_hash());
+    Ok(())
+}
+
+#[test]
+fn test_conformance_roms() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("ch8") {
+            continue;
+        }
+        let rom = fs::read(&path).unwrap();
+        for preset in Quirks::preset_names() {
+            let name = format!("{} with {}", path.display(), preset);
+            let quirks = Quirks::preset(preset).unwrap();
+            lockstep(&name, &rom, quirks, OutOfRange::Fault, 120, 20).unwrap();
+        }
+    }
+}
+
+#[test]
+fn test_self_modifying_code() {
+    let quirks = Quirks::default();
+    lockstep(
+        "self modifying",
+        &SELF_MODIFYING,
+        quirks,
+        OutOfRange::Fault,
+        60,
+        10,
+    )
+    .unwrap();
+
+    let mut cached = machine(&SELF_MODIFYING, quirks, OutOfRange::Fault, Engine::Cached);
+    for _ in 0..100 {
+        cached.run_frame(10).unwrap();
+    }
+    // Memory changed from outside is picked up as well
+    cached.bus_mut().memory_mut()[0x20F] = 0x0C;
+    assert_eq!(
+        cached.run_frame(10),
+        Err(Fault::UnknownOpcode {
+            pc: 0x20C,
+            opcode: 0
+        })
+    );
+}
+
+#[test]
+fn test_wrapped_instruction() {
+    let quirks = Quirks::default();
+    let rom = &WRAPPED_INSTRUCTION;
+    lockstep("wrapped", rom, quirks, OutOfRange::Wrap, 5, 20).unwrap();
+
+    let mut cached = machine(rom, quirks, OutOfRange::Wrap, Engine::Cached);
+    cached.run_frame(100).unwrap();
+    assert_eq!(cached.registers()[0], 0x22);
+    assert_eq!(cached.pc(), 0x216);
+}
+
+// 5XYN and 9XYN with a low nibble the decoder doesn't know still compare
+// registers, as on the VIP, while 0NNN faults
+const UNDECODED: [u8; 14] = [
+    0x60, 0x05, // 200: LD V0, 5
+    0x61, 0x05, // 202: LD V1, 5
+    0x50, 0x11, // 204: SE V0, V1 with a low nibble
+    0x00, 0x00, // 206: skipped
+    0x90, 0x1F, // 208: SNE V0, V1 with a low nibble
+    0x12, 0x0C, // 20A: JP 0x20C
+    0x01, 0x23, // 20C: SYS 0x123
+];
+
+#[test]
+fn test_undecoded_opcodes() {
+    let quirks = Quirks::default();
+    let result = lockstep("undecoded", &UNDECODED, quirks, OutOfRange::Fault, 1, 10);
+    assert_eq!(
+        result,
+        Err(Fault::UnknownOpcode {
+            pc: 0x20C,
+            opcode: 0x123
+        })
+    );
+}
+
+// SplitMix64, for repeatable random programs
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+// 31 random instructions followed by a jump back to the start, with I kept
+// inside the program so its stores land on its own code. Jumps and calls are
+// left out so all of it keeps running, including whatever the stores turn
+// it into.
+fn random_program(random: &mut Random) -> Vec<u8> {
+    let mut rom = Vec::new();
+    while rom.len() < 62 {
+        let opcode = random.next() as u16;
+        let opcode = match opcode & 0xF000 {
+            0xA000 => 0xA200 | opcode & 0x3F,
+            _ => opcode,
+        };
+        match disasm::decode(opcode) {
+            Instruction::Sys(_) | Instruction::Unknown(_) => continue,
+            Instruction::Ret | Instruction::Jp(_) | Instruction::Call(_) => continue,
+            Instruction::JpV0(_) => continue,
+            _ => {}
+        }
+        rom.extend_from_slice(&opcode.to_be_bytes());
+    }
+    rom.extend_from_slice(&[0x12, 0x00]);
+    rom
+}
+
+#[test]
+fn test_random_programs() {
+    let mut random = Random(8);
+    for program in 0..200 {
+        let rom = random_program(&mut random);
+        let name = format!("random program {}", program);
+        let quirks = Quirks::preset(if program % 2 == 0 { "default" } else { "vip" }).unwrap();
+        // Stores can turn code into anything, including unknown opcodes
+        let _ = lockstep(&name, &rom, quirks, OutOfRange::Wrap, 20, 50);
+    }
+}
@@ -0,0 +1,37 @@
+// Runs the inputs that once crashed the fuzz targets in fuzz/, kept in
+// fuzz/regressions under the name of the target they crashed. To add one,
+// copy the crash file cargo fuzz leaves in fuzz/artifacts there.
+
+extern crate rust8;
+
+use std::fs;
+use std::path::Path;
+
+#[path = "../fuzz/harness.rs"]
+mod harness;
+
+fn regressions(target: &str, run: fn(&[u8])) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/regressions")
+        .join(target);
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        println!("{}", path.display());
+        run(&fs::read(&path).unwrap());
+    }
+}
+
+#[test]
+fn test_interpreter_regressions() {
+    regressions("interpreter", harness::interpreter);
+}
+
+#[test]
+fn test_rom_regressions() {
+    regressions("rom", harness::rom);
+}
+
+#[test]
+fn test_state_regressions() {
+    regressions("state", harness::state);
+}
@@ -0,0 +1,63 @@
+// Runs the rust8 binary as a remote control server on standard input and
+// output, and checks nothing but replies reaches standard output: rust8's
+// own messages have to go to standard error or they would be read as
+// replies.
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn test_stdio_carries_only_replies() {
+    let dir = env::temp_dir().join(format!("rust8-remote-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    // A ROM that isn't in the database, and a database with a bad entry,
+    // both of which rust8 complains about
+    let rom = dir.join("game.ch8");
+    fs::write(&rom, [0x60, 0x12, 0x12, 0x02]).unwrap();
+    let romdb = dir.join("romdb.ini");
+    fs::write(&romdb, "title = outside a section\n").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rust8"))
+        .arg("--headless")
+        .args(["--remote", "stdio"])
+        .arg("--romdb")
+        .arg(&romdb)
+        .arg(&rom)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let commands = format!(
+        "registers\nload {}\nload {}\nframe\npeek 200 2\nquit\n",
+        dir.join("missing.ch8").display(),
+        rom.display()
+    );
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(commands.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let replies: Vec<&str> = stdout.lines().collect();
+    assert_eq!(replies.len(), 6, "{}", stdout);
+    for reply in replies.iter() {
+        assert!(
+            reply.starts_with("ok") || reply.starts_with("error"),
+            "not a reply: {}",
+            reply
+        );
+    }
+    assert!(replies[1].starts_with("error"));
+    assert_eq!(replies[4], "ok 6012");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("not in the ROM database"), "{}", stderr);
+    assert!(stderr.contains("romdb.ini"), "{}", stderr);
+}